@@ -1,117 +1,1077 @@
 use axum::{
+    body::Body,
     extract::{Path, Query, State},
     http::StatusCode,
-    response::Json,
-    routing::{get, post},
+    response::{IntoResponse, Json, Response},
+    routing::{delete, get, post},
     Router,
 };
-use puresearch_core::{ReviewDocument, Index};
+use puresearch_core::{DocumentPatch, ReviewDocument, Index, aggregation::{Aggregation, AggregationResult}, analysis::AnalyzerConfig, mapping::{MappingError, Mappings}, query::{Fuzziness, Operator, ParseError, QueryParser}, sort::{Cursor, SortField, RELEVANCE}};
+use http_body_util::BodyExt;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use uuid::Uuid;
-use std::sync::{Arc, Mutex};
-use puresearch_storage::MmapStorage;
-use puresearch_core::storage::{StorageEngine, IndexStorage};
+use std::sync::{Arc, RwLock, Weak};
+use std::time::Duration;
+use puresearch_storage::{Commit, Durability, HighlightOptions, MergeStats, MmapStorage, SnapshotError, SnapshotInfo, Suggestion};
+use puresearch_core::storage::{StorageEngine, IndexStorage, WriteOp};
+
+pub type SharedStorage = Arc<RwLock<MmapStorage>>;
+
+/// Data directory the server stores everything in.
+pub const DATA_DIR: &str = "./data";
+
+/// Number of bulk actions written to storage, and to the WAL, at a time.
+const BULK_BATCH_SIZE: usize = 1000;
+/// Longest line of a bulk body, in bytes. A longer one fails as an item
+/// without being buffered.
+const MAX_BULK_LINE_LEN: usize = 1024 * 1024;
+/// Most bytes read from a bulk body. Reading stops there, and the actions
+/// not yet read fail as a single item.
+const MAX_BULK_BODY_LEN: usize = 1024 * 1024 * 1024;
+
+/// Results returned when a search or suggestion request gives no `limit`.
+const DEFAULT_LIMIT: usize = 10;
+/// Largest `limit` a search or suggestion request may ask for.
+pub const MAX_LIMIT: usize = 10_000;
+
+/// How often the background merger checks whether document segments need
+/// merging.
+const MERGE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How often the background checkpointer checks whether the WAL has grown
+/// enough to take a checkpoint.
+const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Query parameters of the endpoints that write.
+#[derive(Deserialize)]
+pub struct WriteParams {
+    /// `sync`, `group` or `async`, overriding the storage's durability for
+    /// this request.
+    pub durability: Option<Durability>,
+}
 
 #[derive(Deserialize)]
 pub struct SearchQuery {
+    #[serde(default)]
     pub q: String,
+    /// Metadata conditions hits must meet, such as
+    /// `rating:>=4 AND product_id:B00X`. They do not affect scores.
+    pub filter: Option<String>,
+    /// Comma-separated sort keys, such as `timestamp:desc,rating,_score`.
+    /// Defaults to relevance.
+    pub sort: Option<String>,
     pub limit: Option<usize>,
+    /// `cursor` from the previous page, to continue after its last hit.
+    /// The query, filter and sort must be unchanged.
+    pub cursor: Option<String>,
+    /// Search only the members of this index.
+    pub index: Option<Uuid>,
+    /// How clauses without an explicit operator are combined. Defaults to AND.
+    pub default_operator: Option<Operator>,
+    /// Typo tolerance applied to every query word: `auto` or an edit distance.
+    pub fuzziness: Option<String>,
+    /// Return snippets around the matched terms with each hit.
+    #[serde(default)]
+    pub highlight: bool,
+    pub pre_tag: Option<String>,
+    pub post_tag: Option<String>,
+    pub fragment_size: Option<usize>,
+    pub number_of_fragments: Option<usize>,
+    /// Set to `false` to return the document text in snippets unescaped.
+    /// By default it is HTML-escaped.
+    pub escape_html: Option<bool>,
+}
+
+impl SearchQuery {
+    fn highlight_options(&self) -> HighlightOptions {
+        let defaults = HighlightOptions::default();
+        HighlightOptions {
+            pre_tag: self.pre_tag.clone().unwrap_or(defaults.pre_tag),
+            post_tag: self.post_tag.clone().unwrap_or(defaults.post_tag),
+            fragment_size: self.fragment_size.unwrap_or(defaults.fragment_size),
+            number_of_fragments: self.number_of_fragments.unwrap_or(defaults.number_of_fragments),
+            escape_html: self.escape_html.unwrap_or(defaults.escape_html),
+        }
+    }
+}
+
+/// Body of `POST /search`: the `/search` parameters plus aggregations.
+#[derive(Deserialize)]
+pub struct SearchRequest {
+    #[serde(flatten)]
+    pub query: SearchQuery,
+    /// Aggregations over every hit, by the name to return them under.
+    #[serde(default)]
+    pub aggs: BTreeMap<String, Aggregation>,
+}
+
+#[derive(Serialize)]
+pub struct SearchHit {
+    #[serde(flatten)]
+    pub document: ReviewDocument,
+    pub score: f32,
+    /// Snippets of the content with matched terms tagged, when requested.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub highlights: Option<Vec<String>>,
 }
 
 #[derive(Serialize)]
 pub struct SearchResponse {
-    pub documents: Vec<ReviewDocument>,
+    pub documents: Vec<SearchHit>,
+    /// Number of matching documents, which may exceed `documents.len()`.
     pub total: usize,
+    /// Pass as `cursor` to fetch the next page. Absent on the last page.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cursor: Option<String>,
+    /// Results of the requested aggregations, by name.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub aggregations: BTreeMap<String, AggregationResult>,
+}
+
+#[derive(Deserialize)]
+pub struct SuggestQuery {
+    pub prefix: String,
+    pub limit: Option<usize>,
+    /// Restrict suggestions to the vocabulary of one index.
+    pub index: Option<Uuid>,
+}
+
+#[derive(Serialize)]
+pub struct SuggestResponse {
+    pub suggestions: Vec<Suggestion>,
 }
 
 #[derive(Deserialize)]
 pub struct DocumentRequest {
     pub content: String,
     pub metadata: Option<HashMap<String, String>>,
+    /// Index to add the document to. The metadata must fit its mappings.
+    pub index: Option<Uuid>,
+}
+
+/// Body of `PUT /documents/{id}`: the document's new content and metadata.
+#[derive(Deserialize)]
+pub struct ReplaceDocumentRequest {
+    pub content: String,
+    #[serde(default)]
+    pub metadata: HashMap<String, String>,
+}
+
+/// One line of a `POST /documents/_bulk` body.
+#[derive(Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BulkAction {
+    /// Ingests a new document, as `POST /documents` does.
+    Index(DocumentRequest),
+    /// Patches a document, as `PATCH /documents/{id}` does.
+    Update {
+        id: Uuid,
+        #[serde(flatten)]
+        patch: DocumentPatch,
+    },
+    Delete { id: Uuid },
+}
+
+impl BulkAction {
+    fn name(&self) -> &'static str {
+        match self {
+            BulkAction::Index(_) => "index",
+            BulkAction::Update { .. } => "update",
+            BulkAction::Delete { .. } => "delete",
+        }
+    }
+}
+
+/// Outcome of one bulk action, in the order the actions were sent.
+#[derive(Debug, Serialize)]
+pub struct BulkItem {
+    /// Absent if the line could not be parsed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub action: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<Uuid>,
+    pub status: u16,
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    pub error: Option<ErrorResponse>,
+}
+
+impl BulkItem {
+    fn fail(&mut self, err: ApiError) {
+        self.status = err.status.as_u16();
+        self.error = Some(err.body);
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct BulkResponse {
+    /// Whether any action failed.
+    pub errors: bool,
+    pub items: Vec<BulkItem>,
+}
+
+/// Body of `POST /admin/snapshots`.
+#[derive(Deserialize)]
+pub struct SnapshotRequest {
+    /// Defaults to `snapshot-` followed by the current Unix time.
+    pub name: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct SnapshotsResponse {
+    /// Oldest first.
+    pub snapshots: Vec<SnapshotInfo>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ErrorResponse {
+    pub error: String,
+    /// Character offset into the query string, for query syntax errors.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub position: Option<usize>,
+    /// Metadata key whose value was rejected, for mapping errors.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub field: Option<String>,
+}
+
+/// An error status with a JSON body describing it.
+#[derive(Debug)]
+pub struct ApiError {
+    pub status: StatusCode,
+    pub body: ErrorResponse,
+}
+
+impl From<StatusCode> for ApiError {
+    fn from(status: StatusCode) -> Self {
+        Self {
+            status,
+            body: ErrorResponse {
+                error: status.canonical_reason().unwrap_or("error").to_string(),
+                position: None,
+                field: None,
+            },
+        }
+    }
+}
+
+impl ApiError {
+    pub fn bad_request(message: impl Into<String>) -> Self {
+        Self {
+            status: StatusCode::BAD_REQUEST,
+            body: ErrorResponse {
+                error: message.into(),
+                position: None,
+                field: None,
+            },
+        }
+    }
+
+    pub fn payload_too_large(message: impl Into<String>) -> Self {
+        Self {
+            status: StatusCode::PAYLOAD_TOO_LARGE,
+            body: ErrorResponse {
+                error: message.into(),
+                position: None,
+                field: None,
+            },
+        }
+    }
+}
+
+impl From<ParseError> for ApiError {
+    fn from(err: ParseError) -> Self {
+        Self {
+            status: StatusCode::BAD_REQUEST,
+            body: ErrorResponse {
+                error: err.message,
+                position: Some(err.position),
+                field: None,
+            },
+        }
+    }
+}
+
+impl From<MappingError> for ApiError {
+    fn from(err: MappingError) -> Self {
+        Self {
+            status: StatusCode::UNPROCESSABLE_ENTITY,
+            body: ErrorResponse {
+                error: err.to_string(),
+                position: None,
+                field: Some(err.field),
+            },
+        }
+    }
+}
+
+/// A snapshot name that is invalid or already taken is the client's to fix.
+impl From<SnapshotError> for ApiError {
+    fn from(err: SnapshotError) -> Self {
+        let status = match err {
+            SnapshotError::InvalidName(_) => StatusCode::BAD_REQUEST,
+            SnapshotError::AlreadyExists(_) => StatusCode::CONFLICT,
+        };
+        Self {
+            status,
+            body: ErrorResponse {
+                error: err.to_string(),
+                position: None,
+                field: None,
+            },
+        }
+    }
+}
+
+/// Storage failures are internal errors, except that a document rejected by
+/// an index's mappings is the client's to fix.
+impl From<anyhow::Error> for ApiError {
+    fn from(err: anyhow::Error) -> Self {
+        let err = match err.downcast::<MappingError>() {
+            Ok(err) => return err.into(),
+            Err(err) => err,
+        };
+        match err.downcast::<SnapshotError>() {
+            Ok(err) => err.into(),
+            Err(err) => internal_error(err).into(),
+        }
+    }
+}
+
+/// Logs `err` with its causes, which are not for the client, and answers
+/// with a 500.
+fn internal_error(err: impl std::fmt::Display) -> StatusCode {
+    tracing::error!("{err:#}");
+    StatusCode::INTERNAL_SERVER_ERROR
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.status, Json(self.body)).into_response()
+    }
+}
+
+/// Body of `POST /indices`: either a bare name or a name with settings.
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum IndexRequest {
+    Name(String),
+    Settings {
+        name: String,
+        #[serde(default)]
+        analyzer: AnalyzerConfig,
+        #[serde(default)]
+        mappings: Mappings,
+    },
 }
 
 pub fn create_app() -> Router {
-    let storage = Arc::new(Mutex::new(MmapStorage::new("./data").unwrap()));
+    let storage = MmapStorage::new(DATA_DIR).unwrap();
+    create_app_with_storage(storage)
+}
+
+/// Serves `storage`. Background merges and checkpoints run on the current
+/// Tokio runtime; outside of one, none are run.
+pub fn create_app_with_storage(storage: MmapStorage) -> Router {
+    let storage: SharedStorage = Arc::new(RwLock::new(storage));
+    spawn_merger(Arc::downgrade(&storage));
+    spawn_checkpointer(Arc::downgrade(&storage));
 
     Router::new()
         .route("/health", get(health_check))
         .route("/documents", post(ingest_document))
-        .route("/documents/:id", get(get_document))
-        .route("/search", get(search_documents))
+        .route("/documents/_bulk", post(bulk_documents))
+        .route(
+            "/documents/{id}",
+            get(get_document).put(replace_document).patch(patch_document).delete(delete_document),
+        )
+        .route("/search", get(search_documents).post(search_with_aggregations))
+        .route("/indices/{id}/search", get(search_index).post(search_index_with_aggregations))
+        .route("/suggest", get(suggest_terms))
         .route("/indices", post(create_index))
         .route("/indices", get(list_indices))
+        .route("/indices/{id}", get(get_index).delete(delete_index))
+        .route("/indices/{id}/documents", post(ingest_index_document))
+        .route(
+            "/indices/{id}/documents/{doc_id}",
+            post(add_index_document).delete(remove_index_document),
+        )
+        .route("/admin/compact", post(compact))
+        .route("/admin/snapshots", get(list_snapshots).post(create_snapshot))
+        .route("/admin/snapshots/{name}", delete(delete_snapshot))
         .with_state(storage)
 }
 
+/// Runs the merges the storage's merge policy calls for in the background,
+/// until the storage is dropped.
+fn spawn_merger(storage: Weak<RwLock<MmapStorage>>) {
+    let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+        return;
+    };
+    runtime.spawn(async move {
+        let mut interval = tokio::time::interval(MERGE_INTERVAL);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            let Some(storage) = storage.upgrade() else {
+                break;
+            };
+            let result = tokio::task::spawn_blocking(move || {
+                let segments = storage.read().unwrap().plan_merge();
+                segments.map(|segments| merge_segments(&storage, &segments)).transpose()
+            })
+            .await;
+            if let Ok(Err(err)) = result {
+                tracing::error!("segment merge failed: {err:#}");
+            }
+        }
+    });
+}
+
+/// Takes checkpoints in the background as the WAL grows, until the storage
+/// is dropped, so that no write has to take one. A failed checkpoint is
+/// retried on the next check; the writes it covers are already in the WAL.
+fn spawn_checkpointer(storage: Weak<RwLock<MmapStorage>>) {
+    let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+        return;
+    };
+    runtime.spawn(async move {
+        let mut interval = tokio::time::interval(CHECKPOINT_INTERVAL);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            let Some(storage) = storage.upgrade() else {
+                break;
+            };
+            let result = tokio::task::spawn_blocking(move || {
+                if !storage.read().unwrap().checkpoint_due()? {
+                    return Ok(None);
+                }
+                storage.write().unwrap().maybe_checkpoint()
+            })
+            .await;
+            if let Ok(Err(err)) = result {
+                tracing::error!("checkpoint failed: {err:#}");
+            }
+        }
+    });
+}
+
+/// Merges `segments`. The live documents are copied under a read lock, so
+/// searches and reads carry on, and only the swap takes the write lock.
+fn merge_segments(storage: &SharedStorage, segments: &[u32]) -> anyhow::Result<MergeStats> {
+    let merged = storage.read().unwrap().prepare_merge(segments)?;
+    storage.write().unwrap().finish_merge(merged)
+}
+
+/// `POST /admin/compact`: merges every document segment into one, dropping
+/// replaced and deleted documents, and reports what was reclaimed.
+async fn compact(State(state): State<SharedStorage>) -> Result<Json<MergeStats>, ApiError> {
+    let stats = tokio::task::spawn_blocking(move || {
+        let segments = state.write().unwrap().plan_compaction()?;
+        if segments.is_empty() {
+            return Ok(MergeStats::default());
+        }
+        merge_segments(&state, &segments)
+    })
+    .await
+    .map_err(internal_error)??;
+    Ok(Json(stats))
+}
+
+/// `POST /admin/snapshots`: takes a checkpoint and keeps it as a named
+/// snapshot. Writes wait while the checkpoint is taken.
+async fn create_snapshot(
+    State(state): State<SharedStorage>,
+    req: Option<Json<SnapshotRequest>>,
+) -> Result<Json<SnapshotInfo>, ApiError> {
+    let name = match req.and_then(|Json(req)| req.name) {
+        Some(name) => name,
+        None => format!(
+            "snapshot-{}",
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map_err(internal_error)?
+                .as_secs()
+        ),
+    };
+    let info = tokio::task::spawn_blocking(move || state.write().unwrap().snapshot(&name))
+        .await
+        .map_err(internal_error)??;
+    Ok(Json(info))
+}
+
+async fn list_snapshots(State(state): State<SharedStorage>) -> Result<Json<SnapshotsResponse>, ApiError> {
+    let snapshots = state.read().unwrap().snapshots()?;
+    Ok(Json(SnapshotsResponse { snapshots }))
+}
+
+async fn delete_snapshot(
+    State(state): State<SharedStorage>,
+    Path(name): Path<String>,
+) -> Result<StatusCode, ApiError> {
+    match state.read().unwrap().delete_snapshot(&name)? {
+        true => Ok(StatusCode::NO_CONTENT),
+        false => Err(StatusCode::NOT_FOUND.into()),
+    }
+}
+
+/// Runs `write` under the storage's write lock, then waits until what it
+/// logged is as durable as `durability` asks, or the storage is configured
+/// to make it. The lock is released first, so that concurrent writes can
+/// share an fsync.
+async fn durable_write<T, E: From<StatusCode>>(
+    state: &SharedStorage,
+    durability: Option<Durability>,
+    write: impl FnOnce(&mut MmapStorage) -> Result<T, E>,
+) -> Result<T, E> {
+    let (result, commit) = state.write().unwrap().deferred(write);
+    let value = result?;
+    wait_durable(commit, durability).await?;
+    Ok(value)
+}
+
+async fn wait_durable<E: From<StatusCode>>(commit: Commit, durability: Option<Durability>) -> Result<(), E> {
+    tokio::task::spawn_blocking(move || commit.wait(durability))
+        .await
+        .map_err(internal_error)?
+        .map_err(|err| internal_error(err).into())
+}
+
 async fn health_check() -> &'static str {
     "OK"
 }
 
 async fn ingest_document(
-    State(state): State<Arc<Mutex<MmapStorage>>>,
+    State(state): State<SharedStorage>,
+    Query(params): Query<WriteParams>,
     Json(req): Json<DocumentRequest>,
-) -> Result<Json<ReviewDocument>, StatusCode> {
-    let mut storage = state.lock().unwrap();
+) -> Result<Json<ReviewDocument>, ApiError> {
+    let index_id = req.index;
+    durable_write(&state, params.durability, |storage| ingest(storage, req, index_id)).await
+}
+
+/// `POST /indices/{id}/documents`: ingests a document straight into an index.
+async fn ingest_index_document(
+    State(state): State<SharedStorage>,
+    Path(id): Path<Uuid>,
+    Query(params): Query<WriteParams>,
+    Json(req): Json<DocumentRequest>,
+) -> Result<Json<ReviewDocument>, ApiError> {
+    durable_write(&state, params.durability, |storage| ingest(storage, req, Some(id))).await
+}
+
+/// Stores a new document, adding it to `index_id` if given, in one atomic
+/// write. The document is checked against the index's mappings before
+/// anything is written.
+fn ingest(
+    storage: &mut MmapStorage,
+    req: DocumentRequest,
+    index_id: Option<Uuid>,
+) -> Result<Json<ReviewDocument>, ApiError> {
     let doc = ReviewDocument::new(
         req.content,
         req.metadata.unwrap_or_default(),
     );
-    storage.store_document(&doc).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    match index_id {
+        Some(index_id) => {
+            let index = storage
+                .get_index(&index_id)
+                .map_err(internal_error)?
+                .ok_or(StatusCode::NOT_FOUND)?;
+            index.validate(&doc)?;
+            storage
+                .write_batch(vec![
+                    WriteOp::Store(doc.clone()),
+                    WriteOp::AddToIndex { index_id, doc_id: doc.id },
+                ])
+                .map_err(internal_error)?;
+        }
+        None => {
+            storage.store_document(&doc).map_err(internal_error)?;
+        }
+    }
     Ok(Json(doc))
 }
 
+/// `POST /documents/_bulk`: applies newline-delimited JSON actions such as
+/// `{"index": {"content": "..."}}`, `{"update": {"id": "...", "metadata": {...}}}`
+/// and `{"delete": {"id": "..."}}`. The body is read as it arrives and
+/// written in batches, so a failed action does not stop the others.
+async fn bulk_documents(
+    State(state): State<SharedStorage>,
+    Query(params): Query<WriteParams>,
+    mut body: Body,
+) -> Result<Json<BulkResponse>, ApiError> {
+    let mut lines = BulkLines::default();
+    let mut pending = Vec::new();
+    let mut items = Vec::new();
+    let mut received = 0;
+    while let Some(frame) = body.frame().await {
+        let frame = frame.map_err(|_| ApiError::bad_request("failed to read the request body"))?;
+        let Ok(data) = frame.into_data() else {
+            continue;
+        };
+        received += data.len();
+        if received > MAX_BULK_BODY_LEN {
+            let message = format!("bulk body exceeds {MAX_BULK_BODY_LEN} bytes; the rest was not read");
+            pending.push(Err(ApiError::payload_too_large(message)));
+            break;
+        }
+        lines.feed(&data, &mut pending);
+        while pending.len() >= BULK_BATCH_SIZE {
+            let batch = pending.drain(..BULK_BATCH_SIZE).collect();
+            items.extend(write_bulk(&state, batch).0?);
+        }
+    }
+    if received <= MAX_BULK_BODY_LEN {
+        lines.finish(&mut pending);
+    }
+    // Batches are not waited on one by one: the last one's commit covers
+    // the others too.
+    let (written, commit) = write_bulk(&state, pending);
+    items.extend(written?);
+    wait_durable::<ApiError>(commit, params.durability).await?;
+
+    let errors = items.iter().any(|item| item.error.is_some());
+    Ok(Json(BulkResponse { errors, items }))
+}
+
+/// Splits a bulk body into lines as it arrives, parsing each as it is
+/// completed.
+#[derive(Default)]
+struct BulkLines {
+    /// The line read so far.
+    buffer: Vec<u8>,
+    /// Whether the line read so far is too long, and is being skipped.
+    oversized: bool,
+}
+
+impl BulkLines {
+    /// Reads `data`, adding an action for each line it completes to
+    /// `actions`.
+    fn feed(&mut self, mut data: &[u8], actions: &mut Vec<Result<BulkAction, ApiError>>) {
+        while !data.is_empty() {
+            let end = data.iter().position(|&b| b == b'\n');
+            let (part, rest) = match end {
+                Some(end) => (&data[..end], &data[end + 1..]),
+                None => (data, &[][..]),
+            };
+            data = rest;
+            if !self.oversized && self.buffer.len() + part.len() > MAX_BULK_LINE_LEN {
+                self.oversized = true;
+                self.buffer = Vec::new();
+                let message = format!("bulk action exceeds {MAX_BULK_LINE_LEN} bytes");
+                actions.push(Err(ApiError::payload_too_large(message)));
+            }
+            if !self.oversized {
+                self.buffer.extend_from_slice(part);
+            }
+            if end.is_some() {
+                if !std::mem::take(&mut self.oversized) {
+                    actions.extend(parse_bulk_line(&self.buffer));
+                }
+                self.buffer.clear();
+            }
+        }
+    }
+
+    /// Adds an action for the last line, if the body did not end with a
+    /// newline.
+    fn finish(self, actions: &mut Vec<Result<BulkAction, ApiError>>) {
+        if !self.oversized {
+            actions.extend(parse_bulk_line(&self.buffer));
+        }
+    }
+}
+
+/// Parses one NDJSON line, skipping blank ones.
+fn parse_bulk_line(line: &[u8]) -> Option<Result<BulkAction, ApiError>> {
+    if line.trim_ascii().is_empty() {
+        return None;
+    }
+    Some(
+        serde_json::from_slice(line)
+            .map_err(|err| ApiError::bad_request(format!("invalid bulk action: {err}"))),
+    )
+}
+
+/// Writes a batch of bulk actions with a single WAL flush, without waiting
+/// for it to be durable.
+fn write_bulk(
+    state: &SharedStorage,
+    actions: Vec<Result<BulkAction, ApiError>>,
+) -> (Result<Vec<BulkItem>, ApiError>, Commit) {
+    state
+        .write()
+        .unwrap()
+        .deferred(|storage| write_bulk_actions(storage, actions))
+}
+
+fn write_bulk_actions(
+    storage: &mut MmapStorage,
+    actions: Vec<Result<BulkAction, ApiError>>,
+) -> Result<Vec<BulkItem>, ApiError> {
+    let mut items = Vec::with_capacity(actions.len());
+    // Each op, with the item it belongs to and whether it changing nothing
+    // means the document was not found.
+    let mut ops = Vec::new();
+    let mut owners = Vec::new();
+    for action in actions {
+        let action = match action {
+            Ok(action) => action,
+            Err(err) => {
+                items.push(BulkItem {
+                    action: None,
+                    id: None,
+                    status: err.status.as_u16(),
+                    error: Some(err.body),
+                });
+                continue;
+            }
+        };
+        let mut item = BulkItem {
+            action: Some(action.name()),
+            id: None,
+            status: StatusCode::OK.as_u16(),
+            error: None,
+        };
+        let item_ops = match action {
+            BulkAction::Index(req) => {
+                let doc = ReviewDocument::new(req.content, req.metadata.unwrap_or_default());
+                item.id = Some(doc.id);
+                match req.index {
+                    Some(index_id) => match storage.get_index(&index_id)? {
+                        None => {
+                            item.fail(StatusCode::NOT_FOUND.into());
+                            Vec::new()
+                        }
+                        Some(index) => match index.validate(&doc) {
+                            Err(err) => {
+                                item.fail(err.into());
+                                Vec::new()
+                            }
+                            Ok(()) => vec![
+                                WriteOp::Store(doc.clone()),
+                                WriteOp::AddToIndex { index_id, doc_id: doc.id },
+                            ],
+                        },
+                    },
+                    None => vec![WriteOp::Store(doc)],
+                }
+            }
+            BulkAction::Update { id, patch } => {
+                item.id = Some(id);
+                vec![WriteOp::Patch { id, patch }]
+            }
+            BulkAction::Delete { id } => {
+                item.id = Some(id);
+                vec![WriteOp::Delete(id)]
+            }
+        };
+        for op in item_ops {
+            owners.push((items.len(), !matches!(op, WriteOp::Store(_) | WriteOp::AddToIndex { .. })));
+            ops.push(op);
+        }
+        items.push(item);
+    }
+
+    let results = storage.write_each(ops)?;
+    for ((owner, must_change), result) in owners.into_iter().zip(results) {
+        let item = &mut items[owner];
+        if item.error.is_some() {
+            continue;
+        }
+        match result {
+            Ok(false) if must_change => item.fail(StatusCode::NOT_FOUND.into()),
+            Ok(_) => {}
+            Err(err) => item.fail(err.into()),
+        }
+    }
+    Ok(items)
+}
+
 async fn get_document(
-    State(state): State<Arc<Mutex<MmapStorage>>>,
+    State(state): State<SharedStorage>,
     Path(id): Path<Uuid>,
 ) -> Result<Json<ReviewDocument>, StatusCode> {
-    let storage = state.lock().unwrap();
-    let doc = storage.get_document(&id).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let storage = state.read().unwrap();
+    let doc = storage.get_document(&id).map_err(internal_error)?;
     doc.map(Json).ok_or(StatusCode::NOT_FOUND)
 }
 
+/// Replaces a document's content and metadata, keeping its ID, timestamp
+/// and index memberships.
+async fn replace_document(
+    State(state): State<SharedStorage>,
+    Path(id): Path<Uuid>,
+    Query(params): Query<WriteParams>,
+    Json(req): Json<ReplaceDocumentRequest>,
+) -> Result<Json<ReviewDocument>, ApiError> {
+    durable_write(&state, params.durability, |storage| {
+        let op = WriteOp::Replace {
+            id,
+            content: req.content,
+            metadata: req.metadata,
+        };
+        updated_document(storage, id, op)
+    })
+    .await
+}
+
+async fn patch_document(
+    State(state): State<SharedStorage>,
+    Path(id): Path<Uuid>,
+    Query(params): Query<WriteParams>,
+    Json(patch): Json<DocumentPatch>,
+) -> Result<Json<ReviewDocument>, ApiError> {
+    durable_write(&state, params.durability, |storage| {
+        updated_document(storage, id, WriteOp::Patch { id, patch })
+    })
+    .await
+}
+
+/// Carries out `op`, an update to the document `id`, and returns the
+/// document as updated.
+fn updated_document(storage: &mut MmapStorage, id: Uuid, op: WriteOp) -> Result<Json<ReviewDocument>, ApiError> {
+    if !storage.write_batch(vec![op])?[0] {
+        return Err(StatusCode::NOT_FOUND.into());
+    }
+    let doc = storage.get_document(&id)?.ok_or(StatusCode::NOT_FOUND)?;
+    Ok(Json(doc))
+}
+
+/// Deletes a document, removing it from every index.
+async fn delete_document(
+    State(state): State<SharedStorage>,
+    Path(id): Path<Uuid>,
+    Query(params): Query<WriteParams>,
+) -> Result<StatusCode, StatusCode> {
+    durable_write(&state, params.durability, |storage| {
+        match storage.delete_document(&id).map_err(internal_error)? {
+            true => Ok(StatusCode::NO_CONTENT),
+            false => Err(StatusCode::NOT_FOUND),
+        }
+    })
+    .await
+}
+
 async fn search_documents(
-    State(state): State<Arc<Mutex<MmapStorage>>>,
+    State(state): State<SharedStorage>,
     Query(query): Query<SearchQuery>,
-) -> Result<Json<SearchResponse>, StatusCode> {
-    let storage = state.lock().unwrap();
-    let doc_ids = storage.list_documents().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    
+) -> Result<Json<SearchResponse>, ApiError> {
+    run_search(&state, &query, &BTreeMap::new())
+}
+
+async fn search_with_aggregations(
+    State(state): State<SharedStorage>,
+    Json(request): Json<SearchRequest>,
+) -> Result<Json<SearchResponse>, ApiError> {
+    run_search(&state, &request.query, &request.aggs)
+}
+
+async fn search_index(
+    State(state): State<SharedStorage>,
+    Path(id): Path<Uuid>,
+    Query(mut query): Query<SearchQuery>,
+) -> Result<Json<SearchResponse>, ApiError> {
+    query.index = Some(id);
+    run_search(&state, &query, &BTreeMap::new())
+}
+
+async fn search_index_with_aggregations(
+    State(state): State<SharedStorage>,
+    Path(id): Path<Uuid>,
+    Json(mut request): Json<SearchRequest>,
+) -> Result<Json<SearchResponse>, ApiError> {
+    request.query.index = Some(id);
+    run_search(&state, &request.query, &request.aggs)
+}
+
+fn run_search(
+    state: &SharedStorage,
+    query: &SearchQuery,
+    aggs: &BTreeMap<String, Aggregation>,
+) -> Result<Json<SearchResponse>, ApiError> {
+    for (name, aggregation) in aggs {
+        aggregation
+            .validate()
+            .map_err(|message| ApiError::bad_request(format!("aggregation '{name}': {message}")))?;
+    }
+    let mut parser = QueryParser::new(query.default_operator.unwrap_or_default());
+    if let Some(fuzziness) = &query.fuzziness {
+        parser = parser.with_fuzziness(fuzziness.parse::<Fuzziness>().map_err(ApiError::bad_request)?);
+    }
+    let mut parsed = parser.parse(&query.q)?;
+    if let Some(filter) = &query.filter {
+        let filter = parser.parse_filter(filter).map_err(|err| ApiError {
+            status: StatusCode::BAD_REQUEST,
+            body: ErrorResponse {
+                error: format!("invalid filter: {}", err.message),
+                position: Some(err.position),
+                field: None,
+            },
+        })?;
+        parsed = parsed.filtered(filter);
+    }
+    let sort = match &query.sort {
+        Some(sort) => SortField::parse_list(sort).map_err(ApiError::bad_request)?,
+        None => RELEVANCE.to_vec(),
+    };
+    let after = match &query.cursor {
+        Some(token) => Some(Cursor::decode(token, &sort).map_err(ApiError::bad_request)?),
+        None => None,
+    };
+
+    let storage = state.read().unwrap();
+    let searcher = storage.searcher(query.index.as_ref()).ok_or(StatusCode::NOT_FOUND)?;
+    let limit = result_limit(query.limit)?;
+    let page = searcher.search_page(&parsed, &sort, after.as_ref(), limit);
+    let cursor = match page.hits.last() {
+        Some(last) if page.has_more() => Some(searcher.cursor(last, &sort).encode()),
+        _ => None,
+    };
+
     let mut documents = vec![];
-    let limit = query.limit.unwrap_or(10);
+    let highlighter = query.highlight.then(|| searcher.highlighter(&parsed));
+    let highlight_options = query.highlight_options();
     
-    for id in doc_ids {
-        if let Some(doc) = storage.get_document(&id).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)? {
-            if doc.content.to_lowercase().contains(&query.q.to_lowercase()) {
-                documents.push(doc);
-                if documents.len() >= limit {
-                    break;
-                }
-            }
+    for hit in &page.hits {
+        if let Some(doc) = storage.get_document(&hit.id).map_err(internal_error)? {
+            let highlights = highlighter
+                .as_ref()
+                .map(|highlighter| highlighter.highlight(&doc.content, &highlight_options));
+            documents.push(SearchHit { document: doc, score: hit.score, highlights });
         }
     }
     
+    // Aggregations cover every match, not just the page.
+    let hits = if aggs.is_empty() { Vec::new() } else { searcher.matches(&parsed) };
+    let aggregations = aggs
+        .iter()
+        .map(|(name, aggregation)| (name.clone(), searcher.aggregate(&hits, aggregation)))
+        .collect();
+
     let response = SearchResponse {
-        total: documents.len(),
+        total: page.total,
         documents,
+        cursor,
+        aggregations,
     };
     Ok(Json(response))
 }
 
+/// The `limit` of a search or suggestion request, which must not exceed
+/// `MAX_LIMIT`.
+fn result_limit(limit: Option<usize>) -> Result<usize, ApiError> {
+    match limit {
+        Some(limit) if limit > MAX_LIMIT => Err(ApiError::bad_request(format!("limit must be at most {MAX_LIMIT}"))),
+        limit => Ok(limit.unwrap_or(DEFAULT_LIMIT)),
+    }
+}
+
+async fn suggest_terms(
+    State(state): State<SharedStorage>,
+    Query(query): Query<SuggestQuery>,
+) -> Result<Json<SuggestResponse>, ApiError> {
+    let limit = result_limit(query.limit)?;
+    let storage = state.read().unwrap();
+    let suggestions = match query.index {
+        Some(index_id) => storage
+            .suggest_in_index(&index_id, &query.prefix, limit)
+            .ok_or(StatusCode::NOT_FOUND)?,
+        None => storage.suggest(&query.prefix, limit),
+    };
+    Ok(Json(SuggestResponse { suggestions }))
+}
+
 async fn create_index(
-    State(state): State<Arc<Mutex<MmapStorage>>>,
-    Json(name): Json<String>,
+    State(state): State<SharedStorage>,
+    Query(params): Query<WriteParams>,
+    Json(req): Json<IndexRequest>,
 ) -> Result<Json<Index>, StatusCode> {
-    let mut storage = state.lock().unwrap();
-    let index = Index::new(name);
-    storage.store_index(&index).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    Ok(Json(index))
+    let index = match req {
+        IndexRequest::Name(name) => Index::new(name),
+        IndexRequest::Settings { name, analyzer, mappings } => {
+            Index::new(name).with_analyzer(analyzer).with_mappings(mappings)
+        }
+    };
+    durable_write(&state, params.durability, |storage| {
+        storage.store_index(&index).map_err(internal_error)?;
+        Ok(Json(index))
+    })
+    .await
 }
 
 async fn list_indices(
-    State(state): State<Arc<Mutex<MmapStorage>>>,
+    State(state): State<SharedStorage>,
 ) -> Result<Json<Vec<Index>>, StatusCode> {
-    let storage = state.lock().unwrap();
-    let indices = storage.list_indices().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let storage = state.read().unwrap();
+    let indices = storage.list_indices().map_err(internal_error)?;
     Ok(Json(indices))
 }
+
+async fn get_index(
+    State(state): State<SharedStorage>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<Index>, StatusCode> {
+    let storage = state.read().unwrap();
+    let index = storage.get_index(&id).map_err(internal_error)?;
+    index.map(Json).ok_or(StatusCode::NOT_FOUND)
+}
+
+/// Deletes an index. Its documents are kept.
+async fn delete_index(
+    State(state): State<SharedStorage>,
+    Path(id): Path<Uuid>,
+    Query(params): Query<WriteParams>,
+) -> Result<StatusCode, StatusCode> {
+    durable_write(&state, params.durability, |storage| {
+        match storage.delete_index(&id).map_err(internal_error)? {
+            true => Ok(StatusCode::NO_CONTENT),
+            false => Err(StatusCode::NOT_FOUND),
+        }
+    })
+    .await
+}
+
+/// Adds an existing document to an index. Adding a member again is a no-op.
+async fn add_index_document(
+    State(state): State<SharedStorage>,
+    Path((id, doc_id)): Path<(Uuid, Uuid)>,
+    Query(params): Query<WriteParams>,
+) -> Result<StatusCode, ApiError> {
+    durable_write(&state, params.durability, |storage| {
+        let index = storage
+            .get_index(&id)
+            .map_err(internal_error)?
+            .ok_or(StatusCode::NOT_FOUND)?;
+        let doc = storage
+            .get_document(&doc_id)
+            .map_err(internal_error)?
+            .ok_or(StatusCode::NOT_FOUND)?;
+        index.validate(&doc)?;
+        storage
+            .add_document(&id, &doc_id)
+            .map_err(internal_error)?;
+        Ok(StatusCode::NO_CONTENT)
+    })
+    .await
+}
+
+/// Removes a document from an index without deleting the document.
+async fn remove_index_document(
+    State(state): State<SharedStorage>,
+    Path((id, doc_id)): Path<(Uuid, Uuid)>,
+    Query(params): Query<WriteParams>,
+) -> Result<StatusCode, StatusCode> {
+    durable_write(&state, params.durability, |storage| {
+        match storage
+            .remove_document(&id, &doc_id)
+            .map_err(internal_error)?
+        {
+            true => Ok(StatusCode::NO_CONTENT),
+            false => Err(StatusCode::NOT_FOUND),
+        }
+    })
+    .await
+}
@@ -1,15 +1,79 @@
-use puresearch_api::create_app;
-use axum::Server;
+use puresearch_api::{create_app_with_storage, DATA_DIR};
+use puresearch_storage::{snapshot, MmapStorage};
 use std::net::SocketAddr;
+use std::path::Path;
+
+const USAGE: &str = "usage: puresearch-api [snapshot create [NAME] | snapshot list | snapshot delete NAME | snapshot restore NAME|PATH]";
 
 #[tokio::main]
 async fn main() {
-    let app = create_app();
-    
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if !args.is_empty() {
+        if let Err(err) = run_command(&args) {
+            eprintln!("{err:#}");
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let storage = MmapStorage::new(DATA_DIR).unwrap();
+    let report = storage.recovery_report();
+    if !report.is_clean() {
+        eprintln!(
+            "WAL recovery discarded {} bytes ({}){}",
+            report.discarded_bytes,
+            report.reason.as_deref().unwrap_or("no damaged records"),
+            if report.incomplete_batch { " and an uncommitted batch" } else { "" },
+        );
+    }
+    let app = create_app_with_storage(storage);
+
     let addr = SocketAddr::from(([0, 0, 0, 0], 3000));
-    Server::bind(&addr)
-        .serve(app.into_make_service())
+    let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
+    axum::serve(listener, app)
         .await
         .unwrap();
 }
 
+/// Manages snapshots of the data directory. `create` and `restore` lock it,
+/// so they fail while the server runs; `POST /admin/snapshots` takes a
+/// snapshot of a running server.
+fn run_command(args: &[String]) -> anyhow::Result<()> {
+    let data_dir = Path::new(DATA_DIR);
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    match args[..] {
+        ["snapshot", "create", ..] if args.len() <= 3 => {
+            let name = match args.get(2) {
+                Some(name) => name.to_string(),
+                None => format!(
+                    "snapshot-{}",
+                    std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)?.as_secs()
+                ),
+            };
+            let info = MmapStorage::new(data_dir)?.snapshot(&name)?;
+            println!("{}", serde_json::to_string_pretty(&info)?);
+        }
+        ["snapshot", "list"] => {
+            for info in snapshot::list(data_dir)? {
+                println!("{}\t{}\tlsn {}\t{} documents", info.name, info.created_at, info.lsn, info.documents);
+            }
+        }
+        ["snapshot", "delete", name] => {
+            if !snapshot::delete(data_dir, name)? {
+                anyhow::bail!("no snapshot named '{name}'");
+            }
+        }
+        ["snapshot", "restore", from] => {
+            // A snapshot of this data directory by name, or one copied from
+            // elsewhere by path.
+            let snapshot_dir = match snapshot::validate_name(from) {
+                Ok(()) if snapshot::path(data_dir, from).exists() => snapshot::path(data_dir, from),
+                _ => Path::new(from).to_path_buf(),
+            };
+            let info = snapshot::restore(&snapshot_dir, data_dir)?;
+            println!("restored snapshot '{}' at LSN {}", info.name, info.lsn);
+        }
+        _ => anyhow::bail!("{USAGE}"),
+    }
+    Ok(())
+}
@@ -0,0 +1,456 @@
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use axum::Router;
+use http_body_util::BodyExt;
+use puresearch_api::{create_app_with_storage, MAX_LIMIT};
+use puresearch_storage::MmapStorage;
+use serde_json::{json, Value};
+use tempfile::{tempdir, TempDir};
+use tower::ServiceExt;
+
+fn test_app() -> (TempDir, Router) {
+    let temp_dir = tempdir().unwrap();
+    let storage = MmapStorage::new(temp_dir.path()).unwrap();
+    (temp_dir, create_app_with_storage(storage))
+}
+
+async fn send(app: &Router, method: &str, uri: &str, body: Option<Value>) -> (StatusCode, Value) {
+    let request = Request::builder().method(method).uri(uri);
+    let request = match body {
+        Some(body) => request
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap(),
+        None => request.body(Body::empty()).unwrap(),
+    };
+    let response = app.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let bytes = response.into_body().collect().await.unwrap().to_bytes();
+    let value = serde_json::from_slice(&bytes).unwrap_or(Value::Null);
+    (status, value)
+}
+
+#[test]
+fn test_app_builds_outside_a_runtime() {
+    // Background merges and checkpoints are skipped rather than panicking.
+    let (_dir, _app) = test_app();
+}
+
+#[tokio::test]
+async fn test_search_returns_scored_hits() {
+    let (_dir, app) = test_app();
+    for content in ["Battery died after a week", "Battery lasts all day, great battery", "Nice screen"] {
+        let (status, _) = send(&app, "POST", "/documents", Some(json!({ "content": content }))).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    let (status, body) = send(&app, "GET", "/search?q=battery%20-died&limit=5", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["total"], 1);
+    assert_eq!(body["documents"][0]["content"], "Battery lasts all day, great battery");
+    assert!(body["documents"][0]["score"].as_f64().unwrap() > 0.0);
+}
+
+#[tokio::test]
+async fn test_search_syntax_error_is_bad_request() {
+    let (_dir, app) = test_app();
+
+    let (status, body) = send(&app, "GET", "/search?q=battery%20AND%20(life", None).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["error"], "unclosed '('");
+    assert_eq!(body["position"], 12);
+}
+
+#[tokio::test]
+async fn test_limit_above_maximum_is_bad_request() {
+    let (_dir, app) = test_app();
+    send(&app, "POST", "/documents", Some(json!({ "content": "Battery died fast" }))).await;
+
+    let (status, body) = send(&app, "GET", &format!("/search?q=battery&limit={MAX_LIMIT}"), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["total"], 1);
+    for uri in [
+        "/search?q=battery&limit=100000000000",
+        "/search?q=battery&limit=18446744073709551615",
+        "/suggest?prefix=batt&limit=10001",
+    ] {
+        let (status, body) = send(&app, "GET", uri, None).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{uri}");
+        assert_eq!(body["error"], "limit must be at most 10000");
+    }
+}
+
+#[tokio::test]
+async fn test_search_filters_on_metadata() {
+    let (_dir, app) = test_app();
+    for (content, rating) in [("Battery lasts for days", "5"), ("Battery died", "1"), ("Battery is fine", "4")] {
+        let doc = json!({ "content": content, "metadata": { "rating": rating } });
+        let (status, _) = send(&app, "POST", "/documents", Some(doc)).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    let (status, body) = send(&app, "GET", "/search?q=battery&filter=rating:%3E%3D4", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["total"], 2);
+
+    let (status, body) = send(&app, "GET", "/search?filter=rating:(1%20OR%205)", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["total"], 2);
+
+    let (status, body) = send(&app, "GET", "/search?q=battery&filter=rating:[1%20TO", None).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["error"], "invalid filter: unterminated range");
+    assert_eq!(body["position"], 7);
+}
+
+#[tokio::test]
+async fn test_ingest_validates_index_mappings() {
+    let (_dir, app) = test_app();
+    let settings = json!({ "name": "reviews", "mappings": { "rating": "integer", "verified": "boolean" } });
+    let (status, index) = send(&app, "POST", "/indices", Some(settings)).await;
+    assert_eq!(status, StatusCode::OK);
+    let index_id = index["id"].as_str().unwrap();
+
+    let doc = json!({ "content": "Great", "metadata": { "rating": "five" }, "index": index_id });
+    let (status, body) = send(&app, "POST", "/documents", Some(doc)).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body["field"], "rating");
+    assert_eq!(body["error"], "invalid value for field 'rating': expected an integer, got 'five'");
+
+    let doc = json!({ "content": "Great", "metadata": { "rating": "5", "verified": "true" }, "index": index_id });
+    let (status, doc) = send(&app, "POST", "/documents", Some(doc)).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (_, indices) = send(&app, "GET", "/indices", None).await;
+    assert_eq!(indices[0]["documents"], json!([doc["id"]]));
+    assert_eq!(indices[0]["mappings"]["rating"], "integer");
+
+    let doc = json!({ "content": "Great", "index": "00000000-0000-0000-0000-000000000000" });
+    let (status, _) = send(&app, "POST", "/documents", Some(doc)).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_search_sorts_by_metadata() {
+    let (_dir, app) = test_app();
+    for (content, rating) in [("Battery okay", "3"), ("Battery awful", "1"), ("Battery superb", "5")] {
+        let doc = json!({ "content": content, "metadata": { "rating": rating } });
+        send(&app, "POST", "/documents", Some(doc)).await;
+    }
+
+    let (status, body) = send(&app, "GET", "/search?q=battery&sort=rating:asc", None).await;
+    assert_eq!(status, StatusCode::OK);
+    let ratings: Vec<&str> = body["documents"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|hit| hit["metadata"]["rating"].as_str().unwrap())
+        .collect();
+    assert_eq!(ratings, vec!["1", "3", "5"]);
+
+    let (status, body) = send(&app, "GET", "/search?q=battery&sort=rating:sideways", None).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["error"], "sort order must be 'asc' or 'desc', got 'sideways'");
+}
+
+#[tokio::test]
+async fn test_search_pages_with_cursor() {
+    let (_dir, app) = test_app();
+    for i in 0..25 {
+        let content = if i % 2 == 0 { "battery battery review" } else { "battery review" };
+        let doc = json!({ "content": content, "metadata": { "rating": (i % 5).to_string() } });
+        send(&app, "POST", "/documents", Some(doc)).await;
+    }
+
+    for sort in ["", "&sort=rating:desc"] {
+        let mut seen = Vec::new();
+        let mut uri = format!("/search?q=battery&limit=10{sort}");
+        loop {
+            let (status, body) = send(&app, "GET", &uri, None).await;
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(body["total"], 25);
+            for hit in body["documents"].as_array().unwrap() {
+                seen.push(hit["id"].as_str().unwrap().to_string());
+            }
+            match body["cursor"].as_str() {
+                Some(cursor) => uri = format!("/search?q=battery&limit=10{sort}&cursor={cursor}"),
+                None => break,
+            }
+        }
+        assert_eq!(seen.len(), 25);
+        seen.sort();
+        seen.dedup();
+        assert_eq!(seen.len(), 25);
+    }
+
+    let (status, body) = send(&app, "GET", "/search?q=battery&cursor=garbage", None).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["error"], "invalid cursor");
+}
+
+#[tokio::test]
+async fn test_search_with_aggregations() {
+    let (_dir, app) = test_app();
+    for (content, rating) in [("Battery great", "5"), ("Battery fine", "4"), ("Battery superb", "5"), ("Screen", "1")] {
+        let doc = json!({ "content": content, "metadata": { "rating": rating } });
+        send(&app, "POST", "/documents", Some(doc)).await;
+    }
+
+    let request = json!({
+        "q": "battery",
+        "limit": 1,
+        "aggs": {
+            "ratings": { "terms": { "field": "rating" } },
+            "rating_stats": { "stats": { "field": "rating" } },
+        },
+    });
+    let (status, body) = send(&app, "POST", "/search", Some(request)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["documents"].as_array().unwrap().len(), 1);
+    assert_eq!(
+        body["aggregations"]["ratings"]["buckets"],
+        json!([{ "key": "5", "doc_count": 2 }, { "key": "4", "doc_count": 1 }])
+    );
+    assert_eq!(body["aggregations"]["rating_stats"]["max"], 5.0);
+
+    let request = json!({ "q": "battery", "aggs": { "bad": { "histogram": { "field": "rating", "interval": -1.0 } } } });
+    let (status, body) = send(&app, "POST", "/search", Some(request)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["error"], "aggregation 'bad': histogram interval must be a positive number, got -1");
+}
+
+#[tokio::test]
+async fn test_index_scoped_search() {
+    let (_dir, app) = test_app();
+    let (_, first) = send(&app, "POST", "/indices", Some(json!("client_a"))).await;
+    let (_, second) = send(&app, "POST", "/indices", Some(json!("client_b"))).await;
+    let (first, second) = (first["id"].as_str().unwrap(), second["id"].as_str().unwrap());
+
+    for (content, index) in [
+        ("Battery died fast", first),
+        ("Battery lasts forever", second),
+        ("Screen cracked, battery fine", second),
+    ] {
+        let (status, _) = send(&app, "POST", "/documents", Some(json!({ "content": content, "index": index }))).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+    send(&app, "POST", "/documents", Some(json!({ "content": "Battery in no index" }))).await;
+
+    let (status, body) = send(&app, "GET", &format!("/indices/{first}/search?q=battery"), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["total"], 1);
+    assert_eq!(body["documents"][0]["content"], "Battery died fast");
+
+    let (_, body) = send(&app, "GET", &format!("/search?q=battery&index={second}"), None).await;
+    assert_eq!(body["total"], 2);
+    let (_, body) = send(&app, "GET", "/search?q=battery", None).await;
+    assert_eq!(body["total"], 4);
+
+    // Aggregations only see the index's members.
+    let request = json!({ "q": "*", "aggs": { "days": { "date_histogram": { "interval": "day" } } } });
+    let (status, body) = send(&app, "POST", &format!("/indices/{second}/search"), Some(request)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["aggregations"]["days"]["buckets"][0]["doc_count"], 2);
+
+    let missing = "/indices/00000000-0000-0000-0000-000000000000/search?q=battery";
+    let (status, _) = send(&app, "GET", missing, None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_index_membership() {
+    let (_dir, app) = test_app();
+    let (_, index) = send(&app, "POST", "/indices", Some(json!({ "name": "rated", "mappings": { "rating": "integer" } }))).await;
+    let id = index["id"].as_str().unwrap();
+
+    let (status, member) = send(&app, "POST", &format!("/indices/{id}/documents"), Some(json!({ "content": "Battery died fast" }))).await;
+    assert_eq!(status, StatusCode::OK);
+    let member = member["id"].as_str().unwrap();
+    let (_, other) = send(&app, "POST", "/documents", Some(json!({ "content": "Battery lasts", "metadata": { "rating": "5" } }))).await;
+    let other = other["id"].as_str().unwrap();
+    let (_, invalid) = send(&app, "POST", "/documents", Some(json!({ "content": "Battery meh", "metadata": { "rating": "meh" } }))).await;
+    let invalid = invalid["id"].as_str().unwrap();
+
+    let (status, _) = send(&app, "POST", &format!("/indices/{id}/documents/{other}"), None).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let (status, body) = send(&app, "POST", &format!("/indices/{id}/documents/{invalid}"), None).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body["field"], "rating");
+
+    let (status, body) = send(&app, "GET", &format!("/indices/{id}"), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["documents"].as_array().unwrap().len(), 2);
+
+    let (status, _) = send(&app, "DELETE", &format!("/indices/{id}/documents/{member}"), None).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let (status, _) = send(&app, "DELETE", &format!("/indices/{id}/documents/{member}"), None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (_, body) = send(&app, "GET", &format!("/indices/{id}/search?q=battery"), None).await;
+    assert_eq!(body["total"], 1);
+    assert_eq!(body["documents"][0]["id"], other);
+
+    let (status, _) = send(&app, "DELETE", &format!("/indices/{id}"), None).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let (status, _) = send(&app, "GET", &format!("/indices/{id}"), None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _) = send(&app, "GET", &format!("/documents/{member}"), None).await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_update_and_delete_documents() {
+    let (_dir, app) = test_app();
+    let (_, index) = send(&app, "POST", "/indices", Some(json!({ "name": "rated", "mappings": { "rating": "integer" } }))).await;
+    let request = json!({ "content": "Battery died fast", "metadata": { "rating": "1", "product_id": "B00X" }, "index": index["id"] });
+    let (_, doc) = send(&app, "POST", "/documents", Some(request)).await;
+    let uri = format!("/documents/{}", doc["id"].as_str().unwrap());
+
+    let (status, patched) = send(&app, "PATCH", &uri, Some(json!({ "metadata": { "rating": "2", "product_id": null } }))).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(patched["content"], "Battery died fast");
+    assert_eq!(patched["metadata"], json!({ "rating": "2" }));
+    assert_eq!(patched["timestamp"], doc["timestamp"]);
+    let (status, body) = send(&app, "PATCH", &uri, Some(json!({ "metadata": { "rating": "two" } }))).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body["field"], "rating");
+
+    let (status, replaced) = send(&app, "PUT", &uri, Some(json!({ "content": "Screen cracked" }))).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(replaced["id"], doc["id"]);
+    assert_eq!(replaced["metadata"], json!({}));
+    let (_, body) = send(&app, "GET", "/search?q=battery", None).await;
+    assert_eq!(body["total"], 0);
+    let (_, body) = send(&app, "GET", "/search?q=screen", None).await;
+    assert_eq!(body["total"], 1);
+
+    let (status, _) = send(&app, "DELETE", &uri, None).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let (status, _) = send(&app, "GET", &uri, None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _) = send(&app, "DELETE", &uri, None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _) = send(&app, "PUT", &uri, Some(json!({ "content": "Gone" }))).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (_, body) = send(&app, "GET", "/search?q=screen", None).await;
+    assert_eq!(body["total"], 0);
+}
+
+#[tokio::test]
+async fn test_bulk_ingest() {
+    let (_dir, app) = test_app();
+    let (_, index) = send(&app, "POST", "/indices", Some(json!({ "name": "rated", "mappings": { "rating": "integer" } }))).await;
+    let (_, existing) = send(&app, "POST", "/documents", Some(json!({ "content": "Screen cracked" }))).await;
+
+    let mut lines = vec![
+        json!({ "index": { "content": "Battery died fast", "metadata": { "rating": "1" }, "index": index["id"] } }),
+        json!({ "index": { "content": "Battery is fine", "metadata": { "rating": "five" }, "index": index["id"] } }),
+        json!({ "update": { "id": existing["id"], "content": "Screen cracked, battery fine" } }),
+        json!({ "delete": { "id": "00000000-0000-0000-0000-000000000000" } }),
+    ]
+    .into_iter()
+    .map(|line| line.to_string())
+    .collect::<Vec<_>>();
+    lines.insert(2, "{\"upsert\": {}}".to_string());
+    lines.insert(3, String::new());
+    lines.insert(4, format!("{{\"index\": {{\"content\": \"{}\"}}}}", "a".repeat(2 * 1024 * 1024)));
+    for i in 0..1500 {
+        lines.push(json!({ "index": { "content": format!("Review number {i}") } }).to_string());
+    }
+
+    let request = Request::builder()
+        .method("POST")
+        .uri("/documents/_bulk")
+        .header("content-type", "application/x-ndjson")
+        .body(Body::from(lines.join("\n")))
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let bytes = response.into_body().collect().await.unwrap().to_bytes();
+    let body: Value = serde_json::from_slice(&bytes).unwrap();
+
+    assert_eq!(body["errors"], true);
+    let items = body["items"].as_array().unwrap();
+    assert_eq!(items.len(), 1506);
+    let statuses: Vec<u64> = items[..6].iter().map(|item| item["status"].as_u64().unwrap()).collect();
+    assert_eq!(statuses, vec![200, 422, 400, 413, 200, 404]);
+    assert_eq!(items[1]["field"], "rating");
+    assert!(items[2].get("action").is_none());
+    assert_eq!(items[3]["error"], "bulk action exceeds 1048576 bytes");
+    assert!(items[6..].iter().all(|item| item["status"] == 200));
+
+    let (_, body) = send(&app, "GET", "/search?q=battery", None).await;
+    assert_eq!(body["total"], 2);
+    let (_, body) = send(&app, "GET", &format!("/indices/{}/search?q=battery", index["id"].as_str().unwrap()), None).await;
+    assert_eq!(body["total"], 1);
+    let (_, body) = send(&app, "GET", "/search?q=review&limit=1", None).await;
+    assert_eq!(body["total"], 1500);
+}
+
+#[tokio::test]
+async fn test_compact_reclaims_replaced_and_deleted_documents() {
+    let (_dir, app) = test_app();
+    let mut ids = Vec::new();
+    for content in ["Battery died after a week", "Nice screen", "Battery lasts all day"] {
+        let (_, doc) = send(&app, "POST", "/documents", Some(json!({ "content": content }))).await;
+        ids.push(doc["id"].as_str().unwrap().to_string());
+    }
+    let uri = format!("/documents/{}", ids[0]);
+    send(&app, "PUT", &uri, Some(json!({ "content": "Battery died after a month" }))).await;
+    send(&app, "DELETE", &format!("/documents/{}", ids[1]), None).await;
+
+    let (status, stats) = send(&app, "POST", "/admin/compact", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(stats["segments_merged"], 1);
+    assert_eq!(stats["documents"], 2);
+    assert_eq!(stats["deletes_purged"], 1);
+    assert!(stats["bytes_reclaimed"].as_u64().unwrap() > 0);
+
+    let (_, doc) = send(&app, "GET", &uri, None).await;
+    assert_eq!(doc["content"], "Battery died after a month");
+    let (_, body) = send(&app, "GET", "/search?q=battery", None).await;
+    assert_eq!(body["total"], 2);
+}
+
+#[tokio::test]
+async fn test_writes_take_a_durability() {
+    let (_dir, app) = test_app();
+    for durability in ["sync", "group", "async"] {
+        let uri = format!("/documents?durability={durability}");
+        let (status, _) = send(&app, "POST", &uri, Some(json!({ "content": "Battery died" }))).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+    let (_, body) = send(&app, "GET", "/search?q=battery", None).await;
+    assert_eq!(body["total"], 3);
+
+    let uri = "/documents?durability=eventually";
+    let (status, _) = send(&app, "POST", uri, Some(json!({ "content": "Battery died" }))).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_snapshots() {
+    let (_dir, app) = test_app();
+    send(&app, "POST", "/documents", Some(json!({ "content": "Battery died after a week" }))).await;
+
+    let (status, info) = send(&app, "POST", "/admin/snapshots", Some(json!({ "name": "nightly" }))).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(info["name"], "nightly");
+    assert_eq!(info["documents"], 1);
+    let (status, _) = send(&app, "POST", "/admin/snapshots", Some(json!({ "name": "nightly" }))).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    let (status, _) = send(&app, "POST", "/admin/snapshots", Some(json!({ "name": "../nightly" }))).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, info) = send(&app, "POST", "/admin/snapshots", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(info["name"].as_str().unwrap().starts_with("snapshot-"));
+
+    let (_, body) = send(&app, "GET", "/admin/snapshots", None).await;
+    assert_eq!(body["snapshots"].as_array().unwrap().len(), 2);
+
+    let (status, _) = send(&app, "DELETE", "/admin/snapshots/nightly", None).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let (status, _) = send(&app, "DELETE", "/admin/snapshots/nightly", None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (_, body) = send(&app, "GET", "/admin/snapshots", None).await;
+    assert_eq!(body["snapshots"].as_array().unwrap().len(), 1);
+}
@@ -0,0 +1,129 @@
+//! Aggregations computed over every hit of a search.
+//!
+//! ```json
+//! {
+//!   "by_rating": { "terms": { "field": "rating" } },
+//!   "per_month": { "date_histogram": { "interval": "month" } },
+//!   "price": { "stats": { "field": "price" } }
+//! }
+//! ```
+
+use crate::sort::SortValue;
+use chrono::{DateTime, Datelike, NaiveDate, SecondsFormat, Timelike};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Aggregation {
+    /// The `size` most common values of a metadata key.
+    Terms {
+        field: String,
+        #[serde(default = "default_terms_size")]
+        size: usize,
+    },
+    /// Hit counts per `interval`-wide range of a numeric metadata key.
+    Histogram { field: String, interval: f64 },
+    /// Hit counts per calendar interval of the document timestamp, or of a
+    /// metadata key mapped as a `date`.
+    DateHistogram {
+        #[serde(default)]
+        field: Option<String>,
+        interval: CalendarInterval,
+    },
+    /// Count, minimum, maximum, average and sum of a numeric metadata key.
+    Stats { field: String },
+}
+
+fn default_terms_size() -> usize {
+    10
+}
+
+impl Aggregation {
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            Aggregation::Histogram { interval, .. } if !(interval.is_finite() && *interval > 0.0) => {
+                Err(format!("histogram interval must be a positive number, got {interval}"))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CalendarInterval {
+    Hour,
+    Day,
+    /// Weeks starting on Monday.
+    Week,
+    Month,
+    Year,
+}
+
+impl CalendarInterval {
+    /// Start of the UTC interval containing `millis`, in milliseconds since
+    /// the Unix epoch.
+    pub fn bucket_start(&self, millis: i64) -> i64 {
+        let Some(time) = DateTime::from_timestamp_millis(millis) else {
+            return millis;
+        };
+        let date = time.date_naive();
+        let start = match self {
+            CalendarInterval::Hour => date.and_hms_opt(time.hour(), 0, 0),
+            CalendarInterval::Day => date.and_hms_opt(0, 0, 0),
+            CalendarInterval::Week => {
+                let monday = date - chrono::Days::new(date.weekday().num_days_from_monday() as u64);
+                monday.and_hms_opt(0, 0, 0)
+            }
+            CalendarInterval::Month => {
+                NaiveDate::from_ymd_opt(date.year(), date.month(), 1).and_then(|d| d.and_hms_opt(0, 0, 0))
+            }
+            CalendarInterval::Year => NaiveDate::from_ymd_opt(date.year(), 1, 1).and_then(|d| d.and_hms_opt(0, 0, 0)),
+        };
+        start.map_or(millis, |start| start.and_utc().timestamp_millis())
+    }
+}
+
+/// RFC 3339 form of a timestamp in milliseconds, for date bucket keys.
+pub fn format_millis(millis: i64) -> String {
+    DateTime::from_timestamp_millis(millis)
+        .map(|time| time.to_rfc3339_opts(SecondsFormat::Secs, true))
+        .unwrap_or_default()
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum AggregationResult {
+    Buckets { buckets: Vec<Bucket> },
+    Stats(Stats),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Bucket {
+    pub key: SortValue,
+    /// Readable form of a date bucket's key.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key_as_string: Option<String>,
+    pub doc_count: u64,
+}
+
+/// Summary of the numeric values of a key among the hits. Everything but
+/// `count` and `sum` is absent when no hit has a value.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Stats {
+    pub count: u64,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    pub avg: Option<f64>,
+    pub sum: f64,
+}
+
+impl Stats {
+    pub fn add(&mut self, value: f64) {
+        self.count += 1;
+        self.sum += value;
+        self.min = Some(self.min.map_or(value, |min| min.min(value)));
+        self.max = Some(self.max.map_or(value, |max| max.max(value)));
+        self.avg = Some(self.sum / self.count as f64);
+    }
+}
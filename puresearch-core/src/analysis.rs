@@ -0,0 +1,431 @@
+//! Text analysis: turning review text into the terms stored in, and looked
+//! up from, the inverted index.
+//!
+//! An analyzer runs three stages in order: char filters rewrite the raw text,
+//! a tokenizer splits it into tokens, and token filters normalise, drop or
+//! stem those tokens. The same analyzer must be used at ingest and query time
+//! for terms to line up.
+
+use rust_stemmers::{Algorithm, Stemmer};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+use unicode_segmentation::UnicodeSegmentation;
+
+/// A single term produced by analysis.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Token {
+    pub text: String,
+    /// Ordinal of the token in the source text. Positions are not renumbered
+    /// when a filter drops a token, so gaps left by stopwords are preserved.
+    pub position: u32,
+    /// Byte offsets of the token in the original text.
+    pub start: usize,
+    pub end: usize,
+}
+
+/// Rewrites text before tokenization.
+///
+/// Char filters must preserve byte length so that token offsets still point
+/// into the original text.
+pub trait CharFilter: Send + Sync {
+    fn filter(&self, text: &str) -> String;
+}
+
+pub trait Tokenizer: Send + Sync {
+    fn tokenize(&self, text: &str) -> Vec<Token>;
+}
+
+pub trait TokenFilter: Send + Sync {
+    fn filter(&self, tokens: Vec<Token>) -> Vec<Token>;
+}
+
+pub trait Analyzer: Send + Sync {
+    fn analyze(&self, text: &str) -> Vec<Token>;
+}
+
+/// An analyzer assembled from char filters, a tokenizer and token filters.
+pub struct TextAnalyzer {
+    char_filters: Vec<Box<dyn CharFilter>>,
+    tokenizer: Box<dyn Tokenizer>,
+    token_filters: Vec<Box<dyn TokenFilter>>,
+}
+
+impl TextAnalyzer {
+    pub fn new<T: Tokenizer + 'static>(tokenizer: T) -> Self {
+        Self {
+            char_filters: Vec::new(),
+            tokenizer: Box::new(tokenizer),
+            token_filters: Vec::new(),
+        }
+    }
+
+    pub fn with_char_filter<F: CharFilter + 'static>(mut self, filter: F) -> Self {
+        self.char_filters.push(Box::new(filter));
+        self
+    }
+
+    pub fn with_token_filter<F: TokenFilter + 'static>(mut self, filter: F) -> Self {
+        self.token_filters.push(Box::new(filter));
+        self
+    }
+}
+
+impl Analyzer for TextAnalyzer {
+    fn analyze(&self, text: &str) -> Vec<Token> {
+        let mut filtered = text.to_string();
+        for filter in &self.char_filters {
+            filtered = filter.filter(&filtered);
+        }
+        debug_assert_eq!(filtered.len(), text.len(), "char filters must preserve length");
+
+        let mut tokens = self.tokenizer.tokenize(&filtered);
+        for filter in &self.token_filters {
+            tokens = filter.filter(tokens);
+        }
+        tokens
+    }
+}
+
+/// Blanks out HTML tags and decodes character entities, padding with spaces
+/// so byte offsets are unchanged.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct HtmlStripCharFilter;
+
+impl CharFilter for HtmlStripCharFilter {
+    fn filter(&self, text: &str) -> String {
+        let mut out = String::with_capacity(text.len());
+        let mut rest = text;
+        while let Some(c) = rest.chars().next() {
+            let replacement = match c {
+                '<' => tag_len(rest).map(|len| (None, len)),
+                '&' => entity_at(rest).map(|(decoded, len)| (Some(decoded), len)),
+                _ => None,
+            };
+            match replacement {
+                Some((decoded, len)) => {
+                    let mut padding = len;
+                    if let Some(decoded) = decoded {
+                        out.push(decoded);
+                        padding -= decoded.len_utf8();
+                    }
+                    out.extend(std::iter::repeat_n(' ', padding));
+                    rest = &rest[len..];
+                }
+                None => {
+                    out.push(c);
+                    rest = &rest[c.len_utf8()..];
+                }
+            }
+        }
+        out
+    }
+}
+
+/// Byte length of the tag at the start of `text`, if it looks like one.
+/// A bare `<` as in "3 < 5" is left alone.
+fn tag_len(text: &str) -> Option<usize> {
+    let next = text[1..].chars().next()?;
+    if !(next.is_ascii_alphabetic() || next == '/' || next == '!') {
+        return None;
+    }
+    text.find('>').map(|end| end + 1)
+}
+
+/// Decodes the character entity at the start of `text`, returning the
+/// character and the byte length of the entity. Entities whose decoded
+/// character would be longer than the entity itself are left undecoded.
+fn entity_at(text: &str) -> Option<(char, usize)> {
+    let (end, _) = text.char_indices().take(12).find(|&(_, c)| c == ';')?;
+    let decoded = decode_entity(&text[1..end])?;
+    let len = end + 1;
+    (decoded.len_utf8() <= len).then_some((decoded, len))
+}
+
+fn decode_entity(name: &str) -> Option<char> {
+    match name {
+        "amp" => Some('&'),
+        "lt" => Some('<'),
+        "gt" => Some('>'),
+        "quot" => Some('"'),
+        "apos" => Some('\''),
+        "nbsp" => Some(' '),
+        _ => {
+            let code = name.strip_prefix('#')?;
+            let value = match code.strip_prefix(['x', 'X']) {
+                Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                None => code.parse().ok()?,
+            };
+            char::from_u32(value)
+        }
+    }
+}
+
+/// Splits text on Unicode word boundaries (UAX #29), dropping punctuation
+/// and whitespace.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct UnicodeWordTokenizer;
+
+impl Tokenizer for UnicodeWordTokenizer {
+    fn tokenize(&self, text: &str) -> Vec<Token> {
+        text.unicode_word_indices()
+            .enumerate()
+            .map(|(position, (start, word))| Token {
+                text: word.to_string(),
+                position: position as u32,
+                start,
+                end: start + word.len(),
+            })
+            .collect()
+    }
+}
+
+/// Emits the whole input as a single token.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct KeywordTokenizer;
+
+impl Tokenizer for KeywordTokenizer {
+    fn tokenize(&self, text: &str) -> Vec<Token> {
+        if text.is_empty() {
+            return Vec::new();
+        }
+        vec![Token {
+            text: text.to_string(),
+            position: 0,
+            start: 0,
+            end: text.len(),
+        }]
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct LowercaseFilter;
+
+impl TokenFilter for LowercaseFilter {
+    fn filter(&self, mut tokens: Vec<Token>) -> Vec<Token> {
+        for token in &mut tokens {
+            token.text = token.text.to_lowercase();
+        }
+        tokens
+    }
+}
+
+/// Folds accented and other non-ASCII Latin characters to their closest
+/// ASCII equivalent, so "café" and "cafe" produce the same term.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct AsciiFoldingFilter;
+
+impl TokenFilter for AsciiFoldingFilter {
+    fn filter(&self, mut tokens: Vec<Token>) -> Vec<Token> {
+        for token in &mut tokens {
+            if !token.text.is_ascii() {
+                token.text = fold_to_ascii(&token.text);
+            }
+        }
+        tokens
+    }
+}
+
+fn fold_to_ascii(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.nfd().filter(|c| !is_combining_mark(*c)) {
+        match c {
+            'ß' => out.push_str("ss"),
+            'æ' => out.push_str("ae"),
+            'Æ' => out.push_str("AE"),
+            'œ' => out.push_str("oe"),
+            'Œ' => out.push_str("OE"),
+            'ø' => out.push('o'),
+            'Ø' => out.push('O'),
+            'đ' | 'ð' => out.push('d'),
+            'Đ' | 'Ð' => out.push('D'),
+            'ł' => out.push('l'),
+            'Ł' => out.push('L'),
+            'þ' => out.push_str("th"),
+            'Þ' => out.push_str("TH"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+/// Commonly ignored English words. Negations are deliberately absent since
+/// "not" and "no" change the meaning of a review.
+pub const ENGLISH_STOPWORDS: &[&str] = &[
+    "a", "an", "and", "are", "as", "at", "be", "but", "by", "for", "if", "in", "into", "is", "it",
+    "of", "on", "or", "such", "that", "the", "their", "then", "there", "these", "they", "this",
+    "to", "was", "will", "with",
+];
+
+/// Drops tokens found in a stopword list. Expects lowercased input.
+pub struct StopwordFilter {
+    stopwords: HashSet<String>,
+}
+
+impl StopwordFilter {
+    pub fn new<I, S>(stopwords: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Self {
+            stopwords: stopwords.into_iter().map(Into::into).collect(),
+        }
+    }
+
+    pub fn english() -> Self {
+        Self::new(ENGLISH_STOPWORDS.iter().copied())
+    }
+}
+
+impl TokenFilter for StopwordFilter {
+    fn filter(&self, mut tokens: Vec<Token>) -> Vec<Token> {
+        tokens.retain(|token| !self.stopwords.contains(&token.text));
+        tokens
+    }
+}
+
+/// Reduces tokens to their Snowball stem, so "running" and "runs" both
+/// become "run". Expects lowercased input.
+pub struct StemmerFilter {
+    stemmer: Stemmer,
+}
+
+impl StemmerFilter {
+    pub fn new(language: Language) -> Self {
+        Self {
+            stemmer: Stemmer::create(language.algorithm()),
+        }
+    }
+}
+
+impl TokenFilter for StemmerFilter {
+    fn filter(&self, mut tokens: Vec<Token>) -> Vec<Token> {
+        for token in &mut tokens {
+            let stemmed = self.stemmer.stem(&token.text);
+            if stemmed != token.text {
+                token.text = stemmed.into_owned();
+            }
+        }
+        tokens
+    }
+}
+
+/// Languages with a Snowball stemmer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Language {
+    Danish,
+    Dutch,
+    English,
+    Finnish,
+    French,
+    German,
+    Italian,
+    Norwegian,
+    Portuguese,
+    Russian,
+    Spanish,
+    Swedish,
+}
+
+impl Language {
+    fn algorithm(self) -> Algorithm {
+        match self {
+            Language::Danish => Algorithm::Danish,
+            Language::Dutch => Algorithm::Dutch,
+            Language::English => Algorithm::English,
+            Language::Finnish => Algorithm::Finnish,
+            Language::French => Algorithm::French,
+            Language::German => Algorithm::German,
+            Language::Italian => Algorithm::Italian,
+            Language::Norwegian => Algorithm::Norwegian,
+            Language::Portuguese => Algorithm::Portuguese,
+            Language::Russian => Algorithm::Russian,
+            Language::Spanish => Algorithm::Spanish,
+            Language::Swedish => Algorithm::Swedish,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenizerKind {
+    /// Split on Unicode word boundaries.
+    #[default]
+    UnicodeWords,
+    /// Treat the whole text as one token, for IDs and exact values.
+    Keyword,
+}
+
+/// Serializable description of an analyzer, stored on an `Index`.
+///
+/// The default lowercases, folds to ASCII and applies English stemming,
+/// without HTML stripping or stopword removal.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AnalyzerConfig {
+    pub html_strip: bool,
+    pub tokenizer: TokenizerKind,
+    pub lowercase: bool,
+    pub ascii_folding: bool,
+    /// Stopwords to drop. `None` keeps every token.
+    pub stopwords: Option<Vec<String>>,
+    pub stemmer: Option<Language>,
+}
+
+impl Default for AnalyzerConfig {
+    fn default() -> Self {
+        Self {
+            html_strip: false,
+            tokenizer: TokenizerKind::UnicodeWords,
+            lowercase: true,
+            ascii_folding: true,
+            stopwords: None,
+            stemmer: Some(Language::English),
+        }
+    }
+}
+
+impl AnalyzerConfig {
+    /// Lowercasing and folding only: no stemming.
+    pub fn simple() -> Self {
+        Self {
+            stemmer: None,
+            ..Self::default()
+        }
+    }
+
+    /// The default pipeline plus HTML stripping and English stopwords.
+    pub fn english() -> Self {
+        Self {
+            html_strip: true,
+            stopwords: Some(ENGLISH_STOPWORDS.iter().map(|word| word.to_string()).collect()),
+            ..Self::default()
+        }
+    }
+
+    pub fn build(&self) -> TextAnalyzer {
+        let mut analyzer = match self.tokenizer {
+            TokenizerKind::UnicodeWords => TextAnalyzer::new(UnicodeWordTokenizer),
+            TokenizerKind::Keyword => TextAnalyzer::new(KeywordTokenizer),
+        };
+        if self.html_strip {
+            analyzer = analyzer.with_char_filter(HtmlStripCharFilter);
+        }
+        if self.lowercase {
+            analyzer = analyzer.with_token_filter(LowercaseFilter);
+        }
+        if self.ascii_folding {
+            analyzer = analyzer.with_token_filter(AsciiFoldingFilter);
+        }
+        if let Some(stopwords) = &self.stopwords {
+            analyzer = analyzer.with_token_filter(StopwordFilter::new(stopwords.iter().cloned()));
+        }
+        if let Some(language) = self.stemmer {
+            analyzer = analyzer.with_token_filter(StemmerFilter::new(language));
+        }
+        analyzer
+    }
+}
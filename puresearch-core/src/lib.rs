@@ -1,7 +1,16 @@
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 use uuid::Uuid;
 
+pub mod aggregation;
+pub mod analysis;
+pub mod mapping;
+pub mod query;
+pub mod sort;
+
+use analysis::AnalyzerConfig;
+use mapping::{MappingError, Mappings};
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ReviewDocument {
     pub id: Uuid,
@@ -22,14 +31,44 @@ impl ReviewDocument {
                 .as_secs(),
         }
     }
+
+    /// Applies `patch`, keeping the ID and timestamp.
+    pub fn patch(&mut self, patch: DocumentPatch) {
+        if let Some(content) = patch.content {
+            self.content = content;
+        }
+        for (key, value) in patch.metadata {
+            match value {
+                Some(value) => self.metadata.insert(key, value),
+                None => self.metadata.remove(&key),
+            };
+        }
+    }
+}
+
+/// A partial update to a document: new content, if given, replaces the old,
+/// and metadata is merged key by key, with `null` removing a key.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DocumentPatch {
+    #[serde(default)]
+    pub content: Option<String>,
+    #[serde(default)]
+    pub metadata: HashMap<String, Option<String>>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Index {
     pub id: Uuid,
     pub name: String,
-    pub documents: Vec<Uuid>,
+    pub documents: BTreeSet<Uuid>,
     pub created_at: u64,
+    /// Analyzer applied to the content of this index's documents and to
+    /// queries against it.
+    pub analyzer: AnalyzerConfig,
+    /// Types of this index's metadata keys, checked when documents are
+    /// stored in it.
+    #[serde(default)]
+    pub mappings: Mappings,
 }
 
 impl Index {
@@ -37,35 +76,116 @@ impl Index {
         Self {
             id: Uuid::new_v4(),
             name,
-            documents: Vec::new(),
+            documents: BTreeSet::new(),
             created_at: std::time::SystemTime::now()
                 .duration_since(std::time::UNIX_EPOCH)
                 .unwrap()
                 .as_secs(),
+            analyzer: AnalyzerConfig::default(),
+            mappings: Mappings::new(),
         }
     }
 
-    pub fn add_document(&mut self, doc_id: Uuid) {
-        if !self.documents.contains(&doc_id) {
-            self.documents.push(doc_id);
-        }
+    pub fn with_analyzer(mut self, analyzer: AnalyzerConfig) -> Self {
+        self.analyzer = analyzer;
+        self
+    }
+
+    pub fn with_mappings(mut self, mappings: Mappings) -> Self {
+        self.mappings = mappings;
+        self
+    }
+
+    /// Checks `doc`'s metadata against this index's mappings.
+    pub fn validate(&self, doc: &ReviewDocument) -> Result<(), MappingError> {
+        mapping::validate(&self.mappings, &doc.metadata)
+    }
+
+    /// Adds `doc_id` to this index, returning whether it was not already a
+    /// member.
+    pub fn add_document(&mut self, doc_id: Uuid) -> bool {
+        self.documents.insert(doc_id)
+    }
+
+    /// Removes `doc_id` from this index, returning whether it was a member.
+    pub fn remove_document(&mut self, doc_id: &Uuid) -> bool {
+        self.documents.remove(doc_id)
     }
+
+    pub fn contains(&self, doc_id: &Uuid) -> bool {
+        self.documents.contains(doc_id)
+    }
+}
+
+/// A document ID paired with its relevance score for a query.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ScoredDocument {
+    pub id: Uuid,
+    pub score: f32,
 }
 
 pub mod storage {
     use super::*;
+    use crate::query::Query;
+    use crate::sort::SortField;
     use anyhow::Result;
 
+    /// A single change to stored documents or indices, for writing several
+    /// at once.
+    #[derive(Debug, Clone)]
+    pub enum WriteOp {
+        Store(ReviewDocument),
+        /// Patches a stored document; a no-op if it does not exist.
+        Patch { id: Uuid, patch: DocumentPatch },
+        /// Replaces a stored document's content and metadata, keeping its
+        /// ID and timestamp; a no-op if it does not exist.
+        Replace {
+            id: Uuid,
+            content: String,
+            metadata: HashMap<String, String>,
+        },
+        Delete(Uuid),
+        StoreIndex(Index),
+        DeleteIndex(Uuid),
+        AddToIndex { index_id: Uuid, doc_id: Uuid },
+        RemoveFromIndex { index_id: Uuid, doc_id: Uuid },
+    }
+
     pub trait StorageEngine {
         fn store_document(&mut self, doc: &ReviewDocument) -> Result<()>;
         fn get_document(&self, id: &Uuid) -> Result<Option<ReviewDocument>>;
         fn delete_document(&mut self, id: &Uuid) -> Result<bool>;
         fn list_documents(&self) -> Result<Vec<Uuid>>;
+        /// Carries out `ops` in order, each seeing the effects of those
+        /// before it, as one atomic write: if any op is invalid none is
+        /// applied, and after a crash either all of them are recovered or
+        /// none is. Returns whether each op changed anything.
+        fn write_batch(&mut self, ops: Vec<WriteOp>) -> Result<Vec<bool>>;
     }
 
     pub trait IndexStorage {
         fn store_index(&mut self, index: &Index) -> Result<()>;
         fn get_index(&self, id: &Uuid) -> Result<Option<Index>>;
         fn list_indices(&self) -> Result<Vec<Index>>;
+        /// Deletes an index, leaving its member documents in place. Returns
+        /// whether it existed.
+        fn delete_index(&mut self, id: &Uuid) -> Result<bool>;
+        /// Makes an existing document a member of an existing index. Returns
+        /// whether it was not already a member.
+        fn add_document(&mut self, index_id: &Uuid, doc_id: &Uuid) -> Result<bool>;
+        /// Removes a document from an index without deleting it. Returns
+        /// whether it was a member.
+        fn remove_document(&mut self, index_id: &Uuid, doc_id: &Uuid) -> Result<bool>;
+    }
+
+    pub trait SearchIndex {
+        /// Returns every matching document, most relevant first.
+        fn search(&self, query: &Query) -> Result<Vec<ScoredDocument>> {
+            self.search_sorted(query, &[])
+        }
+
+        /// Returns every matching document in `sort` order, or most relevant
+        /// first if `sort` is empty.
+        fn search_sorted(&self, query: &Query, sort: &[SortField]) -> Result<Vec<ScoredDocument>>;
     }
 }
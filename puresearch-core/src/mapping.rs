@@ -0,0 +1,107 @@
+//! Types for metadata keys.
+//!
+//! Metadata values are always stored as the strings they were sent as. An
+//! index's mappings say how to read them: which values are valid, and
+//! whether they compare as strings, numbers or dates in filters, sorting and
+//! aggregations.
+
+use chrono::{DateTime, NaiveDate};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use thiserror::Error;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FieldType {
+    /// An exact string, such as an ID or a category.
+    Keyword,
+    /// Free text, searched through the analyzer.
+    Text,
+    /// A whole number that fits in an `i64`.
+    Integer,
+    Float,
+    /// `true` or `false`.
+    Boolean,
+    /// A `YYYY-MM-DD` date or an RFC 3339 timestamp, taken as UTC when it
+    /// has no offset.
+    Date,
+}
+
+/// A metadata value read according to its field type.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FieldValue {
+    String(String),
+    /// Integers, floats and dates, the latter as milliseconds since the
+    /// Unix epoch.
+    Number(f64),
+    Boolean(bool),
+}
+
+impl FieldType {
+    /// Reads `value` as this type, or describes why it is not one.
+    pub fn parse(&self, value: &str) -> Result<FieldValue, String> {
+        match self {
+            FieldType::Keyword | FieldType::Text => Ok(FieldValue::String(value.to_string())),
+            FieldType::Integer => value
+                .trim()
+                .parse::<i64>()
+                .map(|n| FieldValue::Number(n as f64))
+                .map_err(|_| format!("expected an integer, got '{value}'")),
+            FieldType::Float => value
+                .trim()
+                .parse::<f64>()
+                .ok()
+                .filter(|n| n.is_finite())
+                .map(FieldValue::Number)
+                .ok_or_else(|| format!("expected a number, got '{value}'")),
+            FieldType::Boolean => match value.trim() {
+                "true" => Ok(FieldValue::Boolean(true)),
+                "false" => Ok(FieldValue::Boolean(false)),
+                _ => Err(format!("expected true or false, got '{value}'")),
+            },
+            FieldType::Date => parse_date(value.trim())
+                .map(|millis| FieldValue::Number(millis as f64))
+                .ok_or_else(|| format!("expected a date (YYYY-MM-DD or RFC 3339), got '{value}'")),
+        }
+    }
+
+    /// Whether values of this type are ordered as numbers.
+    pub fn is_numeric(&self) -> bool {
+        matches!(self, FieldType::Integer | FieldType::Float | FieldType::Date)
+    }
+}
+
+/// Milliseconds since the Unix epoch.
+fn parse_date(value: &str) -> Option<i64> {
+    if let Ok(timestamp) = DateTime::parse_from_rfc3339(value) {
+        return Some(timestamp.timestamp_millis());
+    }
+    let date = NaiveDate::parse_from_str(value, "%Y-%m-%d").ok()?;
+    Some(date.and_hms_opt(0, 0, 0)?.and_utc().timestamp_millis())
+}
+
+/// Metadata keys and the types of their values. Keys that are not mapped
+/// accept any string.
+pub type Mappings = BTreeMap<String, FieldType>;
+
+/// A metadata value that does not match its mapped type.
+#[derive(Debug, Clone, Error, PartialEq, Eq, Serialize)]
+#[error("invalid value for field '{field}': {message}")]
+pub struct MappingError {
+    pub field: String,
+    pub message: String,
+}
+
+/// Checks every mapped key present in `metadata`, reporting the first
+/// invalid one in key order.
+pub fn validate(mappings: &Mappings, metadata: &HashMap<String, String>) -> Result<(), MappingError> {
+    for (field, field_type) in mappings {
+        if let Some(value) = metadata.get(field) {
+            field_type.parse(value).map_err(|message| MappingError {
+                field: field.clone(),
+                message,
+            })?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,739 @@
+//! Query language for `SearchQuery.q`.
+//!
+//! ```text
+//! battery AND (life OR charge) -cable "stopped working"~2 source:amazon
+//! ```
+//!
+//! Supported syntax:
+//! - bare words and `"quoted phrases"`, phrases optionally followed by `~slop`
+//! - `word~N` or `word~` for typo-tolerant matching within N edits (or a
+//!   distance chosen from the word's length)
+//! - `AND` / `&&`, `OR` / `||`, `NOT` / `!` and parentheses; `AND` binds
+//!   tighter than `OR`
+//! - `+clause` (required) and `-clause` (excluded)
+//! - `field:value`, `field:"phrase"` and `field:(group)` to search a metadata
+//!   key instead of the review content; `*` matches every document
+//! - `field:>=4`, `field:<b` and `field:[2 TO 5]` (`{}` for exclusive
+//!   bounds, `*` for an open end) to require a metadata value in a range, and
+//!   `_exists_:field` to require the key to be set; these filter without
+//!   affecting scores
+//!
+//! [`QueryParser::parse_filter`] reads the same syntax as a pure filter, in
+//! which `field:value` requires the exact metadata value and
+//! `field:(a OR b)` any one of a list of values.
+//!
+//! Clauses written next to each other are joined with the parser's default
+//! operator, `AND` unless configured otherwise. Query text is kept as
+//! written; the search executor runs it through the target index's analyzer,
+//! so a single `Term` may expand into several index terms.
+
+use serde::{Deserialize, Serialize};
+use std::ops::Bound;
+use thiserror::Error;
+
+/// Field searched when a clause names none.
+pub const DEFAULT_FIELD: &str = "content";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Query {
+    /// Every document, with a constant score.
+    MatchAll,
+    /// Documents whose `field` contains every term `text` analyzes to.
+    Term { field: Option<String>, text: String },
+    /// Documents whose `field` contains the terms of `text` in order. `slop`
+    /// is how many positions the terms may be moved, in total, to line up.
+    Phrase {
+        field: Option<String>,
+        text: String,
+        slop: u32,
+    },
+    /// Like `Term`, but each term also matches indexed terms within
+    /// `fuzziness` edits of it, at a lower score than an exact match.
+    Fuzzy {
+        field: Option<String>,
+        text: String,
+        fuzziness: Fuzziness,
+    },
+    Bool(BoolQuery),
+    /// Documents whose metadata satisfies `filter`, all with the same score.
+    Filter(Filter),
+}
+
+/// A condition on the raw, unanalyzed value of a metadata key.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Filter {
+    /// The key is set to exactly `value`.
+    Term { field: String, value: String },
+    /// The key is set to one of `values`.
+    In { field: String, values: Vec<String> },
+    /// The key is set to a value between the bounds. Values are compared as
+    /// numbers when every given bound is a number, and as strings otherwise.
+    Range {
+        field: String,
+        lower: Bound<String>,
+        upper: Bound<String>,
+    },
+    /// The key is set, to any value.
+    Exists { field: String },
+}
+
+/// Maximum edits allowed for a fuzzy term.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Fuzziness {
+    /// 0 edits for terms of up to 2 characters, 1 for up to 5, 2 beyond.
+    Auto,
+    Distance(u32),
+}
+
+impl Fuzziness {
+    /// Largest edit distance supported, since larger ones match most of the
+    /// dictionary.
+    pub const MAX_DISTANCE: u32 = 2;
+
+    pub fn distance_for(&self, term: &str) -> u32 {
+        match self {
+            Fuzziness::Auto => match term.chars().count() {
+                0..=2 => 0,
+                3..=5 => 1,
+                _ => 2,
+            },
+            Fuzziness::Distance(distance) => *distance,
+        }
+    }
+}
+
+impl std::str::FromStr for Fuzziness {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("auto") {
+            return Ok(Fuzziness::Auto);
+        }
+        match s.parse() {
+            Ok(distance) if distance <= Self::MAX_DISTANCE => Ok(Fuzziness::Distance(distance)),
+            _ => Err(format!(
+                "fuzziness must be 'auto' or an edit distance from 0 to {}",
+                Self::MAX_DISTANCE
+            )),
+        }
+    }
+}
+
+/// Combination of clauses.
+///
+/// A document matches when it matches every `must` and `filter` clause and
+/// no `must_not` clause. If there are no `must` clauses it must also match at
+/// least one `should` clause; otherwise `should` clauses only add to the
+/// score. `filter` clauses never contribute to the score.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BoolQuery {
+    pub must: Vec<Query>,
+    pub should: Vec<Query>,
+    pub must_not: Vec<Query>,
+    pub filter: Vec<Query>,
+}
+
+impl Query {
+    pub fn term(text: &str) -> Self {
+        Query::Term {
+            field: None,
+            text: text.to_string(),
+        }
+    }
+
+    pub fn phrase(text: &str, slop: u32) -> Self {
+        Query::Phrase {
+            field: None,
+            text: text.to_string(),
+            slop,
+        }
+    }
+
+    /// Parses `input` with the default parser settings.
+    pub fn parse(input: &str) -> Result<Query, ParseError> {
+        QueryParser::default().parse(input)
+    }
+
+    /// This query restricted to documents matching `filter`, which does not
+    /// change their scores.
+    pub fn filtered(self, filter: Query) -> Self {
+        Query::Bool(BoolQuery {
+            must: vec![self],
+            filter: vec![filter],
+            ..BoolQuery::default()
+        })
+    }
+}
+
+#[derive(Debug, Clone, Error, PartialEq, Eq, Serialize)]
+#[error("{message} at position {position}")]
+pub struct ParseError {
+    /// Character offset into the query string.
+    pub position: usize,
+    pub message: String,
+}
+
+impl ParseError {
+    fn new(position: usize, message: impl Into<String>) -> Self {
+        Self {
+            position,
+            message: message.into(),
+        }
+    }
+}
+
+/// How clauses written next to each other are combined.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Operator {
+    #[default]
+    And,
+    Or,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct QueryParser {
+    pub default_operator: Operator,
+    /// Fuzziness applied to words written without `~`.
+    pub fuzziness: Option<Fuzziness>,
+}
+
+impl QueryParser {
+    pub fn new(default_operator: Operator) -> Self {
+        Self {
+            default_operator,
+            fuzziness: None,
+        }
+    }
+
+    pub fn with_fuzziness(mut self, fuzziness: Fuzziness) -> Self {
+        self.fuzziness = Some(fuzziness);
+        self
+    }
+
+    pub fn parse(&self, input: &str) -> Result<Query, ParseError> {
+        self.parse_with(input, false)
+    }
+
+    /// Parses `input` as a filter on metadata values. Every clause must name
+    /// a field; `field:value` and `field:"value"` require that exact value
+    /// and a group of values joined by `OR` requires any one of them.
+    pub fn parse_filter(&self, input: &str) -> Result<Query, ParseError> {
+        self.parse_with(input, true)
+    }
+
+    fn parse_with(&self, input: &str, filter: bool) -> Result<Query, ParseError> {
+        let tokens = lex(input)?;
+        let mut parser = Parser {
+            tokens,
+            pos: 0,
+            depth: 0,
+            default_operator: self.default_operator,
+            fuzziness: self.fuzziness,
+            filter,
+            end: input.chars().count(),
+        };
+        if parser.peek().is_none() {
+            return Ok(Query::Bool(BoolQuery::default()));
+        }
+        let clause = parser.parse_or(None)?;
+        if let Some(token) = parser.peek() {
+            let message = match token.kind {
+                TokenKind::RParen => "unmatched ')'",
+                _ => "unexpected input",
+            };
+            return Err(ParseError::new(token.position, message));
+        }
+        Ok(clause.into_query())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum TokenKind {
+    Word {
+        text: String,
+        fuzziness: Option<Fuzziness>,
+    },
+    /// A field name followed by `:`.
+    Field(String),
+    Phrase { text: String, slop: u32 },
+    /// `[lower TO upper]`, with `{` or `}` for an exclusive bound.
+    Range {
+        lower: Bound<String>,
+        upper: Bound<String>,
+    },
+    And,
+    Or,
+    Not,
+    Plus,
+    Minus,
+    LParen,
+    RParen,
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    kind: TokenKind,
+    position: usize,
+}
+
+fn is_word_char(c: char) -> bool {
+    !c.is_whitespace() && !matches!(c, '(' | ')' | '"')
+}
+
+fn lex(input: &str) -> Result<Vec<Token>, ParseError> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let start = i;
+        let kind = match c {
+            c if c.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            '(' => {
+                i += 1;
+                TokenKind::LParen
+            }
+            ')' => {
+                i += 1;
+                TokenKind::RParen
+            }
+            '+' => {
+                i += 1;
+                TokenKind::Plus
+            }
+            '-' => {
+                i += 1;
+                TokenKind::Minus
+            }
+            '!' => {
+                i += 1;
+                TokenKind::Not
+            }
+            '"' => {
+                i += 1;
+                let mut text = String::new();
+                loop {
+                    match chars.get(i) {
+                        None => return Err(ParseError::new(start, "unterminated phrase")),
+                        Some('"') => break,
+                        Some('\\') if i + 1 < chars.len() => {
+                            text.push(chars[i + 1]);
+                            i += 2;
+                        }
+                        Some(&c) => {
+                            text.push(c);
+                            i += 1;
+                        }
+                    }
+                }
+                i += 1;
+                let mut slop = 0;
+                if chars.get(i) == Some(&'~') {
+                    let digits_start = i + 1;
+                    i = digits_start;
+                    while chars.get(i).is_some_and(|c| c.is_ascii_digit()) {
+                        i += 1;
+                    }
+                    let digits: String = chars[digits_start..i].iter().collect();
+                    slop = digits
+                        .parse()
+                        .map_err(|_| ParseError::new(digits_start, "expected a number after '~'"))?;
+                }
+                TokenKind::Phrase { text, slop }
+            }
+            '[' | '{' if matches!(tokens.last(), Some(Token { kind: TokenKind::Field(_), .. })) => {
+                let Some(length) = chars[i..].iter().position(|&c| c == ']' || c == '}') else {
+                    return Err(ParseError::new(start, "unterminated range"));
+                };
+                let body: String = chars[i + 1..i + length].iter().collect();
+                let upper_inclusive = chars[i + length] == ']';
+                i += length + 1;
+                let parts: Vec<&str> = body.split_whitespace().collect();
+                let [lower, "TO", upper] = parts.as_slice() else {
+                    return Err(ParseError::new(start, "expected a range like [lower TO upper]"));
+                };
+                let bound = |value: &str, inclusive: bool| match value {
+                    "*" => Bound::Unbounded,
+                    _ if inclusive => Bound::Included(value.to_string()),
+                    _ => Bound::Excluded(value.to_string()),
+                };
+                TokenKind::Range {
+                    lower: bound(lower, c == '['),
+                    upper: bound(upper, upper_inclusive),
+                }
+            }
+            _ => {
+                let mut word = String::new();
+                let mut field = None;
+                let mut fuzziness = None;
+                // A field's value may itself contain ':', as in timestamps.
+                let is_value = matches!(tokens.last(), Some(Token { kind: TokenKind::Field(_), .. }));
+                while i < chars.len() && is_word_char(chars[i]) {
+                    match chars[i] {
+                        '\\' if i + 1 < chars.len() => {
+                            word.push(chars[i + 1]);
+                            i += 2;
+                        }
+                        '~' if !word.is_empty() => {
+                            let digits_start = i + 1;
+                            i = digits_start;
+                            while i < chars.len() && is_word_char(chars[i]) {
+                                i += 1;
+                            }
+                            let digits: String = chars[digits_start..i].iter().collect();
+                            fuzziness = Some(if digits.is_empty() {
+                                Fuzziness::Auto
+                            } else {
+                                match digits.parse() {
+                                    Ok(distance) if distance <= Fuzziness::MAX_DISTANCE => {
+                                        Fuzziness::Distance(distance)
+                                    }
+                                    _ => {
+                                        return Err(ParseError::new(
+                                            digits_start,
+                                            format!("expected an edit distance from 0 to {}", Fuzziness::MAX_DISTANCE),
+                                        ))
+                                    }
+                                }
+                            });
+                        }
+                        ':' if !is_value && field.is_none() && !word.is_empty() => {
+                            field = Some(std::mem::take(&mut word));
+                            i += 1;
+                            break;
+                        }
+                        c => {
+                            word.push(c);
+                            i += 1;
+                        }
+                    }
+                }
+                match field {
+                    Some(field) => TokenKind::Field(field),
+                    None => match word.as_str() {
+                        "AND" | "&&" => TokenKind::And,
+                        "OR" | "||" => TokenKind::Or,
+                        "NOT" => TokenKind::Not,
+                        _ => TokenKind::Word {
+                            text: word,
+                            fuzziness,
+                        },
+                    },
+                }
+            }
+        };
+        tokens.push(Token {
+            kind,
+            position: start,
+        });
+    }
+    Ok(tokens)
+}
+
+/// How a clause takes part in the enclosing boolean.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Occur {
+    Default,
+    Must,
+    MustNot,
+}
+
+struct Clause {
+    occur: Occur,
+    query: Query,
+}
+
+impl Clause {
+    fn into_query(self) -> Query {
+        match self.occur {
+            Occur::MustNot => Query::Bool(BoolQuery {
+                must: vec![Query::MatchAll],
+                must_not: vec![self.query],
+                ..BoolQuery::default()
+            }),
+            _ => self.query,
+        }
+    }
+}
+
+/// Deepest nesting of parenthesized groups a query may have. Groups are
+/// parsed recursively, so this bounds the stack a query can use.
+pub const MAX_NESTING: usize = 64;
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    /// Parenthesized groups open at the current token.
+    depth: usize,
+    default_operator: Operator,
+    fuzziness: Option<Fuzziness>,
+    /// Whether field values are exact metadata values rather than text.
+    filter: bool,
+    /// Position reported for errors at the end of input.
+    end: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next_position(&self) -> usize {
+        self.peek().map_or(self.end, |token| token.position)
+    }
+
+    fn eat(&mut self, kind: &TokenKind) -> bool {
+        if self.peek().is_some_and(|token| &token.kind == kind) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Whether the next token can begin a clause, for implicit operators.
+    fn at_clause_start(&self) -> bool {
+        self.peek().is_some_and(|token| {
+            !matches!(token.kind, TokenKind::And | TokenKind::Or | TokenKind::RParen)
+        })
+    }
+
+    fn parse_or(&mut self, field: Option<&str>) -> Result<Clause, ParseError> {
+        let mut clauses = vec![self.parse_and(field)?];
+        while self.eat(&TokenKind::Or)
+            || (self.default_operator == Operator::Or && self.at_clause_start())
+        {
+            clauses.push(self.parse_and(field)?);
+        }
+        if clauses.len() == 1 {
+            return Ok(clauses.pop().unwrap());
+        }
+
+        let mut bool_query = BoolQuery::default();
+        for clause in clauses {
+            match clause.occur {
+                Occur::Default => bool_query.should.push(clause.query),
+                Occur::Must => bool_query.must.push(clause.query),
+                Occur::MustNot => bool_query.must_not.push(clause.query),
+            }
+        }
+        Ok(Clause {
+            occur: Occur::Default,
+            query: Query::Bool(bool_query),
+        })
+    }
+
+    fn parse_and(&mut self, field: Option<&str>) -> Result<Clause, ParseError> {
+        let mut clauses = vec![self.parse_unary(field)?];
+        while self.eat(&TokenKind::And)
+            || (self.default_operator == Operator::And && self.at_clause_start())
+        {
+            clauses.push(self.parse_unary(field)?);
+        }
+        if clauses.len() == 1 {
+            return Ok(clauses.pop().unwrap());
+        }
+
+        let mut bool_query = BoolQuery::default();
+        for clause in clauses {
+            match clause.occur {
+                Occur::MustNot => bool_query.must_not.push(clause.query),
+                _ => bool_query.must.push(clause.query),
+            }
+        }
+        if bool_query.must.is_empty() {
+            bool_query.must.push(Query::MatchAll);
+        }
+        Ok(Clause {
+            occur: Occur::Default,
+            query: Query::Bool(bool_query),
+        })
+    }
+
+    fn parse_unary(&mut self, field: Option<&str>) -> Result<Clause, ParseError> {
+        let occur = if self.eat(&TokenKind::Plus) {
+            Occur::Must
+        } else if self.eat(&TokenKind::Minus) || self.eat(&TokenKind::Not) {
+            Occur::MustNot
+        } else {
+            Occur::Default
+        };
+        let query = self.parse_primary(field)?;
+        Ok(Clause { occur, query })
+    }
+
+    fn parse_primary(&mut self, field: Option<&str>) -> Result<Query, ParseError> {
+        let position = self.next_position();
+        let Some(token) = self.tokens.get(self.pos).cloned() else {
+            return Err(ParseError::new(position, "expected a search term"));
+        };
+        self.pos += 1;
+
+        match token.kind {
+            TokenKind::Word { text, .. } if text == "*" && field.is_none() => Ok(Query::MatchAll),
+            TokenKind::Word { text, fuzziness } => {
+                if let Some(field) = field {
+                    if let Some((op, value)) = split_comparison(&text) {
+                        if value.is_empty() {
+                            return Err(ParseError::new(position, format!("expected a value after '{op}'")));
+                        }
+                        return Ok(Query::Filter(comparison_range(field, op, value)));
+                    }
+                }
+                if self.filter {
+                    let field = self.filter_field(field, position)?;
+                    if fuzziness.is_some() {
+                        return Err(ParseError::new(position, "fuzzy matching is not supported in filters"));
+                    }
+                    return Ok(Query::Filter(Filter::Term { field, value: text }));
+                }
+                match fuzziness.or(self.fuzziness) {
+                    Some(fuzziness) => Ok(Query::Fuzzy {
+                        field: field.map(str::to_string),
+                        text,
+                        fuzziness,
+                    }),
+                    None => Ok(Query::Term {
+                        field: field.map(str::to_string),
+                        text,
+                    }),
+                }
+            }
+            TokenKind::Phrase { text, .. } if self.filter => {
+                let field = self.filter_field(field, position)?;
+                Ok(Query::Filter(Filter::Term { field, value: text }))
+            }
+            TokenKind::Phrase { text, slop } => Ok(Query::Phrase {
+                field: field.map(str::to_string),
+                text,
+                slop,
+            }),
+            TokenKind::Range { lower, upper } => match field {
+                Some(field) => Ok(Query::Filter(Filter::Range {
+                    field: field.to_string(),
+                    lower,
+                    upper,
+                })),
+                None => Err(ParseError::new(position, "expected a search term")),
+            },
+            TokenKind::LParen => {
+                if self.eat(&TokenKind::RParen) {
+                    return Err(ParseError::new(position, "empty group"));
+                }
+                if self.depth == MAX_NESTING {
+                    return Err(ParseError::new(
+                        position,
+                        format!("groups nested more than {MAX_NESTING} deep"),
+                    ));
+                }
+                self.depth += 1;
+                let clause = self.parse_or(field)?;
+                self.depth -= 1;
+                if !self.eat(&TokenKind::RParen) {
+                    return Err(ParseError::new(position, "unclosed '('"));
+                }
+                match field {
+                    Some(field) if self.filter => Ok(collapse_in(field, clause.into_query())),
+                    _ => Ok(clause.into_query()),
+                }
+            }
+            TokenKind::Field(name) if name == EXISTS_FIELD => match self.peek().map(|token| &token.kind) {
+                Some(TokenKind::Word { text, fuzziness: None }) => {
+                    let field = text.clone();
+                    self.pos += 1;
+                    Ok(Query::Filter(Filter::Exists { field }))
+                }
+                _ => Err(ParseError::new(
+                    self.next_position(),
+                    format!("expected a field name after '{EXISTS_FIELD}:'"),
+                )),
+            },
+            TokenKind::Field(name) => {
+                if field.is_some() {
+                    return Err(ParseError::new(position, "nested field clause"));
+                }
+                match self.peek().map(|token| &token.kind) {
+                    Some(
+                        TokenKind::Word { .. }
+                        | TokenKind::Phrase { .. }
+                        | TokenKind::Range { .. }
+                        | TokenKind::LParen,
+                    ) => self.parse_primary(Some(&name)),
+                    _ => Err(ParseError::new(
+                        self.next_position(),
+                        format!("expected a value for field '{name}'"),
+                    )),
+                }
+            }
+            TokenKind::RParen => Err(ParseError::new(position, "unmatched ')'")),
+            TokenKind::And | TokenKind::Or | TokenKind::Not | TokenKind::Plus | TokenKind::Minus => {
+                Err(ParseError::new(position, "expected a search term"))
+            }
+        }
+    }
+
+    /// The field a filter value applies to, which must be named.
+    fn filter_field(&self, field: Option<&str>, position: usize) -> Result<String, ParseError> {
+        field
+            .map(str::to_string)
+            .ok_or_else(|| ParseError::new(position, "filter clauses must name a field"))
+    }
+}
+
+/// Pseudo-field whose value names a field that must be set.
+const EXISTS_FIELD: &str = "_exists_";
+
+/// Splits a value such as `>=4` into its comparison and operand.
+fn split_comparison(text: &str) -> Option<(&'static str, &str)> {
+    [">=", "<=", ">", "<"]
+        .into_iter()
+        .find_map(|op| text.strip_prefix(op).map(|value| (op, value)))
+}
+
+fn comparison_range(field: &str, op: &str, value: &str) -> Filter {
+    let value = value.to_string();
+    let (lower, upper) = match op {
+        ">=" => (Bound::Included(value), Bound::Unbounded),
+        ">" => (Bound::Excluded(value), Bound::Unbounded),
+        "<=" => (Bound::Unbounded, Bound::Included(value)),
+        _ => (Bound::Unbounded, Bound::Excluded(value)),
+    };
+    Filter::Range {
+        field: field.to_string(),
+        lower,
+        upper,
+    }
+}
+
+/// Turns a filter group of alternative values, `field:(a OR b)`, into one
+/// `In` filter. Other groups are returned unchanged.
+fn collapse_in(field: &str, query: Query) -> Query {
+    let Query::Bool(bool_query) = &query else {
+        return query;
+    };
+    if !bool_query.must.is_empty() || !bool_query.must_not.is_empty() || !bool_query.filter.is_empty() {
+        return query;
+    }
+    let values: Option<Vec<String>> = bool_query
+        .should
+        .iter()
+        .map(|clause| match clause {
+            Query::Filter(Filter::Term { value, .. }) => Some(value.clone()),
+            _ => None,
+        })
+        .collect();
+    match values {
+        Some(values) => Query::Filter(Filter::In {
+            field: field.to_string(),
+            values,
+        }),
+        None => query,
+    }
+}
@@ -0,0 +1,182 @@
+//! Sort orders for search results.
+//!
+//! ```text
+//! timestamp:desc,rating:asc,_score
+//! ```
+//!
+//! `_score` is the relevance score and `timestamp` the document's
+//! timestamp; any other name is a metadata key. `_score` sorts descending
+//! unless told otherwise, everything else ascending. Documents without a
+//! value for a key sort after those with one, in either order, and ties
+//! left after every key are broken by document ID.
+//!
+//! A [`Cursor`] records where a hit falls in that order, so a later request
+//! can continue right after it.
+
+use crate::mapping::{FieldType, FieldValue};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use uuid::Uuid;
+
+/// Sort key naming the relevance score.
+pub const SCORE: &str = "_score";
+/// Sort key naming `ReviewDocument::timestamp`.
+pub const TIMESTAMP: &str = "timestamp";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SortKey {
+    Score,
+    Timestamp,
+    Field(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    Asc,
+    Desc,
+}
+
+impl SortOrder {
+    /// `ordering` of two values, reversed for descending order.
+    pub fn apply(&self, ordering: Ordering) -> Ordering {
+        match self {
+            SortOrder::Asc => ordering,
+            SortOrder::Desc => ordering.reverse(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SortField {
+    pub key: SortKey,
+    pub order: SortOrder,
+}
+
+/// The default order: most relevant first.
+pub const RELEVANCE: &[SortField] = &[SortField {
+    key: SortKey::Score,
+    order: SortOrder::Desc,
+}];
+
+impl SortField {
+    pub fn new(key: SortKey, order: SortOrder) -> Self {
+        Self { key, order }
+    }
+
+    /// Parses a comma-separated list of `key` or `key:asc|desc`.
+    pub fn parse_list(spec: &str) -> Result<Vec<SortField>, String> {
+        spec.split(',').map(|item| item.trim().parse()).collect()
+    }
+}
+
+impl std::str::FromStr for SortField {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, order) = match s.rsplit_once(':') {
+            Some((name, "asc")) => (name, Some(SortOrder::Asc)),
+            Some((name, "desc")) => (name, Some(SortOrder::Desc)),
+            Some((_, order)) => return Err(format!("sort order must be 'asc' or 'desc', got '{order}'")),
+            None => (s, None),
+        };
+        let key = match name {
+            "" => return Err("expected a field to sort by".to_string()),
+            SCORE => SortKey::Score,
+            TIMESTAMP => SortKey::Timestamp,
+            _ => SortKey::Field(name.to_string()),
+        };
+        let default_order = match key {
+            SortKey::Score => SortOrder::Desc,
+            _ => SortOrder::Asc,
+        };
+        Ok(SortField::new(key, order.unwrap_or(default_order)))
+    }
+}
+
+/// A value a document is sorted by.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum SortValue {
+    Number(f64),
+    String(String),
+}
+
+impl SortValue {
+    /// Reads a metadata value for sorting: as its mapped type if it has
+    /// one, and otherwise as a number if it looks like one. Dates sort as
+    /// their timestamp and booleans as the strings `false` < `true`.
+    pub fn from_metadata(field_type: Option<&FieldType>, value: &str) -> Self {
+        match field_type {
+            Some(field_type) => match field_type.parse(value) {
+                Ok(FieldValue::Number(n)) => SortValue::Number(n),
+                _ => SortValue::String(value.to_string()),
+            },
+            None => match value.trim().parse::<f64>() {
+                Ok(n) if n.is_finite() => SortValue::Number(n),
+                _ => SortValue::String(value.to_string()),
+            },
+        }
+    }
+
+    /// Total order with every number before every string.
+    pub fn compare(&self, other: &SortValue) -> Ordering {
+        match (self, other) {
+            (SortValue::Number(a), SortValue::Number(b)) => a.total_cmp(b),
+            (SortValue::Number(_), SortValue::String(_)) => Ordering::Less,
+            (SortValue::String(_), SortValue::Number(_)) => Ordering::Greater,
+            (SortValue::String(a), SortValue::String(b)) => a.cmp(b),
+        }
+    }
+}
+
+/// Compares two hits' values for `sort`. A missing value sorts after any
+/// present one, whatever the order.
+pub fn compare_values(sort: &[SortField], a: &[Option<SortValue>], b: &[Option<SortValue>]) -> Ordering {
+    for ((field, a), b) in sort.iter().zip(a).zip(b) {
+        let ordering = match (a, b) {
+            (Some(a), Some(b)) => field.order.apply(a.compare(b)),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => Ordering::Equal,
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+    Ordering::Equal
+}
+
+/// A hit's position in a sorted result list: its value for each sort key,
+/// and its ID to break ties.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Cursor {
+    pub values: Vec<Option<SortValue>>,
+    pub id: Uuid,
+}
+
+impl Cursor {
+    /// Where this hit falls relative to `other` in `sort` order.
+    pub fn compare(&self, other: &Cursor, sort: &[SortField]) -> Ordering {
+        compare_values(sort, &self.values, &other.values).then_with(|| self.id.cmp(&other.id))
+    }
+
+    /// An opaque, URL-safe token for this position.
+    pub fn encode(&self) -> String {
+        // Serializing plain values and a UUID cannot fail.
+        URL_SAFE_NO_PAD.encode(serde_json::to_vec(self).expect("cursor serialization"))
+    }
+
+    /// Reads a token made by `encode` for a search sorted by `sort`.
+    pub fn decode(token: &str, sort: &[SortField]) -> Result<Cursor, String> {
+        let invalid = || "invalid cursor".to_string();
+        let bytes = URL_SAFE_NO_PAD.decode(token).map_err(|_| invalid())?;
+        let cursor: Cursor = serde_json::from_slice(&bytes).map_err(|_| invalid())?;
+        if cursor.values.len() != sort.len() {
+            return Err("cursor does not match the sort order".to_string());
+        }
+        Ok(cursor)
+    }
+}
@@ -0,0 +1,32 @@
+use puresearch_core::aggregation::{format_millis, Aggregation, CalendarInterval, Stats};
+
+#[test]
+fn test_calendar_intervals() {
+    // 2024-02-29T13:45:10Z, a Thursday.
+    let millis = 1_709_214_310_000;
+    let start = |interval: CalendarInterval| format_millis(interval.bucket_start(millis));
+
+    assert_eq!(start(CalendarInterval::Hour), "2024-02-29T13:00:00Z");
+    assert_eq!(start(CalendarInterval::Day), "2024-02-29T00:00:00Z");
+    assert_eq!(start(CalendarInterval::Week), "2024-02-26T00:00:00Z");
+    assert_eq!(start(CalendarInterval::Month), "2024-02-01T00:00:00Z");
+    assert_eq!(start(CalendarInterval::Year), "2024-01-01T00:00:00Z");
+}
+
+#[test]
+fn test_aggregation_requests() {
+    let aggregation: Aggregation = serde_json::from_str(r#"{"terms": {"field": "rating"}}"#).unwrap();
+    assert_eq!(aggregation, Aggregation::Terms { field: "rating".to_string(), size: 10 });
+
+    let aggregation: Aggregation = serde_json::from_str(r#"{"date_histogram": {"interval": "week"}}"#).unwrap();
+    assert_eq!(aggregation, Aggregation::DateHistogram { field: None, interval: CalendarInterval::Week });
+
+    let histogram = Aggregation::Histogram { field: "price".to_string(), interval: 0.0 };
+    assert!(histogram.validate().is_err());
+
+    let mut stats = Stats::default();
+    for value in [4.0, 1.0, 7.0] {
+        stats.add(value);
+    }
+    assert_eq!((stats.count, stats.min, stats.max, stats.avg, stats.sum), (3, Some(1.0), Some(7.0), Some(4.0), 12.0));
+}
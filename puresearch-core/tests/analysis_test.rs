@@ -0,0 +1,54 @@
+use puresearch_core::analysis::{
+    Analyzer, AnalyzerConfig, CharFilter, HtmlStripCharFilter, TokenizerKind,
+};
+
+fn terms(config: &AnalyzerConfig, text: &str) -> Vec<String> {
+    config.build().analyze(text).into_iter().map(|token| token.text).collect()
+}
+
+#[test]
+fn test_default_analyzer_stems_and_folds() {
+    let config = AnalyzerConfig::default();
+    assert_eq!(terms(&config, "Running to the Café"), vec!["run", "to", "the", "cafe"]);
+    assert_eq!(terms(&config, "runs"), terms(&config, "running"));
+    assert_eq!(terms(&config, "Straße"), vec!["strass"]);
+}
+
+#[test]
+fn test_offsets_point_into_original_text() {
+    let text = "Naïve <b>design</b>, great value";
+    let tokens = AnalyzerConfig::english().build().analyze(text);
+
+    let surface: Vec<&str> = tokens.iter().map(|token| &text[token.start..token.end]).collect();
+    assert_eq!(surface, vec!["Naïve", "design", "great", "value"]);
+    assert_eq!(tokens[0].text, "naiv");
+}
+
+#[test]
+fn test_stopwords_keep_position_gaps() {
+    let tokens = AnalyzerConfig::english().build().analyze("it is not worth the price");
+    let positions: Vec<(String, u32)> = tokens.into_iter().map(|token| (token.text, token.position)).collect();
+    assert_eq!(
+        positions,
+        vec![("not".to_string(), 2), ("worth".to_string(), 3), ("price".to_string(), 5)]
+    );
+}
+
+#[test]
+fn test_html_strip_preserves_length() {
+    let text = "<p>Fish &amp; chips &#233;&lt;3</p>";
+    let stripped = HtmlStripCharFilter.filter(text);
+    assert_eq!(stripped.len(), text.len());
+    assert_eq!(stripped.split_whitespace().collect::<Vec<_>>(), vec!["Fish", "&", "chips", "é", "<", "3"]);
+    assert_eq!(HtmlStripCharFilter.filter("3 < 5 & 7 > 2"), "3 < 5 & 7 > 2");
+}
+
+#[test]
+fn test_keyword_tokenizer() {
+    let config = AnalyzerConfig {
+        tokenizer: TokenizerKind::Keyword,
+        stemmer: None,
+        ..AnalyzerConfig::default()
+    };
+    assert_eq!(terms(&config, "SKU-123 Blue"), vec!["sku-123 blue"]);
+}
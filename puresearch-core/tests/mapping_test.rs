@@ -0,0 +1,43 @@
+use puresearch_core::mapping::{validate, FieldType, FieldValue, MappingError, Mappings};
+use std::collections::HashMap;
+
+#[test]
+fn test_field_types_parse_values() {
+    assert_eq!(FieldType::Integer.parse("42"), Ok(FieldValue::Number(42.0)));
+    assert!(FieldType::Integer.parse("4.5").is_err());
+    assert_eq!(FieldType::Float.parse("4.5"), Ok(FieldValue::Number(4.5)));
+    assert!(FieldType::Float.parse("NaN").is_err());
+    assert_eq!(FieldType::Boolean.parse("true"), Ok(FieldValue::Boolean(true)));
+    assert!(FieldType::Boolean.parse("yes").is_err());
+    assert_eq!(FieldType::Keyword.parse("B00X"), Ok(FieldValue::String("B00X".to_string())));
+
+    // Dates are milliseconds since the epoch, with or without a time.
+    assert_eq!(FieldType::Date.parse("1970-01-02"), Ok(FieldValue::Number(86_400_000.0)));
+    assert_eq!(FieldType::Date.parse("1970-01-02T01:00:00+01:00"), Ok(FieldValue::Number(86_400_000.0)));
+    assert!(FieldType::Date.parse("yesterday").is_err());
+}
+
+#[test]
+fn test_validate_reports_first_invalid_field() {
+    let mappings = Mappings::from([
+        ("rating".to_string(), FieldType::Integer),
+        ("verified".to_string(), FieldType::Boolean),
+    ]);
+    let mut metadata = HashMap::from([
+        ("rating".to_string(), "five".to_string()),
+        ("verified".to_string(), "maybe".to_string()),
+        ("source".to_string(), "anything".to_string()),
+    ]);
+
+    assert_eq!(
+        validate(&mappings, &metadata),
+        Err(MappingError {
+            field: "rating".to_string(),
+            message: "expected an integer, got 'five'".to_string(),
+        })
+    );
+    metadata.insert("rating".to_string(), "5".to_string());
+    assert_eq!(validate(&mappings, &metadata).unwrap_err().field, "verified");
+    metadata.remove("verified");
+    assert!(validate(&mappings, &metadata).is_ok());
+}
@@ -0,0 +1,194 @@
+use puresearch_core::query::{BoolQuery, Filter, Fuzziness, Operator, ParseError, Query, QueryParser, MAX_NESTING};
+use std::ops::Bound;
+
+fn field_term(field: &str, text: &str) -> Query {
+    Query::Term {
+        field: Some(field.to_string()),
+        text: text.to_string(),
+    }
+}
+
+#[test]
+fn test_parse_terms_and_phrases() {
+    assert_eq!(Query::parse("battery").unwrap(), Query::term("battery"));
+    assert_eq!(
+        Query::parse(r#"great "battery life"~2 value"#).unwrap(),
+        Query::Bool(BoolQuery {
+            must: vec![Query::term("great"), Query::phrase("battery life", 2), Query::term("value")],
+            ..BoolQuery::default()
+        })
+    );
+    assert_eq!(Query::parse("   ").unwrap(), Query::Bool(BoolQuery::default()));
+}
+
+#[test]
+fn test_parse_operators_and_precedence() {
+    assert_eq!(
+        Query::parse("a OR b AND c").unwrap(),
+        Query::Bool(BoolQuery {
+            should: vec![
+                Query::term("a"),
+                Query::Bool(BoolQuery {
+                    must: vec![Query::term("b"), Query::term("c")],
+                    ..BoolQuery::default()
+                }),
+            ],
+            ..BoolQuery::default()
+        })
+    );
+    assert_eq!(
+        Query::parse("(a || b) && NOT c").unwrap(),
+        Query::Bool(BoolQuery {
+            must: vec![Query::Bool(BoolQuery {
+                should: vec![Query::term("a"), Query::term("b")],
+                ..BoolQuery::default()
+            })],
+            must_not: vec![Query::term("c")],
+            ..BoolQuery::default()
+        })
+    );
+    assert_eq!(
+        Query::parse("-refund").unwrap(),
+        Query::Bool(BoolQuery {
+            must: vec![Query::MatchAll],
+            must_not: vec![Query::term("refund")],
+            ..BoolQuery::default()
+        })
+    );
+    assert_eq!(
+        QueryParser::new(Operator::Or).parse("+battery life -cable").unwrap(),
+        Query::Bool(BoolQuery {
+            must: vec![Query::term("battery")],
+            should: vec![Query::term("life")],
+            must_not: vec![Query::term("cable")],
+            ..BoolQuery::default()
+        })
+    );
+}
+
+#[test]
+fn test_parse_field_clauses() {
+    assert_eq!(Query::parse("source:amazon").unwrap(), field_term("source", "amazon"));
+    assert_eq!(
+        Query::parse(r#"title:"great value""#).unwrap(),
+        Query::Phrase {
+            field: Some("title".to_string()),
+            text: "great value".to_string(),
+            slop: 0,
+        }
+    );
+    assert_eq!(
+        Query::parse("source:(amazon OR ebay)").unwrap(),
+        Query::Bool(BoolQuery {
+            should: vec![field_term("source", "amazon"), field_term("source", "ebay")],
+            ..BoolQuery::default()
+        })
+    );
+    assert_eq!(Query::parse(r"wi-fi note\:s").unwrap(), Query::Bool(BoolQuery {
+        must: vec![Query::term("wi-fi"), Query::term("note:s")],
+        ..BoolQuery::default()
+    }));
+}
+
+#[test]
+fn test_parse_errors_report_position() {
+    let error = |input: &str| Query::parse(input).unwrap_err();
+
+    assert_eq!(error("battery AND"), ParseError { position: 11, message: "expected a search term".to_string() });
+    assert_eq!(error("(battery life").position, 0);
+    assert_eq!(error("battery life)").position, 12);
+    assert_eq!(error(r#"great "battery"#).position, 6);
+    assert_eq!(error("source: AND x").position, 8);
+    assert_eq!(error("café OR").position, 7);
+}
+
+#[test]
+fn test_parse_rejects_deeply_nested_groups() {
+    let nested = |depth: usize| format!("{}battery{}", "(".repeat(depth), ")".repeat(depth));
+
+    assert_eq!(Query::parse(&nested(MAX_NESTING)).unwrap(), Query::term("battery"));
+    let error = Query::parse(&nested(MAX_NESTING + 1)).unwrap_err();
+    assert_eq!(error.position, MAX_NESTING);
+    assert!(error.message.contains("nested"), "{error}");
+
+    // Far past the limit, as a URL could carry, fails the same way rather
+    // than overflowing the stack.
+    let deep = "(".repeat(100_000);
+    assert_eq!(Query::parse(&deep).unwrap_err().position, MAX_NESTING);
+    assert_eq!(QueryParser::default().parse_filter(&deep).unwrap_err().position, MAX_NESTING);
+}
+
+#[test]
+fn test_parse_fuzzy_terms() {
+    let fuzzy = |text: &str, fuzziness| Query::Fuzzy {
+        field: None,
+        text: text.to_string(),
+        fuzziness,
+    };
+
+    assert_eq!(Query::parse("battary~1").unwrap(), fuzzy("battary", Fuzziness::Distance(1)));
+    assert_eq!(Query::parse("battary~").unwrap(), fuzzy("battary", Fuzziness::Auto));
+    assert_eq!(Query::parse("battary~3").unwrap_err().position, 8);
+    assert_eq!(
+        QueryParser::default()
+            .with_fuzziness(Fuzziness::Auto)
+            .parse("recieved \"battery life\"")
+            .unwrap(),
+        Query::Bool(BoolQuery {
+            must: vec![fuzzy("recieved", Fuzziness::Auto), Query::phrase("battery life", 0)],
+            ..BoolQuery::default()
+        })
+    );
+    assert_eq!(Fuzziness::Auto.distance_for("ok"), 0);
+    assert_eq!(Fuzziness::Auto.distance_for("cable"), 1);
+    assert_eq!(Fuzziness::Auto.distance_for("battery"), 2);
+    assert!("3".parse::<Fuzziness>().is_err());
+}
+
+#[test]
+fn test_parse_filters() {
+    let range = |lower: Bound<&str>, upper: Bound<&str>| {
+        Query::Filter(Filter::Range {
+            field: "rating".to_string(),
+            lower: lower.map(str::to_string),
+            upper: upper.map(str::to_string),
+        })
+    };
+
+    assert_eq!(Query::parse("rating:>=4").unwrap(), range(Bound::Included("4"), Bound::Unbounded));
+    assert_eq!(Query::parse("rating:<2").unwrap(), range(Bound::Unbounded, Bound::Excluded("2")));
+    assert_eq!(Query::parse("rating:[2 TO 5}").unwrap(), range(Bound::Included("2"), Bound::Excluded("5")));
+    assert_eq!(Query::parse("rating:{* TO 3]").unwrap(), range(Bound::Unbounded, Bound::Included("3")));
+    assert_eq!(
+        Query::parse("battery _exists_:verified").unwrap(),
+        Query::Bool(BoolQuery {
+            must: vec![
+                Query::term("battery"),
+                Query::Filter(Filter::Exists { field: "verified".to_string() }),
+            ],
+            ..BoolQuery::default()
+        })
+    );
+    assert_eq!(Query::parse("rating:>=").unwrap_err().position, 7);
+    assert_eq!(Query::parse("rating:[2 5]").unwrap_err().position, 7);
+
+    // In a filter, field values are exact and a group of values is a list.
+    let parser = QueryParser::default();
+    assert_eq!(
+        parser.parse_filter(r#"product_id:B00X source:(amazon OR "web shop")"#).unwrap(),
+        Query::Bool(BoolQuery {
+            must: vec![
+                Query::Filter(Filter::Term {
+                    field: "product_id".to_string(),
+                    value: "B00X".to_string(),
+                }),
+                Query::Filter(Filter::In {
+                    field: "source".to_string(),
+                    values: vec!["amazon".to_string(), "web shop".to_string()],
+                }),
+            ],
+            ..BoolQuery::default()
+        })
+    );
+    assert_eq!(parser.parse_filter("rating:4 battery").unwrap_err().position, 9);
+}
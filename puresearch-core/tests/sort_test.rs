@@ -0,0 +1,63 @@
+use puresearch_core::mapping::FieldType;
+use puresearch_core::sort::{Cursor, SortField, SortKey, SortOrder, SortValue};
+use std::cmp::Ordering;
+use uuid::Uuid;
+
+#[test]
+fn test_parse_sort_list() {
+    assert_eq!(
+        SortField::parse_list("timestamp:desc, rating:asc,_score,product_id").unwrap(),
+        vec![
+            SortField::new(SortKey::Timestamp, SortOrder::Desc),
+            SortField::new(SortKey::Field("rating".to_string()), SortOrder::Asc),
+            SortField::new(SortKey::Score, SortOrder::Desc),
+            SortField::new(SortKey::Field("product_id".to_string()), SortOrder::Asc),
+        ]
+    );
+    assert_eq!(
+        SortField::parse_list("_score:asc").unwrap(),
+        vec![SortField::new(SortKey::Score, SortOrder::Asc)]
+    );
+    assert!(SortField::parse_list("rating:up").is_err());
+    assert!(SortField::parse_list("rating,").is_err());
+}
+
+#[test]
+fn test_sort_values_follow_mappings() {
+    let keyword = Some(&FieldType::Keyword);
+    let ten = SortValue::from_metadata(None, "10");
+    let nine = SortValue::from_metadata(None, "9");
+    assert_eq!(nine.compare(&ten), Ordering::Less);
+    assert_eq!(
+        SortValue::from_metadata(keyword, "9").compare(&SortValue::from_metadata(keyword, "10")),
+        Ordering::Greater
+    );
+    assert_eq!(
+        SortValue::from_metadata(Some(&FieldType::Date), "2024-01-01"),
+        SortValue::Number(1_704_067_200_000.0)
+    );
+    // Numbers sort before strings in a column that mixes them.
+    assert_eq!(ten.compare(&SortValue::from_metadata(None, "n/a")), Ordering::Less);
+}
+
+#[test]
+fn test_cursor_round_trip() {
+    let sort = SortField::parse_list("rating:desc,_score").unwrap();
+    let cursor = Cursor {
+        values: vec![None, Some(SortValue::Number(1.25))],
+        id: Uuid::new_v4(),
+    };
+    let token = cursor.encode();
+    assert!(token.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'));
+    assert_eq!(Cursor::decode(&token, &sort), Ok(cursor.clone()));
+
+    assert_eq!(Cursor::decode(&token, &sort[..1]), Err("cursor does not match the sort order".to_string()));
+    assert_eq!(Cursor::decode("not a cursor", &sort), Err("invalid cursor".to_string()));
+
+    // A present value comes before a missing one even in descending order.
+    let rated = Cursor {
+        values: vec![Some(SortValue::Number(1.0)), Some(SortValue::Number(0.5))],
+        id: cursor.id,
+    };
+    assert_eq!(rated.compare(&cursor, &sort), Ordering::Less);
+}
@@ -0,0 +1,79 @@
+use crate::doc_values::DocValues;
+use crate::metadata_index::MetadataIndex;
+use puresearch_core::aggregation::{format_millis, Aggregation, AggregationResult, Bucket, Stats};
+use puresearch_core::sort::SortValue;
+use puresearch_core::ScoredDocument;
+use std::collections::BTreeMap;
+
+/// Computes `aggregation` over `hits`. Values come from the metadata index
+/// and doc values, so no document is loaded.
+pub fn aggregate(
+    metadata: &MetadataIndex,
+    doc_values: &DocValues,
+    hits: &[ScoredDocument],
+    aggregation: &Aggregation,
+) -> AggregationResult {
+    match aggregation {
+        Aggregation::Terms { field, size } => {
+            let mut buckets: Vec<Bucket> = metadata
+                .value_counts(field, hits.iter().map(|hit| &hit.id))
+                .into_iter()
+                .map(|(key, doc_count)| Bucket {
+                    key,
+                    key_as_string: None,
+                    doc_count,
+                })
+                .collect();
+            buckets.sort_by(|a, b| b.doc_count.cmp(&a.doc_count).then_with(|| a.key.compare(&b.key)));
+            buckets.truncate(*size);
+            AggregationResult::Buckets { buckets }
+        }
+        Aggregation::Histogram { field, interval } => {
+            let mut counts: BTreeMap<i64, u64> = BTreeMap::new();
+            for hit in hits {
+                if let Some(value) = doc_values.number(&hit.id, field) {
+                    *counts.entry((value / interval).floor() as i64).or_default() += 1;
+                }
+            }
+            let buckets = counts
+                .into_iter()
+                .map(|(bucket, doc_count)| Bucket {
+                    key: SortValue::Number(bucket as f64 * interval),
+                    key_as_string: None,
+                    doc_count,
+                })
+                .collect();
+            AggregationResult::Buckets { buckets }
+        }
+        Aggregation::DateHistogram { field, interval } => {
+            let mut counts: BTreeMap<i64, u64> = BTreeMap::new();
+            for hit in hits {
+                let millis = match field {
+                    Some(field) => doc_values.number(&hit.id, field).map(|millis| millis as i64),
+                    None => doc_values.timestamp(&hit.id).map(|secs| secs as i64 * 1000),
+                };
+                if let Some(millis) = millis {
+                    *counts.entry(interval.bucket_start(millis)).or_default() += 1;
+                }
+            }
+            let buckets = counts
+                .into_iter()
+                .map(|(start, doc_count)| Bucket {
+                    key: SortValue::Number(start as f64),
+                    key_as_string: Some(format_millis(start)),
+                    doc_count,
+                })
+                .collect();
+            AggregationResult::Buckets { buckets }
+        }
+        Aggregation::Stats { field } => {
+            let mut stats = Stats::default();
+            for hit in hits {
+                if let Some(value) = doc_values.number(&hit.id, field) {
+                    stats.add(value);
+                }
+            }
+            AggregationResult::Stats(stats)
+        }
+    }
+}
@@ -0,0 +1,275 @@
+//! Checkpoints: the stored documents and indices as of a WAL position,
+//! persisted in segment files so that startup only replays the WAL records
+//! written after it.
+//!
+//! [`MANIFEST`] in the data directory names the current checkpoint. It is
+//! replaced atomically once the new checkpoint's segments are synced, so a
+//! crash part way through a checkpoint leaves the previous one in effect.
+//!
+//! Document bodies already live in the document store's segments, so a
+//! checkpoint records where each document is and how far each of those
+//! segments reached, along with each segment's deletion bitmap. Version 1
+//! checkpoints held the documents themselves, and are still read.
+//!
+//! A merge of document segments does not take a checkpoint. It is recorded
+//! in [`MERGES`] instead, as the merged segment and the segments it
+//! replaced, and recovery replays it on top of the checkpoint. The next
+//! checkpoint covers it and removes the file.
+
+use crate::doc_store::{DocumentStore, Location, SegmentState};
+use crate::segment::SegmentFile;
+use anyhow::{bail, Context, Result};
+use puresearch_core::{Index, ReviewDocument};
+use roaring::RoaringTreemap;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+/// File naming the current checkpoint.
+pub const MANIFEST: &str = "checkpoint.json";
+/// Directory holding checkpoint segments.
+pub const DIR: &str = "checkpoints";
+/// File listing the merges installed since the current checkpoint.
+pub const MERGES: &str = "merges.json";
+
+/// Format of checkpoints written by this build.
+pub const VERSION: u32 = 2;
+
+/// Records are written to segments in chunks of about this many bytes.
+const WRITE_CHUNK: usize = 1 << 20;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    #[serde(default = "first_version")]
+    pub version: u32,
+    /// LSN of the first WAL record the checkpoint does not include.
+    pub lsn: u64,
+    /// Segment of document locations, or of the documents themselves in a
+    /// version 1 checkpoint, relative to the data directory.
+    pub documents: String,
+    /// Segment of indices, relative to the data directory.
+    pub indices: String,
+    /// How far each document segment reached.
+    #[serde(default)]
+    pub segments: Vec<SegmentState>,
+    /// Segment of deletion bitmaps, one per document segment with dead
+    /// records.
+    #[serde(default)]
+    pub deletes: Option<String>,
+    /// Document segment being appended to. Absent from older checkpoints,
+    /// whose writes go to a new segment instead.
+    #[serde(default)]
+    pub active: Option<u32>,
+}
+
+/// A merge of document segments installed since a checkpoint. The copied
+/// records are in its segment, so only the segments involved are recorded.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MergeRecord {
+    /// The merged segment, or `None` if nothing in the sources was live.
+    pub segment: Option<SegmentState>,
+    /// The segments it replaced.
+    pub sources: Vec<u32>,
+}
+
+/// Contents of [`MERGES`].
+#[derive(Serialize, Deserialize)]
+struct MergeLog {
+    /// LSN of the checkpoint the merges were installed after.
+    lsn: u64,
+    merges: Vec<MergeRecord>,
+}
+
+fn first_version() -> u32 {
+    1
+}
+
+/// The documents of a checkpoint.
+pub enum CheckpointDocuments {
+    /// Where each document is in the document store.
+    Locations(Vec<(Uuid, Location)>),
+    /// The documents themselves, from a version 1 checkpoint.
+    Bodies(Vec<ReviewDocument>),
+}
+
+/// A checkpoint read back from disk.
+pub struct Checkpoint {
+    pub manifest: Manifest,
+    pub documents: CheckpointDocuments,
+    pub indices: Vec<Index>,
+    /// Dead records of each document segment, by offset.
+    pub deletes: Vec<(u32, RoaringTreemap)>,
+}
+
+/// Persists the documents in `store` and `indices` as the state after every
+/// WAL record before `lsn`, and makes it the current checkpoint. Segments
+/// of older checkpoints are removed.
+pub fn write<'a>(
+    data_dir: &Path,
+    lsn: u64,
+    store: &mut DocumentStore,
+    indices: impl Iterator<Item = &'a Index>,
+) -> Result<Manifest> {
+    std::fs::create_dir_all(data_dir.join(DIR))?;
+    let manifest = Manifest {
+        version: VERSION,
+        lsn,
+        documents: format!("{DIR}/{lsn}.documents"),
+        indices: format!("{DIR}/{lsn}.indices"),
+        segments: store.sync()?,
+        deletes: Some(format!("{DIR}/{lsn}.deletes")),
+        active: Some(store.active()),
+    };
+    write_segment(&data_dir.join(&manifest.documents), store.locations())?;
+    write_segment(&data_dir.join(&manifest.indices), indices)?;
+    let mut deletes = Vec::new();
+    for (id, bitmap) in store.deletes() {
+        let mut bytes = Vec::with_capacity(bitmap.serialized_size());
+        bitmap.serialize_into(&mut bytes)?;
+        deletes.push((id, bytes));
+    }
+    write_segment(&data_dir.join(manifest.deletes.as_ref().unwrap()), deletes.into_iter())?;
+    File::open(data_dir.join(DIR))?.sync_all()?;
+
+    replace_synced(data_dir, MANIFEST, &serde_json::to_vec_pretty(&manifest)?)?;
+
+    // Merges recorded against the previous checkpoint are part of this one.
+    let merges_path = data_dir.join(MERGES);
+    if merges_path.exists() {
+        std::fs::remove_file(merges_path)?;
+    }
+    remove_stale(data_dir, &manifest)?;
+    Ok(manifest)
+}
+
+/// Records `merge` as installed after the checkpoint at `lsn`.
+pub fn record_merge(data_dir: &Path, lsn: u64, merge: MergeRecord) -> Result<()> {
+    let mut merges = load_merges(data_dir, lsn)?;
+    merges.push(merge);
+    let log = MergeLog { lsn, merges };
+    replace_synced(data_dir, MERGES, &serde_json::to_vec_pretty(&log)?)
+}
+
+/// The merges installed after the checkpoint at `lsn`, oldest first.
+pub fn load_merges(data_dir: &Path, lsn: u64) -> Result<Vec<MergeRecord>> {
+    let path = data_dir.join(MERGES);
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let log: MergeLog = serde_json::from_slice(&std::fs::read(&path)?)
+        .with_context(|| format!("invalid merge log {}", path.display()))?;
+    // Merges recorded against an earlier checkpoint, left by a crash before
+    // the file was removed, are already part of this one.
+    if log.lsn != lsn {
+        return Ok(Vec::new());
+    }
+    Ok(log.merges)
+}
+
+/// The document segments a checkpoint's `segments` leave once `merges`
+/// are replayed.
+pub fn merged_segments(segments: &[SegmentState], merges: &[MergeRecord]) -> Vec<SegmentState> {
+    let mut segments = segments.to_vec();
+    for merge in merges {
+        segments.retain(|segment| !merge.sources.contains(&segment.id));
+        segments.extend(merge.segment);
+    }
+    segments
+}
+
+/// Atomically replaces the file `name` in `data_dir` with `bytes`.
+fn replace_synced(data_dir: &Path, name: &str, bytes: &[u8]) -> Result<()> {
+    let path = data_dir.join(name);
+    let new_path = path.with_extension("new");
+    let mut file = File::create(&new_path)?;
+    file.write_all(bytes)?;
+    file.sync_all()?;
+    std::fs::rename(&new_path, &path)?;
+    File::open(data_dir)?.sync_all()?;
+    Ok(())
+}
+
+/// Writes `items` to a new segment at `path`. A checkpoint taken again at
+/// the same LSN finds the current checkpoint's segment there, so the new
+/// one is written alongside it and renamed over it once synced; the caller
+/// syncs the directory.
+fn write_segment<T: Serialize>(path: &Path, items: impl Iterator<Item = T>) -> Result<()> {
+    let mut new_path = path.as_os_str().to_owned();
+    new_path.push(".new");
+    let new_path = PathBuf::from(new_path);
+    // Left over from a write that never completed.
+    if new_path.exists() {
+        std::fs::remove_file(&new_path)?;
+    }
+    let mut segment = SegmentFile::create(&new_path)?;
+    let mut chunk = Vec::new();
+    for item in items {
+        SegmentFile::frame(&bincode::serialize(&item)?, &mut chunk);
+        if chunk.len() >= WRITE_CHUNK {
+            segment.write(&chunk)?;
+            chunk.clear();
+        }
+    }
+    if !chunk.is_empty() {
+        segment.write(&chunk)?;
+    }
+    segment.sync()?;
+    std::fs::rename(&new_path, path)?;
+    Ok(())
+}
+
+/// Reads the current checkpoint, if one has been taken.
+pub fn load(data_dir: &Path) -> Result<Option<Checkpoint>> {
+    let manifest_path = data_dir.join(MANIFEST);
+    if !manifest_path.exists() {
+        return Ok(None);
+    }
+    let manifest: Manifest = serde_json::from_slice(&std::fs::read(&manifest_path)?)
+        .with_context(|| format!("invalid checkpoint manifest {}", manifest_path.display()))?;
+    let documents_path = data_dir.join(&manifest.documents);
+    let documents = match manifest.version {
+        1 => CheckpointDocuments::Bodies(read_segment(&documents_path)?),
+        VERSION => CheckpointDocuments::Locations(read_segment(&documents_path)?),
+        version => bail!("checkpoint version {version} is not supported (expected {VERSION})"),
+    };
+    let indices = read_segment(&data_dir.join(&manifest.indices))?;
+    let mut deletes = Vec::new();
+    if let Some(path) = &manifest.deletes {
+        for (id, bytes) in read_segment::<(u32, Vec<u8>)>(&data_dir.join(path))? {
+            deletes.push((id, RoaringTreemap::deserialize_from(&bytes[..])?));
+        }
+    }
+    Ok(Some(Checkpoint {
+        manifest,
+        documents,
+        indices,
+        deletes,
+    }))
+}
+
+fn read_segment<T: for<'de> Deserialize<'de>>(path: &Path) -> Result<Vec<T>> {
+    let segment =
+        SegmentFile::open(path).with_context(|| format!("missing checkpoint segment {}", path.display()))?;
+    let records = segment
+        .records()
+        .with_context(|| format!("corrupt checkpoint segment {}", path.display()))?;
+    records
+        .iter()
+        .map(|record| Ok(bincode::deserialize(record)?))
+        .collect()
+}
+
+/// Removes checkpoint segments other than those of `manifest`.
+fn remove_stale(data_dir: &Path, manifest: &Manifest) -> Result<()> {
+    let mut current = vec![data_dir.join(&manifest.documents), data_dir.join(&manifest.indices)];
+    current.extend(manifest.deletes.iter().map(|path| data_dir.join(path)));
+    for entry in std::fs::read_dir(data_dir.join(DIR))? {
+        let path = entry?.path();
+        if !current.contains(&path) {
+            std::fs::remove_file(&path)?;
+        }
+    }
+    Ok(())
+}
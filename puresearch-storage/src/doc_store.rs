@@ -0,0 +1,548 @@
+use crate::checkpoint::MergeRecord;
+use crate::merge::{MergeStats, SegmentStats};
+use crate::segment::SegmentFile;
+use anyhow::{bail, Context, Result};
+use puresearch_core::ReviewDocument;
+use roaring::RoaringTreemap;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+/// Directory holding document segments, relative to the data directory.
+pub const DIR: &str = "segments";
+
+/// Length of the length and checksum in front of each record.
+const FRAME_HEADER_LEN: usize = 8;
+
+/// Live records are copied into a merged segment in chunks of about this
+/// many bytes.
+const MERGE_CHUNK: usize = 1024 * 1024;
+
+/// Where a version of a document is stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Location {
+    pub segment: u32,
+    /// Offset of the record's frame within the segment.
+    pub offset: u64,
+    /// Length of the record's payload.
+    pub len: u32,
+}
+
+impl Location {
+    fn frame_len(&self) -> u64 {
+        FRAME_HEADER_LEN as u64 + self.len as u64
+    }
+}
+
+/// A segment and how many bytes of it a checkpoint covers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SegmentState {
+    pub id: u32,
+    pub len: u64,
+}
+
+/// Document bodies, kept in append-only segment files instead of on the
+/// heap.
+///
+/// Every stored version of a document is appended to the active segment as
+/// a checksummed bincode record, and a map from ID to [`Location`] points at
+/// the latest one. Reads go through each segment's memory map, so the
+/// working set is bounded by the page cache. Once the active segment
+/// reaches `segment_bytes` a new one is started.
+///
+/// Replaced and deleted versions stay behind as dead records, marked in a
+/// deletion bitmap per segment. Deleting a document only sets its bit, so
+/// its location stays as a tombstone, which readers skip, until a merge
+/// drops the record. Checkpoints persist the bitmaps with the locations.
+///
+/// Segments are not synced as they are written: the WAL is what makes a
+/// write durable, and a checkpoint syncs them before recording how far they
+/// reach.
+///
+/// Sealed segments can be merged to reclaim their dead records. The live
+/// records are copied into a new segment with [`DocumentStore::prepare_merge`],
+/// which only reads the store, and [`DocumentStore::install_merge`] then
+/// points their documents at the copies. The merged segments' files stay
+/// until the merge is recorded, when [`DocumentStore::remove_retired`]
+/// deletes them. A merged segment is never appended to.
+pub struct DocumentStore {
+    dir: PathBuf,
+    segments: BTreeMap<u32, SegmentFile>,
+    active: u32,
+    /// Latest version of each document, including deleted documents whose
+    /// records have not been merged away.
+    locations: HashMap<Uuid, Location>,
+    /// Offsets of the dead records in each segment.
+    deleted: HashMap<u32, RoaringTreemap>,
+    /// Bytes of live records in each segment.
+    live_bytes: HashMap<u32, u64>,
+    /// Files of merged segments, to delete once the merge is recorded.
+    retired: Vec<PathBuf>,
+    segment_bytes: u64,
+}
+
+/// Live records of some segments, copied into a new segment file that is
+/// not part of the store yet.
+pub struct MergedSegment {
+    path: PathBuf,
+    file: SegmentFile,
+    sources: Vec<u32>,
+    /// Each copied document, where it was, and the offset of its copy.
+    moves: Vec<(Uuid, Location, u64)>,
+}
+
+impl MergedSegment {
+    /// The segments merged.
+    pub fn sources(&self) -> &[u32] {
+        &self.sources
+    }
+}
+
+impl DocumentStore {
+    /// Opens the segments in `dir` as of a checkpoint. Each segment in
+    /// `checkpointed` is cut back to the length it had then, and any other
+    /// segment, written since, is removed: the WAL replays what it held.
+    /// Writes go on in the segment `active`, or in a new one if it is not
+    /// given or was merged away.
+    pub fn open<P: AsRef<Path>>(
+        dir: P,
+        checkpointed: &[SegmentState],
+        active: Option<u32>,
+        segment_bytes: u64,
+    ) -> Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        std::fs::create_dir_all(&dir)?;
+        let lengths: HashMap<u32, u64> = checkpointed.iter().map(|state| (state.id, state.len)).collect();
+
+        let mut segments = BTreeMap::new();
+        for entry in std::fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|extension| extension == "merge") {
+                // Left by a merge that never finished.
+                std::fs::remove_file(&path)?;
+                continue;
+            }
+            let Some(id) = segment_id(&path) else {
+                continue;
+            };
+            match lengths.get(&id) {
+                Some(&len) => {
+                    let mut segment = SegmentFile::open(&path)?;
+                    if segment.len() as u64 > len {
+                        segment.truncate(len as usize)?;
+                    }
+                    segments.insert(id, segment);
+                }
+                None => std::fs::remove_file(&path)?,
+            }
+        }
+        if let Some(missing) = lengths.keys().find(|id| !segments.contains_key(id)) {
+            bail!("document segment {} is missing", segment_path(&dir, *missing).display());
+        }
+
+        let active = match active {
+            Some(id) if segments.contains_key(&id) => id,
+            _ => {
+                let id = segments.keys().next_back().map_or(0, |id| id + 1);
+                segments.insert(id, SegmentFile::create(segment_path(&dir, id))?);
+                id
+            }
+        };
+        Ok(Self {
+            dir,
+            segments,
+            active,
+            locations: HashMap::new(),
+            deleted: HashMap::new(),
+            live_bytes: HashMap::new(),
+            retired: Vec::new(),
+            segment_bytes,
+        })
+    }
+
+    /// Records where a checkpointed document is stored.
+    pub fn restore(&mut self, id: Uuid, location: Location) {
+        self.set_location(id, location);
+    }
+
+    /// Restores a checkpointed segment's deletion bitmap.
+    pub fn restore_deletes(&mut self, segment: u32, deleted: RoaringTreemap) {
+        if self.segments.contains_key(&segment) {
+            self.deleted.insert(segment, deleted);
+        }
+    }
+
+    /// Replays `merge`, installed after the checkpoint the store was
+    /// restored from. Documents whose checkpointed version was in its
+    /// sources point at their copy in the merged segment, or are dropped if
+    /// there is none, as they were dead by the merge. Reads the whole merged
+    /// segment, to find which document each copy belongs to.
+    pub fn replay_merge(&mut self, merge: &MergeRecord) -> Result<()> {
+        if let Some(state) = merge.segment {
+            let segment = self
+                .segments
+                .get(&state.id)
+                .with_context(|| format!("document segment {} is missing", state.id))?;
+            let mut copies = Vec::new();
+            let mut offset = 0;
+            while offset < segment.len() as u64 {
+                let header = segment.read_at(offset as usize, FRAME_HEADER_LEN)?;
+                let location = Location {
+                    segment: state.id,
+                    offset,
+                    len: u32::from_le_bytes(header[..4].try_into().unwrap()),
+                };
+                let frame = segment.read_at(offset as usize, location.frame_len() as usize)?;
+                check_frame(&frame, &location)?;
+                let doc: ReviewDocument = bincode::deserialize(&frame[FRAME_HEADER_LEN..])?;
+                copies.push((doc.id, location));
+                offset += location.frame_len();
+            }
+            for (id, location) in copies {
+                if self.locations.get(&id).is_some_and(|old| merge.sources.contains(&old.segment)) {
+                    self.set_location(id, location);
+                }
+            }
+        }
+        self.locations.retain(|_, location| !merge.sources.contains(&location.segment));
+        for id in &merge.sources {
+            self.deleted.remove(id);
+            self.live_bytes.remove(id);
+        }
+        Ok(())
+    }
+
+    /// Points `id` at `location`, marking the version it replaces dead.
+    fn set_location(&mut self, id: Uuid, location: Location) {
+        *self.live_bytes.entry(location.segment).or_default() += location.frame_len();
+        if let Some(old) = self.locations.insert(id, location) {
+            self.mark_deleted(&old);
+        }
+    }
+
+    /// Sets the deletion bit of the record at `location`, returning whether
+    /// it was live.
+    fn mark_deleted(&mut self, location: &Location) -> bool {
+        let newly_deleted = self.deleted.entry(location.segment).or_default().insert(location.offset);
+        if newly_deleted {
+            if let Some(live) = self.live_bytes.get_mut(&location.segment) {
+                *live -= location.frame_len();
+            }
+        }
+        newly_deleted
+    }
+
+    fn is_deleted(&self, location: &Location) -> bool {
+        self.deleted
+            .get(&location.segment)
+            .is_some_and(|deleted| deleted.contains(location.offset))
+    }
+
+    /// Location of the live version of `id`.
+    fn live(&self, id: &Uuid) -> Option<&Location> {
+        self.locations.get(id).filter(|location| !self.is_deleted(location))
+    }
+
+    pub fn get(&self, id: &Uuid) -> Result<Option<ReviewDocument>> {
+        match self.live(id) {
+            Some(location) => self.read(location).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// The latest version of `id`, even if it has been deleted, as long as
+    /// no merge has dropped it yet.
+    pub fn latest(&self, id: &Uuid) -> Result<Option<ReviewDocument>> {
+        match self.locations.get(id) {
+            Some(location) => self.read(location).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn read(&self, location: &Location) -> Result<ReviewDocument> {
+        let segment = self
+            .segments
+            .get(&location.segment)
+            .with_context(|| format!("document segment {} is missing", location.segment))?;
+        let frame = segment.read_at(location.offset as usize, location.frame_len() as usize)?;
+        check_frame(&frame, location)?;
+        Ok(bincode::deserialize(&frame[FRAME_HEADER_LEN..])?)
+    }
+
+    pub fn contains(&self, id: &Uuid) -> bool {
+        self.live(id).is_some()
+    }
+
+    /// Appends `doc` as its latest version.
+    pub fn put(&mut self, doc: &ReviewDocument) -> Result<()> {
+        if self.segments[&self.active].len() as u64 >= self.segment_bytes {
+            self.roll()?;
+        }
+        let payload = bincode::serialize(doc)?;
+        let mut frame = Vec::with_capacity(FRAME_HEADER_LEN + payload.len());
+        SegmentFile::frame(&payload, &mut frame);
+        let segment = self.segments.get_mut(&self.active).unwrap();
+        let offset = segment.write(&frame)?;
+        self.set_location(
+            doc.id,
+            Location {
+                segment: self.active,
+                offset: offset as u64,
+                len: payload.len() as u32,
+            },
+        );
+        Ok(())
+    }
+
+    /// The segment being appended to.
+    pub fn active(&self) -> u32 {
+        self.active
+    }
+
+    /// Seals the active segment and starts a new one.
+    fn roll(&mut self) -> Result<()> {
+        self.segments.get_mut(&self.active).unwrap().remap()?;
+        self.active = self.next_id();
+        self.segments
+            .insert(self.active, SegmentFile::create(segment_path(&self.dir, self.active))?);
+        Ok(())
+    }
+
+    fn next_id(&self) -> u32 {
+        self.segments.keys().next_back().map_or(0, |id| id + 1)
+    }
+
+    /// Seals the active segment unless it is empty, so that everything
+    /// written so far can be merged.
+    pub fn seal(&mut self) -> Result<()> {
+        if !self.segments[&self.active].is_empty() {
+            self.roll()?;
+        }
+        Ok(())
+    }
+
+    /// Marks the live version of `id` deleted, returning whether there was
+    /// one. Its record stays in its segment until merged away.
+    pub fn delete(&mut self, id: &Uuid) -> bool {
+        match self.locations.get(id).copied() {
+            Some(location) => self.mark_deleted(&location),
+            None => false,
+        }
+    }
+
+    /// Deleted documents whose records are in `segments`, which a merge of
+    /// those segments drops for good.
+    pub fn tombstones_in(&self, segments: &[u32]) -> Vec<Uuid> {
+        self.locations
+            .iter()
+            .filter(|(_, location)| segments.contains(&location.segment) && self.is_deleted(location))
+            .map(|(id, _)| *id)
+            .collect()
+    }
+
+    /// Sizes and live data of the sealed segments, which are the ones that
+    /// can be merged.
+    pub fn sealed_segments(&self) -> Vec<SegmentStats> {
+        self.segments
+            .iter()
+            .filter(|(id, _)| **id != self.active)
+            .map(|(id, segment)| SegmentStats {
+                id: *id,
+                len: segment.len() as u64,
+                live_bytes: self.live_bytes.get(id).copied().unwrap_or(0),
+            })
+            .collect()
+    }
+
+    /// Copies the live records of the sealed segments `sources` into a new
+    /// segment file. Only reads the store, so reads can go on meanwhile;
+    /// the copy takes effect once passed to `install_merge`.
+    pub fn prepare_merge(&self, sources: &[u32]) -> Result<MergedSegment> {
+        for id in sources {
+            if *id == self.active || !self.segments.contains_key(id) {
+                bail!("document segment {id} is not sealed");
+            }
+        }
+        let mut moves: Vec<(Uuid, Location, u64)> = self
+            .locations
+            .iter()
+            .filter(|(_, location)| sources.contains(&location.segment) && !self.is_deleted(location))
+            .map(|(id, location)| (*id, *location, 0))
+            .collect();
+        // Copy in segment order, so reads of the sources are sequential.
+        moves.sort_by_key(|(_, location, _)| (location.segment, location.offset));
+
+        let path = self.dir.join(format!("{}.merge", Uuid::new_v4()));
+        let mut file = SegmentFile::create(&path)?;
+        let mut chunk = Vec::with_capacity(MERGE_CHUNK);
+        let mut offset = 0;
+        for (_, location, new_offset) in &mut moves {
+            let frame = self.segments[&location.segment].read_at(location.offset as usize, location.frame_len() as usize)?;
+            check_frame(&frame, location)?;
+            *new_offset = offset;
+            offset += frame.len() as u64;
+            chunk.extend_from_slice(&frame);
+            if chunk.len() >= MERGE_CHUNK {
+                file.write(&chunk)?;
+                chunk.clear();
+            }
+        }
+        if !chunk.is_empty() {
+            file.write(&chunk)?;
+        }
+        file.sync()?;
+        file.remap()?;
+        Ok(MergedSegment {
+            path,
+            file,
+            sources: sources.to_vec(),
+            moves,
+        })
+    }
+
+    /// Adds a merged segment to the store in place of its sources, and drops
+    /// the tombstones of documents deleted in them. Documents written or
+    /// deleted since it was prepared keep their new state. Fails without
+    /// changing anything if a source was merged meanwhile. Returns what was
+    /// reclaimed, and the merge to record so that it survives a restart.
+    pub fn install_merge(&mut self, merged: MergedSegment) -> Result<(MergeStats, MergeRecord)> {
+        let MergedSegment {
+            path,
+            file,
+            sources,
+            moves,
+        } = merged;
+        if let Some(id) = sources.iter().find(|id| !self.segments.contains_key(id)) {
+            std::fs::remove_file(&path)?;
+            bail!("document segment {id} was merged concurrently");
+        }
+
+        let bytes_before: u64 = sources.iter().map(|id| self.segments[id].len() as u64).sum();
+        let tombstones = self.tombstones_in(&sources);
+        for id in &tombstones {
+            self.locations.remove(id);
+        }
+        let mut stats = MergeStats {
+            segments_merged: sources.len(),
+            documents: 0,
+            deletes_purged: tombstones.len(),
+            bytes_reclaimed: bytes_before.saturating_sub(file.len() as u64),
+        };
+        let mut record = MergeRecord {
+            segment: None,
+            sources: sources.clone(),
+        };
+        if file.is_empty() {
+            std::fs::remove_file(&path)?;
+        } else {
+            let id = self.next_id();
+            let segment_path = segment_path(&self.dir, id);
+            record.segment = Some(SegmentState {
+                id,
+                len: file.len() as u64,
+            });
+            drop(file);
+            std::fs::rename(&path, &segment_path)?;
+            std::fs::File::open(&self.dir)?.sync_all()?;
+            self.segments.insert(id, SegmentFile::open(&segment_path)?);
+            for (doc_id, old, offset) in moves {
+                if self.live(&doc_id) == Some(&old) {
+                    self.set_location(
+                        doc_id,
+                        Location {
+                            segment: id,
+                            offset,
+                            len: old.len,
+                        },
+                    );
+                    stats.documents += 1;
+                }
+            }
+        }
+        for id in sources {
+            self.segments.remove(&id);
+            self.deleted.remove(&id);
+            self.live_bytes.remove(&id);
+            self.retired.push(segment_path(&self.dir, id));
+        }
+        Ok((stats, record))
+    }
+
+    /// Deletes the files of merged segments. Call once the merge is
+    /// recorded, or a checkpoint taken after it is in place, as recovery
+    /// reads them until then.
+    pub fn remove_retired(&mut self) -> Result<()> {
+        for path in self.retired.drain(..) {
+            std::fs::remove_file(&path)?;
+        }
+        Ok(())
+    }
+
+    /// IDs of the live documents.
+    pub fn ids(&self) -> impl Iterator<Item = &Uuid> {
+        self.locations().map(|(id, _)| id)
+    }
+
+    /// Locations of the live documents.
+    pub fn locations(&self) -> impl Iterator<Item = (&Uuid, &Location)> {
+        self.locations.iter().filter(|(_, location)| !self.is_deleted(location))
+    }
+
+    /// Deletion bitmap of each segment.
+    pub fn deletes(&self) -> impl Iterator<Item = (u32, &RoaringTreemap)> {
+        self.deleted.iter().map(|(id, deleted)| (*id, deleted))
+    }
+
+    pub fn len(&self) -> usize {
+        self.locations().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.locations().next().is_none()
+    }
+
+    /// Syncs every segment, and the directory listing them, to disk and
+    /// returns how far each reaches.
+    pub fn sync(&mut self) -> Result<Vec<SegmentState>> {
+        let mut states = Vec::with_capacity(self.segments.len());
+        for (id, segment) in &mut self.segments {
+            segment.sync()?;
+            segment.remap()?;
+            states.push(SegmentState {
+                id: *id,
+                len: segment.len() as u64,
+            });
+        }
+        std::fs::File::open(&self.dir)?.sync_all()?;
+        Ok(states)
+    }
+}
+
+fn check_frame(frame: &[u8], location: &Location) -> Result<()> {
+    let checksum = u32::from_le_bytes(frame[4..FRAME_HEADER_LEN].try_into().unwrap());
+    if crc32fast::hash(&frame[FRAME_HEADER_LEN..]) != checksum {
+        bail!(
+            "checksum mismatch in document segment {} at offset {}",
+            location.segment,
+            location.offset
+        );
+    }
+    Ok(())
+}
+
+/// Name of the file of segment `id` in the segments directory.
+pub fn file_name(id: u32) -> String {
+    format!("{id:08}.seg")
+}
+
+fn segment_path(dir: &Path, id: u32) -> PathBuf {
+    dir.join(file_name(id))
+}
+
+fn segment_id(path: &Path) -> Option<u32> {
+    if path.extension()? != "seg" {
+        return None;
+    }
+    path.file_stem()?.to_str()?.parse().ok()
+}
@@ -0,0 +1,149 @@
+use puresearch_core::mapping::Mappings;
+use puresearch_core::sort::{compare_values, Cursor, SortField, SortKey, SortValue};
+use puresearch_core::{ReviewDocument, ScoredDocument};
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+use uuid::Uuid;
+
+/// A hit keyed by its position in a sort order, so that a max-heap of them
+/// keeps the hit that sorts last on top.
+struct Ranked<'a> {
+    cursor: Cursor,
+    hit: ScoredDocument,
+    sort: &'a [SortField],
+}
+
+impl PartialEq for Ranked<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Ranked<'_> {}
+
+impl PartialOrd for Ranked<'_> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Ranked<'_> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.cursor.compare(&other.cursor, self.sort)
+    }
+}
+
+/// Per-field sort values, stored column by column.
+///
+/// Each metadata key has its own column mapping documents to the value they
+/// sort by, already converted according to the index's mappings, so sorting
+/// hits never has to load or parse the documents themselves.
+#[derive(Default)]
+pub struct DocValues {
+    columns: HashMap<String, HashMap<Uuid, SortValue>>,
+    timestamps: HashMap<Uuid, u64>,
+    mappings: Mappings,
+}
+
+impl DocValues {
+    pub fn new(mappings: Mappings) -> Self {
+        Self {
+            columns: HashMap::new(),
+            timestamps: HashMap::new(),
+            mappings,
+        }
+    }
+
+    pub fn add_document(&mut self, doc: &ReviewDocument) {
+        for (field, value) in &doc.metadata {
+            let value = SortValue::from_metadata(self.mappings.get(field), value);
+            self.columns.entry(field.clone()).or_default().insert(doc.id, value);
+        }
+        self.timestamps.insert(doc.id, doc.timestamp);
+    }
+
+    pub fn remove_document(&mut self, doc: &ReviewDocument) {
+        for field in doc.metadata.keys() {
+            if let Some(column) = self.columns.get_mut(field) {
+                column.remove(&doc.id);
+                if column.is_empty() {
+                    self.columns.remove(field);
+                }
+            }
+        }
+        self.timestamps.remove(&doc.id);
+    }
+
+    /// The value `hit` sorts by for `key`, if it has one.
+    pub fn value(&self, hit: &ScoredDocument, key: &SortKey) -> Option<SortValue> {
+        match key {
+            SortKey::Score => Some(SortValue::Number(hit.score as f64)),
+            SortKey::Timestamp => self.timestamps.get(&hit.id).map(|t| SortValue::Number(*t as f64)),
+            SortKey::Field(field) => self.columns.get(field)?.get(&hit.id).cloned(),
+        }
+    }
+
+    /// The numeric value of `field` for `id`, if it has one.
+    pub fn number(&self, id: &Uuid, field: &str) -> Option<f64> {
+        match self.columns.get(field)?.get(id)? {
+            SortValue::Number(n) => Some(*n),
+            SortValue::String(_) => None,
+        }
+    }
+
+    pub fn timestamp(&self, id: &Uuid) -> Option<u64> {
+        self.timestamps.get(id).copied()
+    }
+
+    /// `hit`'s values for each key of `sort`.
+    pub fn values(&self, hit: &ScoredDocument, sort: &[SortField]) -> Vec<Option<SortValue>> {
+        sort.iter().map(|field| self.value(hit, &field.key)).collect()
+    }
+
+    /// Where `hit` falls in `sort` order.
+    pub fn cursor(&self, hit: &ScoredDocument, sort: &[SortField]) -> Cursor {
+        Cursor {
+            values: self.values(hit, sort),
+            id: hit.id,
+        }
+    }
+
+    /// The first `limit` of `hits` in `sort` order that come after `after`,
+    /// and how many hits come after it in all. Keeps only `limit` hits at a
+    /// time, so a page costs the same wherever it starts.
+    pub fn top(
+        &self,
+        hits: impl IntoIterator<Item = ScoredDocument>,
+        sort: &[SortField],
+        after: Option<&Cursor>,
+        limit: usize,
+    ) -> (Vec<ScoredDocument>, usize) {
+        // Not sized from `limit`, which can far exceed the hits.
+        let mut heap = BinaryHeap::new();
+        let mut remaining = 0;
+        for hit in hits {
+            let cursor = self.cursor(&hit, sort);
+            if after.is_some_and(|after| cursor.compare(after, sort).is_le()) {
+                continue;
+            }
+            remaining += 1;
+            let ranked = Ranked { cursor, hit, sort };
+            if heap.len() < limit {
+                heap.push(ranked);
+            } else if heap.peek().is_some_and(|last| ranked < *last) {
+                heap.pop();
+                heap.push(ranked);
+            }
+        }
+        let page = heap.into_sorted_vec().into_iter().map(|ranked| ranked.hit).collect();
+        (page, remaining)
+    }
+
+    /// Orders hits by `sort`, then by document ID.
+    pub fn sort(&self, hits: &mut Vec<ScoredDocument>, sort: &[SortField]) {
+        let mut keyed: Vec<(Vec<Option<SortValue>>, ScoredDocument)> =
+            hits.drain(..).map(|hit| (self.values(&hit, sort), hit)).collect();
+        keyed.sort_by(|(a, hit_a), (b, hit_b)| compare_values(sort, a, b).then_with(|| hit_a.id.cmp(&hit_b.id)));
+        hits.extend(keyed.into_iter().map(|(_, hit)| hit));
+    }
+}
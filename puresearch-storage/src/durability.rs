@@ -0,0 +1,68 @@
+use crate::wal::WalSyncer;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// When a write counts as done, relative to its WAL records reaching disk.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Durability {
+    /// Done once its records are fsynced. The fsync runs straight away, or
+    /// right after the one already under way.
+    Sync,
+    /// Done once its records are fsynced, but the fsync is put off by up to
+    /// `StorageOptions::group_commit_latency` so that concurrent writes
+    /// share it.
+    #[default]
+    Group,
+    /// Done once its records are handed to the OS. They are fsynced within
+    /// `StorageOptions::sync_interval`, so a power failure can lose writes
+    /// made in that window; a crash of the process alone cannot.
+    Async,
+}
+
+/// How far the WAL had got when a write finished, to wait on until the
+/// write is durable.
+///
+/// Waiting does not need the storage, so a write can release its lock
+/// first and let other writes in to share the fsync.
+pub struct Commit {
+    syncer: Arc<WalSyncer>,
+    lsn: u64,
+    durability: Durability,
+    group_commit_latency: Duration,
+}
+
+impl Commit {
+    pub(crate) fn new(syncer: Arc<WalSyncer>, lsn: u64, durability: Durability, group_commit_latency: Duration) -> Self {
+        Self {
+            syncer,
+            lsn,
+            durability,
+            group_commit_latency,
+        }
+    }
+
+    /// Whether the write's records are on disk yet.
+    pub fn is_durable(&self) -> bool {
+        self.syncer.synced_lsn() >= self.lsn
+    }
+
+    /// Blocks until some other thread, such as the background syncer, has
+    /// made the write durable, or until `timeout` runs out. Returns whether
+    /// it is durable.
+    pub fn wait_synced(&self, timeout: Duration) -> bool {
+        self.syncer.wait_synced(self.lsn, timeout)
+    }
+
+    /// Blocks until the write is as durable as `durability` asks, or as the
+    /// storage's configured durability does if `None`.
+    pub fn wait(&self, durability: Option<Durability>) -> Result<()> {
+        match durability.unwrap_or(self.durability) {
+            Durability::Sync => self.syncer.wait(self.lsn, Duration::ZERO),
+            Durability::Group => self.syncer.wait(self.lsn, self.group_commit_latency),
+            Durability::Async => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,43 @@
+/// Edit distance between `a` and `b` counting insertions, deletions,
+/// substitutions and transpositions of adjacent characters (optimal string
+/// alignment), or `None` if it exceeds `max`.
+///
+/// Transpositions count as one edit because swapped letters ("recieved") are
+/// among the most common typos.
+pub fn edit_distance_within(a: &str, b: &str, max: u32) -> Option<u32> {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let max = max as usize;
+    if a.len().abs_diff(b.len()) > max {
+        return None;
+    }
+
+    // Three rolling rows of the DP table: two rows back (for
+    // transpositions), the previous row and the current one.
+    let mut before_prev: Vec<usize> = vec![0; b.len() + 1];
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    let mut current = vec![0; b.len() + 1];
+
+    for i in 1..=a.len() {
+        current[0] = i;
+        let mut row_min = current[0];
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            let mut value = (prev[j] + 1).min(current[j - 1] + 1).min(prev[j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                value = value.min(before_prev[j - 2] + 1);
+            }
+            current[j] = value;
+            row_min = row_min.min(value);
+        }
+        // Every later row is at least this row's minimum.
+        if row_min > max {
+            return None;
+        }
+        std::mem::swap(&mut before_prev, &mut prev);
+        std::mem::swap(&mut prev, &mut current);
+    }
+
+    let distance = prev[b.len()];
+    (distance <= max).then_some(distance as u32)
+}
@@ -0,0 +1,190 @@
+use crate::fuzzy::edit_distance_within;
+use puresearch_core::analysis::{Analyzer, Token};
+use std::collections::HashSet;
+
+#[derive(Debug, Clone)]
+pub struct HighlightOptions {
+    pub pre_tag: String,
+    pub post_tag: String,
+    /// Approximate length of each fragment in bytes, before tags are added.
+    pub fragment_size: usize,
+    pub number_of_fragments: usize,
+    /// HTML-escape the document text, inside and outside the tags, so that
+    /// snippets are safe to render as HTML. The tags themselves are kept.
+    pub escape_html: bool,
+}
+
+impl Default for HighlightOptions {
+    fn default() -> Self {
+        Self {
+            pre_tag: "<em>".to_string(),
+            post_tag: "</em>".to_string(),
+            fragment_size: 150,
+            number_of_fragments: 3,
+            escape_html: true,
+        }
+    }
+}
+
+/// Marks the terms of a query in document text.
+///
+/// The text is run through the same analyzer as the index, so a token is
+/// highlighted exactly when it would have matched, and its byte offsets say
+/// where to put the tags.
+pub struct Highlighter<'a> {
+    analyzer: &'a dyn Analyzer,
+    terms: HashSet<String>,
+    /// Fuzzy query terms and the edit distance they allow.
+    fuzzy_terms: Vec<(String, u32)>,
+}
+
+/// A span of text and the matched tokens inside it.
+struct Fragment {
+    start: usize,
+    end: usize,
+    matches: Vec<(usize, usize)>,
+}
+
+impl<'a> Highlighter<'a> {
+    pub fn new(analyzer: &'a dyn Analyzer, terms: HashSet<String>, fuzzy_terms: Vec<(String, u32)>) -> Self {
+        Self {
+            analyzer,
+            terms,
+            fuzzy_terms,
+        }
+    }
+
+    fn is_match(&self, token: &Token) -> bool {
+        self.terms.contains(&token.text)
+            || self
+                .fuzzy_terms
+                .iter()
+                .any(|(term, distance)| edit_distance_within(term, &token.text, *distance).is_some())
+    }
+
+    /// Up to `number_of_fragments` snippets of `text` around matched terms,
+    /// with matches wrapped in the configured tags. Fragments with the most
+    /// matches are chosen and returned in the order they appear. Returns
+    /// nothing if no term matches.
+    pub fn highlight(&self, text: &str, options: &HighlightOptions) -> Vec<String> {
+        let matches: Vec<(usize, usize)> = self
+            .analyzer
+            .analyze(text)
+            .iter()
+            .filter(|token| self.is_match(token))
+            .map(|token| (token.start, token.end))
+            .collect();
+
+        let mut fragments = build_fragments(text, &matches, options.fragment_size);
+        fragments.sort_by(|a, b| b.matches.len().cmp(&a.matches.len()).then(a.start.cmp(&b.start)));
+        fragments.truncate(options.number_of_fragments);
+        fragments.sort_by_key(|fragment| fragment.start);
+
+        fragments
+            .iter()
+            .map(|fragment| render(text, fragment, options))
+            .collect()
+    }
+}
+
+/// Groups matches into windows of roughly `size` bytes, each starting a
+/// little before its first match and trimmed to word boundaries.
+fn build_fragments(text: &str, matches: &[(usize, usize)], size: usize) -> Vec<Fragment> {
+    let mut fragments: Vec<Fragment> = Vec::new();
+    let mut i = 0;
+    while i < matches.len() {
+        let (first_start, first_end) = matches[i];
+        let lead = size.saturating_sub(first_end - first_start) / 4;
+        let start = word_start(text, first_start.saturating_sub(lead), first_start);
+        let limit = (start + size).max(first_end);
+
+        let mut group = vec![matches[i]];
+        i += 1;
+        while i < matches.len() && matches[i].1 <= limit {
+            group.push(matches[i]);
+            i += 1;
+        }
+        let last_end = group.last().unwrap().1;
+        let end = word_end(text, limit.min(text.len()), last_end);
+        fragments.push(Fragment {
+            start,
+            end,
+            matches: group,
+        });
+    }
+    fragments
+}
+
+/// Moves `pos` forward to the start of a word, without passing `limit`.
+fn word_start(text: &str, pos: usize, limit: usize) -> usize {
+    if pos == 0 {
+        return 0;
+    }
+    let pos = ceil_char_boundary(text, pos);
+    if text[..pos].ends_with(char::is_whitespace) {
+        return pos;
+    }
+    match text[pos..limit].find(char::is_whitespace) {
+        Some(offset) => {
+            let after = pos + offset;
+            after + text[after..].chars().next().map_or(0, char::len_utf8)
+        }
+        None => pos,
+    }
+}
+
+/// Moves `pos` back to the end of a word, without passing `limit`.
+fn word_end(text: &str, pos: usize, limit: usize) -> usize {
+    let pos = ceil_char_boundary(text, pos);
+    if pos >= text.len() {
+        return text.len();
+    }
+    if text[pos..].starts_with(char::is_whitespace) {
+        return pos;
+    }
+    match text[limit..pos].rfind(char::is_whitespace) {
+        Some(offset) => limit + offset,
+        None => pos,
+    }
+}
+
+fn ceil_char_boundary(text: &str, mut pos: usize) -> usize {
+    while pos < text.len() && !text.is_char_boundary(pos) {
+        pos += 1;
+    }
+    pos.min(text.len())
+}
+
+fn render(text: &str, fragment: &Fragment, options: &HighlightOptions) -> String {
+    let push_text = |out: &mut String, text: &str| {
+        if options.escape_html {
+            escape_html(text, out);
+        } else {
+            out.push_str(text);
+        }
+    };
+    let mut out = String::with_capacity(fragment.end - fragment.start + 16);
+    let mut cursor = fragment.start;
+    for &(start, end) in &fragment.matches {
+        push_text(&mut out, &text[cursor..start]);
+        out.push_str(&options.pre_tag);
+        push_text(&mut out, &text[start..end]);
+        out.push_str(&options.post_tag);
+        cursor = end;
+    }
+    push_text(&mut out, &text[cursor..fragment.end]);
+    out.trim().to_string()
+}
+
+fn escape_html(text: &str, out: &mut String) {
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+}
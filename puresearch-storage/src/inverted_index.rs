@@ -0,0 +1,554 @@
+use crate::aggregations;
+use crate::doc_values::DocValues;
+use crate::fuzzy::edit_distance_within;
+use crate::highlight::Highlighter;
+use crate::metadata_index::MetadataIndex;
+use crate::scoring::Bm25;
+use crate::term_dictionary::{Suggestion, TermDictionary};
+use puresearch_core::aggregation::{Aggregation, AggregationResult};
+use puresearch_core::analysis::{Analyzer, AnalyzerConfig, Token};
+use puresearch_core::mapping::{FieldType, Mappings};
+use puresearch_core::query::{BoolQuery, Fuzziness, Query, DEFAULT_FIELD};
+use puresearch_core::sort::{Cursor, SortField, RELEVANCE};
+use puresearch_core::{ReviewDocument, ScoredDocument};
+use std::collections::{HashMap, HashSet};
+use uuid::Uuid;
+
+/// Matching documents and their scores for one query clause.
+type Matches = HashMap<Uuid, f32>;
+
+type Postings = HashMap<Uuid, Vec<u32>>;
+
+/// Most dictionary terms a single fuzzy term expands to. The closest terms
+/// are kept, preferring more frequent ones among equally close terms.
+const MAX_FUZZY_EXPANSIONS: usize = 50;
+
+/// Postings and length statistics for one field.
+#[derive(Default)]
+struct FieldIndex {
+    terms: HashMap<String, Postings>,
+    doc_lengths: HashMap<Uuid, u32>,
+    total_length: u64,
+}
+
+impl FieldIndex {
+    fn add(&mut self, id: Uuid, tokens: Vec<Token>) {
+        let length = tokens.len() as u32;
+        for token in tokens {
+            self.terms
+                .entry(token.text)
+                .or_default()
+                .entry(id)
+                .or_default()
+                .push(token.position);
+        }
+        self.doc_lengths.insert(id, length);
+        self.total_length += length as u64;
+    }
+
+    fn remove(&mut self, id: &Uuid, tokens: Vec<Token>) {
+        let terms: HashSet<String> = tokens.into_iter().map(|token| token.text).collect();
+        for term in terms {
+            if let Some(docs) = self.terms.get_mut(&term) {
+                docs.remove(id);
+                if docs.is_empty() {
+                    self.terms.remove(&term);
+                }
+            }
+        }
+        if let Some(length) = self.doc_lengths.remove(id) {
+            self.total_length -= length as u64;
+        }
+    }
+
+    fn avg_doc_len(&self) -> f32 {
+        if self.doc_lengths.is_empty() {
+            0.0
+        } else {
+            self.total_length as f32 / self.doc_lengths.len() as f32
+        }
+    }
+
+    /// Postings of the dictionary terms within `max_distance` edits of
+    /// `term`, with their distance.
+    fn fuzzy_terms(&self, term: &str, max_distance: u32) -> Vec<(&Postings, u32)> {
+        let mut candidates: Vec<(&str, &Postings, u32)> = self
+            .terms
+            .iter()
+            .filter_map(|(candidate, docs)| {
+                edit_distance_within(term, candidate, max_distance)
+                    .map(|distance| (candidate.as_str(), docs, distance))
+            })
+            .collect();
+        candidates.sort_by(|a, b| {
+            a.2.cmp(&b.2)
+                .then_with(|| b.1.len().cmp(&a.1.len()))
+                .then_with(|| a.0.cmp(b.0))
+        });
+        candidates.truncate(MAX_FUZZY_EXPANSIONS);
+        candidates.into_iter().map(|(_, docs, distance)| (docs, distance)).collect()
+    }
+}
+
+/// One page of search results.
+#[derive(Debug, Clone)]
+pub struct Page {
+    pub hits: Vec<ScoredDocument>,
+    /// Every match, before and after the page.
+    pub total: usize,
+    /// Matches from the start of the page on, the page included.
+    pub remaining: usize,
+}
+
+impl Page {
+    /// Whether there are matches after the page.
+    pub fn has_more(&self) -> bool {
+        self.remaining > self.hits.len()
+    }
+}
+
+/// Term -> postings maps over `ReviewDocument.content` and each metadata key.
+///
+/// Terms are produced by the index's analyzer, which is applied both to
+/// document text and to queries. Metadata keys mapped as keywords skip it:
+/// their whole value is one term, matched exactly. Each posting lists the positions at which
+/// the term occurs in the document, which gives both the term frequency for
+/// BM25 and the word order needed by phrase queries. Every field keeps its
+/// own length statistics. The index is kept in memory and maintained
+/// incrementally as documents are stored and deleted; it is rebuilt from the
+/// WAL on startup.
+///
+/// Alongside the postings, the content words as written (lowercased but not
+/// stemmed) are kept in a `TermDictionary` for autocomplete, the raw
+/// metadata values in a `MetadataIndex` for filters, and sort values in
+/// `DocValues`.
+pub struct InvertedIndex {
+    analyzer: Box<dyn Analyzer>,
+    mappings: Mappings,
+    fields: HashMap<String, FieldIndex>,
+    metadata: MetadataIndex,
+    doc_values: DocValues,
+    vocabulary: TermDictionary,
+    /// Every indexed document, for `MatchAll` and pure negations.
+    doc_ids: HashSet<Uuid>,
+    bm25: Bm25,
+}
+
+impl Default for InvertedIndex {
+    fn default() -> Self {
+        Self::new(Box::new(AnalyzerConfig::default().build()))
+    }
+}
+
+impl InvertedIndex {
+    pub fn new(analyzer: Box<dyn Analyzer>) -> Self {
+        Self {
+            analyzer,
+            mappings: Mappings::new(),
+            fields: HashMap::new(),
+            metadata: MetadataIndex::default(),
+            doc_values: DocValues::default(),
+            vocabulary: TermDictionary::new(),
+            doc_ids: HashSet::new(),
+            bm25: Bm25::default(),
+        }
+    }
+
+    /// Uses `mappings` to read metadata values in filters and sorting. Must
+    /// be called before any document is added.
+    pub fn with_mappings(mut self, mappings: Mappings) -> Self {
+        self.metadata = MetadataIndex::new(mappings.clone());
+        self.doc_values = DocValues::new(mappings.clone());
+        self.mappings = mappings;
+        self
+    }
+
+    fn analyze(&self, text: &str) -> Vec<Token> {
+        self.analyzer.analyze(text)
+    }
+
+    /// The terms of `text` in `field`: the analyzer's tokens, or the text
+    /// itself if the field is mapped as a keyword.
+    fn analyze_field(&self, field: &str, text: &str) -> Vec<Token> {
+        if self.mappings.get(field) == Some(&FieldType::Keyword) {
+            return vec![Token {
+                text: text.to_string(),
+                position: 0,
+                start: 0,
+                end: text.len(),
+            }];
+        }
+        self.analyze(text)
+    }
+
+    /// The text of each indexed field of `doc`.
+    fn field_values(doc: &ReviewDocument) -> impl Iterator<Item = (&str, &str)> {
+        std::iter::once((DEFAULT_FIELD, doc.content.as_str())).chain(
+            doc.metadata
+                .iter()
+                .filter(|(key, _)| key.as_str() != DEFAULT_FIELD)
+                .map(|(key, value)| (key.as_str(), value.as_str())),
+        )
+    }
+
+    /// Distinct content words of `doc` as written, lowercased.
+    fn surface_forms(doc: &ReviewDocument, tokens: &[Token]) -> HashSet<String> {
+        tokens
+            .iter()
+            .map(|token| doc.content[token.start..token.end].to_lowercase())
+            .collect()
+    }
+
+    pub fn add_document(&mut self, doc: &ReviewDocument) {
+        for (field, text) in Self::field_values(doc) {
+            let tokens = self.analyze_field(field, text);
+            if field == DEFAULT_FIELD {
+                for word in Self::surface_forms(doc, &tokens) {
+                    self.vocabulary.increment(&word);
+                }
+            }
+            self.fields.entry(field.to_string()).or_default().add(doc.id, tokens);
+        }
+        self.metadata.add_document(doc);
+        self.doc_values.add_document(doc);
+        self.doc_ids.insert(doc.id);
+    }
+
+    /// Removes `doc` from the postings. The document passed in must be the
+    /// version that was indexed, since its text determines which posting
+    /// lists are touched.
+    pub fn remove_document(&mut self, doc: &ReviewDocument) {
+        for (field, text) in Self::field_values(doc) {
+            let tokens = self.analyze_field(field, text);
+            if field == DEFAULT_FIELD {
+                for word in Self::surface_forms(doc, &tokens) {
+                    self.vocabulary.decrement(&word);
+                }
+            }
+            if let Some(index) = self.fields.get_mut(field) {
+                index.remove(&doc.id, tokens);
+                if index.doc_lengths.is_empty() {
+                    self.fields.remove(field);
+                }
+            }
+        }
+        self.metadata.remove_document(doc);
+        self.doc_values.remove_document(doc);
+        self.doc_ids.remove(&doc.id);
+    }
+
+    pub fn contains(&self, id: &Uuid) -> bool {
+        self.doc_ids.contains(id)
+    }
+
+    pub fn doc_count(&self) -> usize {
+        self.doc_ids.len()
+    }
+
+    /// The most common content words starting with `prefix`.
+    pub fn suggest(&self, prefix: &str, limit: usize) -> Vec<Suggestion> {
+        self.vocabulary.suggest(&prefix.to_lowercase(), limit)
+    }
+
+    /// A highlighter for the content terms `query` searches for. Terms under
+    /// `must_not` are left out since they never appear in a hit.
+    pub fn highlighter(&self, query: &Query) -> Highlighter<'_> {
+        let mut terms = HashSet::new();
+        let mut fuzzy_terms = Vec::new();
+        self.collect_highlight_terms(query, &mut terms, &mut fuzzy_terms);
+        Highlighter::new(self.analyzer.as_ref(), terms, fuzzy_terms)
+    }
+
+    fn collect_highlight_terms(&self, query: &Query, terms: &mut HashSet<String>, fuzzy_terms: &mut Vec<(String, u32)>) {
+        match query {
+            Query::MatchAll | Query::Filter(_) => {}
+            Query::Term { field, text } | Query::Phrase { field, text, .. } => {
+                if field_name(field) == DEFAULT_FIELD {
+                    terms.extend(self.analyze(text).into_iter().map(|token| token.text));
+                }
+            }
+            Query::Fuzzy { field, text, fuzziness } => {
+                if field_name(field) == DEFAULT_FIELD {
+                    for token in self.analyze(text) {
+                        let distance = fuzziness.distance_for(&token.text).min(Fuzziness::MAX_DISTANCE);
+                        fuzzy_terms.push((token.text, distance));
+                    }
+                }
+            }
+            Query::Bool(bool_query) => {
+                for clause in bool_query.must.iter().chain(&bool_query.should) {
+                    self.collect_highlight_terms(clause, terms, fuzzy_terms);
+                }
+            }
+        }
+    }
+
+    /// Returns the documents matching `query` in `sort` order, or ranked by
+    /// BM25 score if `sort` is empty. Ties are broken by document ID.
+    pub fn search(&self, query: &Query, sort: &[SortField]) -> Vec<ScoredDocument> {
+        let matches = self.evaluate(query).unwrap_or_default();
+        let mut hits: Vec<ScoredDocument> = matches
+            .into_iter()
+            .map(|(id, score)| ScoredDocument { id, score })
+            .collect();
+        if sort.is_empty() || sort == RELEVANCE {
+            hits.sort_by(|a, b| b.score.total_cmp(&a.score).then_with(|| a.id.cmp(&b.id)));
+        } else {
+            self.doc_values.sort(&mut hits, sort);
+        }
+        hits
+    }
+
+    /// The documents matching `query`, in no particular order.
+    pub fn matches(&self, query: &Query) -> Vec<ScoredDocument> {
+        self.evaluate(query)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(id, score)| ScoredDocument { id, score })
+            .collect()
+    }
+
+    /// The first `limit` documents matching `query` and `filter` that come
+    /// after `after` in `sort` order, with how many matches there are in
+    /// all and how many come after `after`.
+    pub fn search_page(
+        &self,
+        query: &Query,
+        sort: &[SortField],
+        after: Option<&Cursor>,
+        limit: usize,
+        filter: impl Fn(&Uuid) -> bool,
+    ) -> Page {
+        let matches: Vec<ScoredDocument> = self.matches(query).into_iter().filter(|hit| filter(&hit.id)).collect();
+        let total = matches.len();
+        let (hits, remaining) = self.doc_values.top(matches, sort, after, limit);
+        Page { hits, total, remaining }
+    }
+
+    /// Computes `aggregation` over `hits`, which must come from this index.
+    pub fn aggregate(&self, hits: &[ScoredDocument], aggregation: &Aggregation) -> AggregationResult {
+        aggregations::aggregate(&self.metadata, &self.doc_values, hits, aggregation)
+    }
+
+    /// Where `hit` falls among results sorted by `sort`.
+    pub fn cursor(&self, hit: &ScoredDocument, sort: &[SortField]) -> Cursor {
+        self.doc_values.cursor(hit, sort)
+    }
+
+    /// Evaluates one clause. `None` means the clause places no constraint on
+    /// the result, which happens when its text analyzes to nothing (for
+    /// example, only stopwords).
+    fn evaluate(&self, query: &Query) -> Option<Matches> {
+        match query {
+            Query::MatchAll => Some(self.doc_ids.iter().map(|id| (*id, 1.0)).collect()),
+            Query::Term { field, text } => {
+                let field = field_name(field);
+                let terms: Vec<String> = self.analyze_field(field, text).into_iter().map(|token| token.text).collect();
+                self.match_all_terms(field, &terms)
+            }
+            Query::Phrase { field, text, slop } => self.match_phrase(field_name(field), text, *slop),
+            Query::Fuzzy { field, text, fuzziness } => self.match_fuzzy(field_name(field), text, *fuzziness),
+            Query::Bool(bool_query) => self.evaluate_bool(bool_query),
+            Query::Filter(filter) => Some(self.metadata.evaluate(filter).into_iter().map(|id| (id, 0.0)).collect()),
+        }
+    }
+
+    fn evaluate_bool(&self, query: &BoolQuery) -> Option<Matches> {
+        let must: Vec<Matches> = query.must.iter().filter_map(|q| self.evaluate(q)).collect();
+        let should: Vec<Matches> = query.should.iter().filter_map(|q| self.evaluate(q)).collect();
+        let must_not: Vec<Matches> = query.must_not.iter().filter_map(|q| self.evaluate(q)).collect();
+        let filter: Vec<Matches> = query.filter.iter().filter_map(|q| self.evaluate(q)).collect();
+
+        let mut result = if !must.is_empty() {
+            let mut must = must.into_iter();
+            let first = must.next().unwrap();
+            let mut result = must.fold(first, |acc, matches| intersect(acc, &matches));
+            for matches in &should {
+                for (id, score) in result.iter_mut() {
+                    if let Some(extra) = matches.get(id) {
+                        *score += extra;
+                    }
+                }
+            }
+            result
+        } else if !should.is_empty() {
+            let mut result = Matches::new();
+            for matches in should {
+                for (id, score) in matches {
+                    *result.entry(id).or_insert(0.0) += score;
+                }
+            }
+            result
+        } else if !must_not.is_empty() || !filter.is_empty() {
+            self.doc_ids.iter().map(|id| (*id, 1.0)).collect()
+        } else {
+            return None;
+        };
+
+        for matches in &filter {
+            result.retain(|id, _| matches.contains_key(id));
+        }
+        for matches in &must_not {
+            result.retain(|id, _| !matches.contains_key(id));
+        }
+        Some(result)
+    }
+
+    /// Documents whose `field` contains every one of `terms`, scored by the
+    /// sum of the terms' BM25 contributions.
+    fn match_all_terms(&self, field: &str, terms: &[String]) -> Option<Matches> {
+        let mut terms = terms.to_vec();
+        terms.sort();
+        terms.dedup();
+        if terms.is_empty() {
+            return None;
+        }
+        let Some(index) = self.fields.get(field) else {
+            return Some(Matches::new());
+        };
+
+        let mut lists = Vec::with_capacity(terms.len());
+        for term in &terms {
+            match index.terms.get(term) {
+                Some(docs) => lists.push(docs),
+                None => return Some(Matches::new()),
+            }
+        }
+
+        // Walk the shortest posting list and probe the others.
+        lists.sort_by_key(|docs| docs.len());
+        let (first, rest) = lists.split_first().unwrap();
+        let matches = first
+            .keys()
+            .filter(|id| rest.iter().all(|docs| docs.contains_key(id)))
+            .map(|id| (*id, self.score(index, id, &lists)))
+            .collect();
+        Some(matches)
+    }
+
+    fn match_phrase(&self, field: &str, text: &str, slop: u32) -> Option<Matches> {
+        let tokens = self.analyze_field(field, text);
+        let first_position = tokens.first()?.position;
+        let Some(index) = self.fields.get(field) else {
+            return Some(Matches::new());
+        };
+
+        let mut lists = Vec::with_capacity(tokens.len());
+        for token in &tokens {
+            match index.terms.get(&token.text) {
+                Some(docs) => lists.push(docs),
+                None => return Some(Matches::new()),
+            }
+        }
+        // Offsets of each term within the phrase, keeping any gaps left by
+        // stopwords so they must be matched in the document too.
+        let offsets: Vec<u32> = tokens.iter().map(|token| token.position - first_position).collect();
+
+        let (first, rest) = lists.split_first().unwrap();
+        let matches = first
+            .keys()
+            .filter(|id| rest.iter().all(|docs| docs.contains_key(id)))
+            .filter(|id| {
+                let positions: Vec<&[u32]> = lists.iter().map(|docs| docs[*id].as_slice()).collect();
+                phrase_matches(&positions, &offsets, slop)
+            })
+            .map(|id| (*id, self.score(index, id, &lists)))
+            .collect();
+        Some(matches)
+    }
+
+    /// Documents whose `field` contains, for every term of `text`, that term
+    /// or one within the allowed edit distance. Each document scores its best
+    /// matching variant per term, scaled down by how many edits it took.
+    fn match_fuzzy(&self, field: &str, text: &str, fuzziness: Fuzziness) -> Option<Matches> {
+        let tokens = self.analyze_field(field, text);
+        if tokens.is_empty() {
+            return None;
+        }
+        let Some(index) = self.fields.get(field) else {
+            return Some(Matches::new());
+        };
+
+        let mut result: Option<Matches> = None;
+        for token in tokens {
+            let max_distance = fuzziness.distance_for(&token.text).min(Fuzziness::MAX_DISTANCE);
+            let mut matches = Matches::new();
+            for (docs, distance) in index.fuzzy_terms(&token.text, max_distance) {
+                let boost = 1.0 - distance as f32 / (token.text.chars().count() as f32 + 1.0);
+                for id in docs.keys() {
+                    let score = self.score(index, id, &[docs]) * boost;
+                    let best = matches.entry(*id).or_insert(0.0);
+                    *best = best.max(score);
+                }
+            }
+            result = Some(match result {
+                None => matches,
+                Some(acc) => intersect(acc, &matches),
+            });
+        }
+        result
+    }
+
+    fn score(&self, index: &FieldIndex, id: &Uuid, lists: &[&Postings]) -> f32 {
+        let doc_count = index.doc_lengths.len();
+        let avg_doc_len = index.avg_doc_len();
+        let doc_len = index.doc_lengths.get(id).copied().unwrap_or(0);
+        lists
+            .iter()
+            .map(|docs| {
+                let idf = self.bm25.idf(doc_count, docs.len());
+                self.bm25.term_score(idf, docs[id].len() as u32, doc_len, avg_doc_len)
+            })
+            .sum()
+    }
+}
+
+fn field_name(field: &Option<String>) -> &str {
+    field.as_deref().unwrap_or(DEFAULT_FIELD)
+}
+
+/// Keeps the documents present in both sets, summing their scores.
+fn intersect(acc: Matches, other: &Matches) -> Matches {
+    acc.into_iter()
+        .filter_map(|(id, score)| other.get(&id).map(|extra| (id, score + extra)))
+        .collect()
+}
+
+/// Whether one occurrence of each phrase term can be picked so that the
+/// terms line up within `slop` positions.
+///
+/// Each term's positions are shifted back by its offset in the phrase, so an
+/// exact match is one shifted position shared by every term. The spread
+/// between the smallest and largest chosen shifted position is the number of
+/// moves needed, which is found with a sweep over the sorted lists.
+fn phrase_matches(positions: &[&[u32]], offsets: &[u32], slop: u32) -> bool {
+    let shifted: Vec<Vec<i64>> = positions
+        .iter()
+        .zip(offsets)
+        .map(|(list, &offset)| {
+            let mut list: Vec<i64> = list.iter().map(|&p| p as i64 - offset as i64).collect();
+            list.sort_unstable();
+            list
+        })
+        .collect();
+
+    let mut cursors = vec![0usize; shifted.len()];
+    loop {
+        let mut min = (i64::MAX, 0);
+        let mut max = i64::MIN;
+        for (term, list) in shifted.iter().enumerate() {
+            let value = list[cursors[term]];
+            if value < min.0 {
+                min = (value, term);
+            }
+            max = max.max(value);
+        }
+        if max - min.0 <= slop as i64 {
+            return true;
+        }
+        cursors[min.1] += 1;
+        if cursors[min.1] == shifted[min.1].len() {
+            return false;
+        }
+    }
+}
@@ -1,87 +1,691 @@
-use anyhow::Result;
-use puresearch_core::{storage::{StorageEngine, IndexStorage}, ReviewDocument, Index};
+use anyhow::{bail, Result};
+use puresearch_core::aggregation::{Aggregation, AggregationResult};
+use puresearch_core::{storage::{StorageEngine, IndexStorage, SearchIndex, WriteOp}, query::Query, sort::{Cursor, SortField}, ReviewDocument, Index, ScoredDocument};
 use std::collections::HashMap;
+use std::fs::{File, OpenOptions, TryLockError};
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
 use uuid::Uuid;
 
+pub mod aggregations;
+pub mod checkpoint;
+pub mod doc_store;
+pub mod doc_values;
+pub mod durability;
+pub mod fuzzy;
+pub mod highlight;
+pub mod inverted_index;
+pub mod merge;
+pub mod metadata_index;
+pub mod scoring;
+pub mod searcher;
 pub mod segment;
+pub mod snapshot;
+mod staging;
+pub mod term_dictionary;
 pub mod wal;
 
+pub use doc_store::{DocumentStore, MergedSegment};
+pub use doc_values::DocValues;
+pub use durability::{Commit, Durability};
+pub use highlight::{HighlightOptions, Highlighter};
+pub use inverted_index::{InvertedIndex, Page};
+pub use merge::{MergePolicy, MergeStats};
+pub use metadata_index::MetadataIndex;
+pub use scoring::Bm25;
+pub use searcher::Searcher;
+pub use snapshot::{SnapshotError, SnapshotInfo};
 pub use segment::SegmentFile;
-pub use wal::WriteAheadLog;
+pub use term_dictionary::{Suggestion, TermDictionary};
+pub use wal::{RecoveryReport, WalSyncer, WriteAheadLog};
+
+use checkpoint::CheckpointDocuments;
+use staging::Staging;
+use wal::WalEntry;
+
+#[derive(Debug, Clone)]
+pub struct StorageOptions {
+    /// WAL size in bytes past which `MmapStorage::maybe_checkpoint` takes a
+    /// checkpoint, so that the log and startup replay stay bounded. Writes
+    /// never take one themselves. `None` leaves checkpoints to explicit
+    /// `MmapStorage::checkpoint` calls.
+    pub checkpoint_wal_bytes: Option<u64>,
+    /// Size in bytes at which a document segment is sealed and a new one
+    /// started.
+    pub segment_bytes: u64,
+    /// Which sealed document segments `MmapStorage::merge` merges.
+    pub merge_policy: MergePolicy,
+    /// When writes count as done. Writes made through `MmapStorage::deferred`
+    /// can ask for another durability.
+    pub durability: Durability,
+    /// Longest a `Durability::Group` write waits for others to share its
+    /// fsync.
+    pub group_commit_latency: Duration,
+    /// How often appended WAL records are fsynced in the background, which
+    /// bounds how many `Durability::Async` writes a power failure can lose.
+    pub sync_interval: Duration,
+}
+
+impl Default for StorageOptions {
+    fn default() -> Self {
+        Self {
+            checkpoint_wal_bytes: Some(64 * 1024 * 1024),
+            segment_bytes: 64 * 1024 * 1024,
+            merge_policy: MergePolicy::default(),
+            durability: Durability::default(),
+            group_commit_latency: Duration::from_millis(2),
+            sync_interval: Duration::from_secs(1),
+        }
+    }
+}
+
+/// File in a data directory that whoever has the directory open holds an
+/// exclusive lock on.
+pub const LOCK: &str = "LOCK";
+
+/// Takes an exclusive lock on `data_dir`, held until the returned file is
+/// closed. Fails straight away if it is already held, by a running server
+/// or any other open storage.
+pub fn lock_data_dir(data_dir: &Path) -> Result<File> {
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(data_dir.join(LOCK))?;
+    match file.try_lock() {
+        Ok(()) => Ok(file),
+        Err(TryLockError::WouldBlock) => bail!("{} is in use; stop the server first", data_dir.display()),
+        Err(TryLockError::Error(err)) => Err(err.into()),
+    }
+}
 
 pub struct MmapStorage {
     data_dir: PathBuf,
-    documents: HashMap<Uuid, ReviewDocument>,
+    /// Keeps other processes from opening the data directory meanwhile.
+    _lock: File,
+    documents: DocumentStore,
     indices: HashMap<Uuid, Index>,
+    inverted_index: InvertedIndex,
+    /// Postings for each index, built with that index's analyzer.
+    index_postings: HashMap<Uuid, InvertedIndex>,
     wal: WriteAheadLog,
+    recovery: RecoveryReport,
+    /// LSN of the current checkpoint, if one has been taken.
+    checkpoint_lsn: Option<u64>,
+    options: StorageOptions,
+    /// Whether the caller waits for durability itself, through the `Commit`
+    /// returned by `deferred`.
+    deferred: bool,
 }
 
 impl MmapStorage {
     pub fn new<P: AsRef<Path>>(data_dir: P) -> Result<Self> {
+        Self::with_options(data_dir, StorageOptions::default())
+    }
+
+    pub fn with_options<P: AsRef<Path>>(data_dir: P, options: StorageOptions) -> Result<Self> {
         let data_dir = data_dir.as_ref().to_path_buf();
         std::fs::create_dir_all(&data_dir)?;
+        let lock = lock_data_dir(&data_dir)?;
+        if data_dir.join(snapshot::RESTORING).exists() {
+            bail!("a snapshot restore into {} did not finish; run it again", data_dir.display());
+        }
         
+        let checkpoint = checkpoint::load(&data_dir)?;
+        let (segments, active, merges) = match &checkpoint {
+            Some(checkpoint) => {
+                let merges = checkpoint::load_merges(&data_dir, checkpoint.manifest.lsn)?;
+                let segments = checkpoint::merged_segments(&checkpoint.manifest.segments, &merges);
+                (segments, checkpoint.manifest.active, merges)
+            }
+            None => (Vec::new(), None, Vec::new()),
+        };
+        let documents = DocumentStore::open(data_dir.join(doc_store::DIR), &segments, active, options.segment_bytes)?;
         let wal = WriteAheadLog::new(data_dir.join("wal.log"))?;
+        WalSyncer::sync_periodically(Arc::downgrade(&wal.syncer()), options.sync_interval)?;
         let mut storage = Self {
             data_dir,
-            documents: HashMap::new(),
+            _lock: lock,
+            documents,
             indices: HashMap::new(),
+            inverted_index: InvertedIndex::default(),
+            index_postings: HashMap::new(),
             wal,
+            recovery: RecoveryReport::default(),
+            checkpoint_lsn: None,
+            options,
+            deferred: false,
         };
         
-        storage.recover_from_wal()?;
+        storage.recover_from_wal(checkpoint, &merges)?;
         Ok(storage)
     }
 
-    fn recover_from_wal(&mut self) -> Result<()> {
-        let entries = self.wal.read_all_entries()?;
-        for entry in entries {
+    /// Loads `checkpoint`, if any, and replays the `merges` installed after
+    /// it, then replays the WAL records written after it.
+    fn recover_from_wal(
+        &mut self,
+        checkpoint: Option<checkpoint::Checkpoint>,
+        merges: &[checkpoint::MergeRecord],
+    ) -> Result<()> {
+        let mut checkpoint_lsn = None;
+        if let Some(checkpoint) = checkpoint {
+            let restored = match checkpoint.documents {
+                CheckpointDocuments::Locations(locations) => {
+                    for (id, location) in locations {
+                        self.documents.restore(id, location);
+                    }
+                    true
+                }
+                CheckpointDocuments::Bodies(documents) => {
+                    for doc in documents {
+                        self.apply_store(doc)?;
+                    }
+                    false
+                }
+            };
+            for (segment, deleted) in checkpoint.deletes {
+                self.documents.restore_deletes(segment, deleted);
+            }
+            for merge in merges {
+                self.documents.replay_merge(merge)?;
+            }
+            if restored {
+                let ids: Vec<Uuid> = self.documents.ids().copied().collect();
+                for id in ids {
+                    let doc = self.documents.get(&id)?.unwrap();
+                    self.inverted_index.add_document(&doc);
+                }
+            }
+            for index in checkpoint.indices {
+                self.apply_store_index(index)?;
+            }
+            checkpoint_lsn = Some(checkpoint.manifest.lsn);
+        }
+        let from_lsn = checkpoint_lsn.unwrap_or(0);
+
+        let (entries, mut report) = self.wal.recover()?;
+        let base_lsn = self.wal.base_lsn();
+        if base_lsn > from_lsn {
+            bail!("WAL starts at LSN {base_lsn}, after the checkpoint at LSN {from_lsn}; records in between are missing");
+        }
+        if self.wal.next_lsn() < from_lsn {
+            // Everything left in the log is in the checkpoint, so start a
+            // fresh log after it for new records to be numbered past it.
+            self.wal.rotate(from_lsn)?;
+        }
+        report.checkpoint_lsn = checkpoint_lsn;
+        self.checkpoint_lsn = checkpoint_lsn;
+
+        let mut batch: Option<Vec<WalEntry>> = None;
+        for entry in entries.into_iter().skip((from_lsn - base_lsn) as usize) {
             match entry {
-                wal::WalEntry::Document(doc) => {
-                    self.documents.insert(doc.id, doc);
-                },
-                wal::WalEntry::Delete(id) => {
-                    self.documents.remove(&id);
+                WalEntry::BeginBatch => batch = Some(Vec::new()),
+                WalEntry::CommitBatch => {
+                    for entry in batch.take().unwrap_or_default() {
+                        self.apply(entry)?;
+                    }
+                }
+                WalEntry::AbortBatch => batch = None,
+                entry => match batch.as_mut() {
+                    Some(batch) => batch.push(entry),
+                    None => {
+                        self.apply(entry)?;
+                    }
                 },
-                wal::WalEntry::Index(index) => {
-                    self.indices.insert(index.id, index);
+            }
+        }
+        if batch.is_some() {
+            // The log ends inside a batch that was never committed. Mark it
+            // aborted, or entries written from here on would be read as
+            // part of it.
+            self.wal.write_entry(&WalEntry::AbortBatch)?;
+            report.incomplete_batch = true;
+        }
+        self.recovery = report;
+        Ok(())
+    }
+
+    /// Persists the current documents and indices as a checkpoint and starts
+    /// a new, empty WAL after it. Returns the checkpoint's LSN.
+    pub fn checkpoint(&mut self) -> Result<u64> {
+        Ok(self.write_checkpoint()?.lsn)
+    }
+
+    fn write_checkpoint(&mut self) -> Result<checkpoint::Manifest> {
+        self.wal.flush()?;
+        let lsn = self.wal.next_lsn();
+        let manifest = checkpoint::write(&self.data_dir, lsn, &mut self.documents, self.indices.values())?;
+        self.checkpoint_lsn = Some(lsn);
+        self.documents.remove_retired()?;
+        self.wal.rotate(lsn)?;
+        Ok(manifest)
+    }
+
+    /// Takes a checkpoint and keeps it as the snapshot `name`, which
+    /// `snapshot::restore` can later bring back. Hard links the checkpoint
+    /// and the document segments instead of copying them, so the write lock
+    /// is held about as long as for a checkpoint.
+    pub fn snapshot(&mut self, name: &str) -> Result<SnapshotInfo> {
+        snapshot::validate_name(name)?;
+        if snapshot::path(&self.data_dir, name).exists() {
+            return Err(SnapshotError::AlreadyExists(name.to_string()).into());
+        }
+        let manifest = self.write_checkpoint()?;
+        snapshot::create(&self.data_dir, name, &manifest, self.documents.len())
+    }
+
+    /// Every snapshot of the data directory, oldest first.
+    pub fn snapshots(&self) -> Result<Vec<SnapshotInfo>> {
+        snapshot::list(&self.data_dir)
+    }
+
+    /// Deletes the snapshot `name`, returning whether it existed.
+    pub fn delete_snapshot(&self, name: &str) -> Result<bool> {
+        snapshot::delete(&self.data_dir, name)
+    }
+
+    /// The document segments the merge policy would merge next, if any.
+    pub fn plan_merge(&self) -> Option<Vec<u32>> {
+        self.options.merge_policy.select(&self.documents.sealed_segments())
+    }
+
+    /// Seals the active document segment and returns every sealed segment,
+    /// to merge all documents written so far into one.
+    pub fn plan_compaction(&mut self) -> Result<Vec<u32>> {
+        self.documents.seal()?;
+        Ok(self.documents.sealed_segments().iter().map(|segment| segment.id).collect())
+    }
+
+    /// Copies the live documents of `segments` into a new segment. Needs
+    /// only shared access, so searches and reads can go on while it runs.
+    pub fn prepare_merge(&self, segments: &[u32]) -> Result<MergedSegment> {
+        self.documents.prepare_merge(segments)
+    }
+
+    /// Swaps a prepared merge in for its source segments, then records the
+    /// swap next to the checkpoint so that it survives a restart and the sources can be
+    /// deleted. Only the segments involved are written down, so this is
+    /// quick however many documents were merged.
+    pub fn finish_merge(&mut self, merged: MergedSegment) -> Result<MergeStats> {
+        let (stats, record) = self.documents.install_merge(merged)?;
+        // Without a checkpoint, recovery rebuilds every segment from the
+        // WAL and needs none of them.
+        if let Some(lsn) = self.checkpoint_lsn {
+            checkpoint::record_merge(&self.data_dir, lsn, record)?;
+        }
+        self.documents.remove_retired()?;
+        Ok(stats)
+    }
+
+    /// Runs the merge the merge policy calls for, if any.
+    pub fn merge(&mut self) -> Result<Option<MergeStats>> {
+        let Some(segments) = self.plan_merge() else {
+            return Ok(None);
+        };
+        let merged = self.prepare_merge(&segments)?;
+        self.finish_merge(merged).map(Some)
+    }
+
+    /// Merges every document segment into one, leaving no dead records.
+    pub fn compact(&mut self) -> Result<MergeStats> {
+        let segments = self.plan_compaction()?;
+        if segments.is_empty() {
+            return Ok(MergeStats::default());
+        }
+        let merged = self.prepare_merge(&segments)?;
+        self.finish_merge(merged)
+    }
+
+    /// Whether the WAL has outgrown `checkpoint_wal_bytes`. Cheap enough to
+    /// poll under a read lock.
+    pub fn checkpoint_due(&self) -> Result<bool> {
+        match self.options.checkpoint_wal_bytes {
+            Some(limit) => Ok(self.wal.byte_len()? > limit),
+            None => Ok(false),
+        }
+    }
+
+    /// Takes a checkpoint if one is due, returning its LSN.
+    pub fn maybe_checkpoint(&mut self) -> Result<Option<u64>> {
+        if !self.checkpoint_due()? {
+            return Ok(None);
+        }
+        self.checkpoint().map(Some)
+    }
+
+    /// How durable writes are unless a caller asks otherwise.
+    pub fn durability(&self) -> Durability {
+        self.options.durability
+    }
+
+    /// Runs `write` without waiting for what it logs to be durable, and
+    /// returns a `Commit` to wait on instead. Callers that share the storage
+    /// behind a lock can release it before waiting, so that concurrent
+    /// writes are covered by one fsync.
+    pub fn deferred<T>(&mut self, write: impl FnOnce(&mut Self) -> T) -> (T, Commit) {
+        self.deferred = true;
+        let result = write(self);
+        self.deferred = false;
+        (result, self.commit_point())
+    }
+
+    fn commit_point(&self) -> Commit {
+        Commit::new(
+            self.wal.syncer(),
+            self.wal.next_lsn(),
+            self.options.durability,
+            self.options.group_commit_latency,
+        )
+    }
+
+    /// Waits until what was just logged is as durable as configured, unless
+    /// the write is `deferred`. Holding `&mut self`, there are no concurrent
+    /// writes to wait for, so a group commit does not wait for them.
+    fn make_durable(&self) -> Result<()> {
+        if self.deferred || self.options.durability == Durability::Async {
+            return Ok(());
+        }
+        self.wal.syncer().wait(self.wal.next_lsn(), Duration::ZERO)
+    }
+
+    /// The syncer that fsyncs the WAL for every write.
+    pub fn wal_syncer(&self) -> Arc<WalSyncer> {
+        self.wal.syncer()
+    }
+
+    /// What was recovered from the WAL on startup, including anything that
+    /// had to be discarded.
+    pub fn recovery_report(&self) -> &RecoveryReport {
+        &self.recovery
+    }
+
+    /// Applies a logged entry to the document store and in-memory state,
+    /// returning whether it changed anything.
+    fn apply(&mut self, entry: WalEntry) -> Result<bool> {
+        match entry {
+            WalEntry::Document(doc) => {
+                self.apply_store(doc)?;
+                Ok(true)
+            }
+            WalEntry::Delete(id) => self.apply_delete(&id),
+            WalEntry::Index(index) => {
+                self.apply_store_index(index)?;
+                Ok(true)
+            }
+            WalEntry::DeleteIndex(id) => Ok(self.apply_delete_index(&id)),
+            WalEntry::AddToIndex { index_id, doc_id } => self.apply_add_to_index(&index_id, &doc_id),
+            WalEntry::RemoveFromIndex { index_id, doc_id } => self.apply_remove_from_index(&index_id, &doc_id),
+            WalEntry::Patch { id, .. } | WalEntry::Replace { id, .. } => {
+                // Only updates to stored documents are logged, but one that finds
+                // nothing changes nothing.
+                let Some(mut doc) = self.documents.get(&id)? else {
+                    return Ok(false);
+                };
+                staging::update(&mut doc, &entry);
+                self.apply_store(doc)?;
+                Ok(true)
+            }
+            WalEntry::BeginBatch | WalEntry::CommitBatch | WalEntry::AbortBatch => Ok(false),
+        }
+    }
+
+    /// Logs and applies a single op.
+    fn write(&mut self, op: WriteOp) -> Result<bool> {
+        match Staging::new(&self.documents, &self.indices).prepare(op)? {
+            Some(entry) => {
+                self.wal.write_entry(&entry)?;
+                let changed = self.apply(entry)?;
+                self.make_durable()?;
+                Ok(changed)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Checks `ops` in order, each against the effects of those before it.
+    /// Returns the entries carrying out the valid ones, and whether each op
+    /// would change anything or why it is invalid.
+    fn stage(&self, ops: Vec<WriteOp>) -> (Vec<WalEntry>, Vec<Result<bool>>) {
+        let mut staging = Staging::new(&self.documents, &self.indices);
+        let mut entries = Vec::new();
+        let mut results = Vec::with_capacity(ops.len());
+        for op in ops {
+            match staging.prepare(op) {
+                Ok(Some(entry)) => {
+                    staging.stage(&entry);
+                    entries.push(entry);
+                    results.push(Ok(true));
+                }
+                Ok(None) => results.push(Ok(false)),
+                Err(err) => results.push(Err(err)),
+            }
+        }
+        (entries, results)
+    }
+
+    /// Logs `entries` as one batch, then applies them.
+    fn commit(&mut self, entries: Vec<WalEntry>) -> Result<()> {
+        self.wal.write_batch(&entries)?;
+        for entry in entries {
+            self.apply(entry)?;
+        }
+        self.make_durable()
+    }
+
+    /// Like `write_batch`, except that an invalid op is skipped, failing on
+    /// its own, and the valid ones are still written together.
+    pub fn write_each(&mut self, ops: Vec<WriteOp>) -> Result<Vec<Result<bool>>> {
+        let (entries, results) = self.stage(ops);
+        self.commit(entries)?;
+        Ok(results)
+    }
+
+    fn apply_store(&mut self, doc: ReviewDocument) -> Result<()> {
+        // A deleted version has no postings left, so only a live one's go.
+        // They are only touched once the new version is stored, so a
+        // failed write leaves them matching the store.
+        let old = self.documents.latest(&doc.id)?;
+        self.documents.put(&doc)?;
+        if let Some(old) = old {
+            self.remove_postings(&old);
+        }
+        for (index_id, postings) in self.index_postings.iter_mut() {
+            // Postings can outlive their index while a replay drops it.
+            if self.indices.get(index_id).is_some_and(|index| index.contains(&doc.id)) {
+                postings.add_document(&doc);
+            }
+        }
+        self.inverted_index.add_document(&doc);
+        Ok(())
+    }
+
+    /// Marks `id` deleted, drops it from every index and removes its
+    /// postings, so that it stops counting towards scoring statistics and
+    /// suggestions at once. Its record stays until a merge purges it.
+    fn apply_delete(&mut self, id: &Uuid) -> Result<bool> {
+        let Some(doc) = self.documents.get(id)? else {
+            return Ok(false);
+        };
+        self.documents.delete(id);
+        self.remove_postings(&doc);
+        for index in self.indices.values_mut() {
+            index.remove_document(id);
+        }
+        Ok(true)
+    }
+
+    /// Removes `doc`, the version that was indexed, from every set of
+    /// postings holding it.
+    fn remove_postings(&mut self, doc: &ReviewDocument) {
+        let all_postings = std::iter::once(&mut self.inverted_index).chain(self.index_postings.values_mut());
+        for postings in all_postings {
+            if postings.contains(&doc.id) {
+                postings.remove_document(doc);
+            }
+        }
+    }
+
+    /// Records `index` and brings its postings in line with its membership.
+    /// Postings are rebuilt from scratch when the index is new or its
+    /// analyzer changed, and updated for the membership difference otherwise.
+    fn apply_store_index(&mut self, index: Index) -> Result<()> {
+        let existing = self
+            .indices
+            .get(&index.id)
+            .filter(|old| old.analyzer == index.analyzer && old.mappings == index.mappings);
+
+        match existing {
+            Some(old) => {
+                let postings = self.index_postings.get_mut(&index.id).unwrap();
+                for id in old.documents.difference(&index.documents) {
+                    if let Some(doc) = self.documents.get(id)? {
+                        postings.remove_document(&doc);
+                    }
+                }
+                for id in index.documents.difference(&old.documents) {
+                    if let Some(doc) = self.documents.get(id)? {
+                        postings.add_document(&doc);
+                    }
+                }
+            }
+            None => {
+                let mut postings =
+                    InvertedIndex::new(Box::new(index.analyzer.build())).with_mappings(index.mappings.clone());
+                for id in &index.documents {
+                    if let Some(doc) = self.documents.get(id)? {
+                        postings.add_document(&doc);
+                    }
                 }
+                self.index_postings.insert(index.id, postings);
             }
         }
+        self.indices.insert(index.id, index);
         Ok(())
     }
 
+    fn apply_delete_index(&mut self, id: &Uuid) -> bool {
+        self.index_postings.remove(id);
+        self.indices.remove(id).is_some()
+    }
+
+    fn apply_add_to_index(&mut self, index_id: &Uuid, doc_id: &Uuid) -> Result<bool> {
+        let (Some(index), Some(doc)) = (self.indices.get_mut(index_id), self.documents.get(doc_id)?) else {
+            return Ok(false);
+        };
+        if !index.add_document(*doc_id) {
+            return Ok(false);
+        }
+        self.index_postings.get_mut(index_id).unwrap().add_document(&doc);
+        Ok(true)
+    }
+
+    fn apply_remove_from_index(&mut self, index_id: &Uuid, doc_id: &Uuid) -> Result<bool> {
+        let Some(index) = self.indices.get_mut(index_id) else {
+            return Ok(false);
+        };
+        if !index.remove_document(doc_id) {
+            return Ok(false);
+        }
+        if let Some(doc) = self.documents.get(doc_id)? {
+            self.index_postings.get_mut(index_id).unwrap().remove_document(&doc);
+        }
+        Ok(true)
+    }
+
+    /// A searcher over the postings of `index_id` if given, which cover
+    /// only its members and use its analyzer, mappings and statistics, or
+    /// otherwise over those of every document. Returns `None` if the index
+    /// does not exist.
+    pub fn searcher(&self, index_id: Option<&Uuid>) -> Option<Searcher<'_>> {
+        let postings = match index_id {
+            Some(index_id) => self.index_postings.get(index_id)?,
+            None => &self.inverted_index,
+        };
+        Some(Searcher::new(postings, &self.documents))
+    }
+
+    /// Highlighter for hits of `query` over every document.
+    pub fn highlighter(&self, query: &Query) -> Highlighter<'_> {
+        self.inverted_index.highlighter(query)
+    }
+
+    /// Completions for `prefix` from the vocabulary of every document.
+    pub fn suggest(&self, prefix: &str, limit: usize) -> Vec<Suggestion> {
+        self.inverted_index.suggest(prefix, limit)
+    }
+
+    /// Completions for `prefix` from the vocabulary of `index_id`'s members.
+    /// Returns `None` if the index does not exist.
+    pub fn suggest_in_index(&self, index_id: &Uuid, prefix: &str, limit: usize) -> Option<Vec<Suggestion>> {
+        self.index_postings.get(index_id).map(|postings| postings.suggest(prefix, limit))
+    }
+
+    /// Searches only the members of `index_id`, using that index's analyzer
+    /// and mappings. Returns `None` if the index does not exist.
+    pub fn search_in_index(
+        &self,
+        index_id: &Uuid,
+        query: &Query,
+        sort: &[SortField],
+    ) -> Result<Option<Vec<ScoredDocument>>> {
+        Ok(self.searcher(Some(index_id)).map(|searcher| searcher.search(query, sort)))
+    }
+
+    /// Computes `aggregation` over hits of a search across every document.
+    pub fn aggregate(&self, hits: &[ScoredDocument], aggregation: &Aggregation) -> AggregationResult {
+        self.inverted_index.aggregate(hits, aggregation)
+    }
+
+    /// Where `hit` falls among results over every document sorted by `sort`,
+    /// for resuming a search after it.
+    pub fn cursor(&self, hit: &ScoredDocument, sort: &[SortField]) -> Cursor {
+        self.inverted_index.cursor(hit, sort)
+    }
+
+    pub fn data_dir(&self) -> &Path {
+        &self.data_dir
+    }
+
     pub fn flush(&mut self) -> Result<()> {
         self.wal.sync()
     }
 }
 
 impl StorageEngine for MmapStorage {
+    /// Fails with a `MappingError` if `doc` belongs to an index whose
+    /// mappings its metadata violates.
     fn store_document(&mut self, doc: &ReviewDocument) -> Result<()> {
-        self.wal.write_document_entry(doc)?;
-        self.documents.insert(doc.id, doc.clone());
+        self.write(WriteOp::Store(doc.clone()))?;
         Ok(())
     }
 
     fn get_document(&self, id: &Uuid) -> Result<Option<ReviewDocument>> {
-        Ok(self.documents.get(id).cloned())
+        self.documents.get(id)
     }
 
     fn delete_document(&mut self, id: &Uuid) -> Result<bool> {
-        self.wal.write_delete_entry(id)?;
-        Ok(self.documents.remove(id).is_some())
+        self.write(WriteOp::Delete(*id))
     }
 
     fn list_documents(&self) -> Result<Vec<Uuid>> {
-        Ok(self.documents.keys().copied().collect())
+        Ok(self.documents.ids().copied().collect())
+    }
+
+    fn write_batch(&mut self, ops: Vec<WriteOp>) -> Result<Vec<bool>> {
+        let (entries, results) = self.stage(ops);
+        let results = results.into_iter().collect::<Result<Vec<bool>>>()?;
+        self.commit(entries)?;
+        Ok(results)
     }
 }
 
 impl IndexStorage for MmapStorage {
+    /// Fails with a `MappingError` if a member document violates the
+    /// index's mappings.
     fn store_index(&mut self, index: &Index) -> Result<()> {
-        self.wal.write_index_entry(index)?;
-        self.indices.insert(index.id, index.clone());
+        self.write(WriteOp::StoreIndex(index.clone()))?;
         Ok(())
     }
 
@@ -92,4 +696,30 @@ impl IndexStorage for MmapStorage {
     fn list_indices(&self) -> Result<Vec<Index>> {
         Ok(self.indices.values().cloned().collect())
     }
+
+    fn delete_index(&mut self, id: &Uuid) -> Result<bool> {
+        self.write(WriteOp::DeleteIndex(*id))
+    }
+
+    /// Fails if either does not exist, or with a `MappingError` if the
+    /// document violates the index's mappings.
+    fn add_document(&mut self, index_id: &Uuid, doc_id: &Uuid) -> Result<bool> {
+        self.write(WriteOp::AddToIndex {
+            index_id: *index_id,
+            doc_id: *doc_id,
+        })
+    }
+
+    fn remove_document(&mut self, index_id: &Uuid, doc_id: &Uuid) -> Result<bool> {
+        self.write(WriteOp::RemoveFromIndex {
+            index_id: *index_id,
+            doc_id: *doc_id,
+        })
+    }
+}
+
+impl SearchIndex for MmapStorage {
+    fn search_sorted(&self, query: &Query, sort: &[SortField]) -> Result<Vec<ScoredDocument>> {
+        Ok(Searcher::new(&self.inverted_index, &self.documents).search(query, sort))
+    }
 }
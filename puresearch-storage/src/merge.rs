@@ -0,0 +1,105 @@
+//! Choosing which document segments to merge.
+//!
+//! Replaced and deleted documents leave dead records in their segments.
+//! Merging copies the live records of several sealed segments into one new
+//! segment and drops the old ones, so that disk usage tracks live data.
+
+use serde::{Deserialize, Serialize};
+
+/// Size and live data of a sealed segment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SegmentStats {
+    pub id: u32,
+    pub len: u64,
+    /// Bytes of records holding the latest version of a stored document.
+    pub live_bytes: u64,
+}
+
+impl SegmentStats {
+    pub fn dead_bytes(&self) -> u64 {
+        self.len - self.live_bytes
+    }
+}
+
+/// A tiered merge policy.
+///
+/// Segments are grouped into tiers by live size, each tier holding segments
+/// up to `segments_per_tier` times larger than the one below, starting from
+/// `floor_segment_bytes`. Once a tier has `segments_per_tier` segments they
+/// are merged into one of the next tier, so there are few segments at every
+/// size and each record is copied about once per tier. A segment that is
+/// mostly dead is merged regardless of its tier.
+#[derive(Debug, Clone)]
+pub struct MergePolicy {
+    pub segments_per_tier: usize,
+    /// Segments with less live data than this all count as the lowest tier.
+    pub floor_segment_bytes: u64,
+    /// Fraction of dead bytes past which a segment is merged on its own
+    /// account.
+    pub max_dead_ratio: f64,
+}
+
+impl Default for MergePolicy {
+    fn default() -> Self {
+        Self {
+            segments_per_tier: 10,
+            floor_segment_bytes: 1024 * 1024,
+            max_dead_ratio: 0.5,
+        }
+    }
+}
+
+impl MergePolicy {
+    /// The segments to merge next, out of `segments`, or `None` if none need
+    /// merging.
+    pub fn select(&self, segments: &[SegmentStats]) -> Option<Vec<u32>> {
+        let mut selected: Vec<u32> = segments
+            .iter()
+            .filter(|segment| segment.len > 0 && segment.dead_bytes() as f64 > segment.len as f64 * self.max_dead_ratio)
+            .map(|segment| segment.id)
+            .collect();
+
+        let mut tiers: Vec<Vec<&SegmentStats>> = Vec::new();
+        for segment in segments.iter().filter(|segment| !selected.contains(&segment.id)) {
+            let tier = self.tier(segment.live_bytes);
+            if tiers.len() <= tier {
+                tiers.resize(tier + 1, Vec::new());
+            }
+            tiers[tier].push(segment);
+        }
+        if let Some(tier) = tiers.iter_mut().find(|tier| tier.len() >= self.segments_per_tier.max(2)) {
+            tier.sort_by_key(|segment| segment.live_bytes);
+            selected.extend(tier.iter().take(self.segments_per_tier.max(2)).map(|segment| segment.id));
+        }
+
+        (!selected.is_empty()).then(|| {
+            selected.sort_unstable();
+            selected
+        })
+    }
+
+    fn tier(&self, live_bytes: u64) -> usize {
+        let floor = self.floor_segment_bytes.max(1);
+        let factor = self.segments_per_tier.max(2) as u64;
+        let mut tier = 0;
+        let mut limit = floor;
+        while live_bytes > limit {
+            tier += 1;
+            limit = limit.saturating_mul(factor);
+        }
+        tier
+    }
+}
+
+/// What a merge did.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MergeStats {
+    /// Segments merged away.
+    pub segments_merged: usize,
+    /// Live documents copied into the new segment.
+    pub documents: usize,
+    /// Deleted documents whose records were dropped.
+    pub deletes_purged: usize,
+    /// Disk space freed.
+    pub bytes_reclaimed: u64,
+}
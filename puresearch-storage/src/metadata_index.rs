@@ -0,0 +1,244 @@
+use puresearch_core::mapping::{FieldType, FieldValue, Mappings};
+use puresearch_core::query::Filter;
+use puresearch_core::sort::SortValue;
+use puresearch_core::ReviewDocument;
+use std::borrow::Borrow;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ops::Bound;
+use uuid::Uuid;
+
+/// A metadata value that parses as a finite number, ordered numerically.
+#[derive(Debug, Clone, Copy)]
+struct Number(f64);
+
+impl Number {
+    fn parse(value: &str) -> Option<Self> {
+        value.trim().parse::<f64>().ok().filter(|n| n.is_finite()).map(Number)
+    }
+}
+
+impl PartialEq for Number {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Number {}
+
+impl PartialOrd for Number {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Number {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+/// The documents holding each value of one metadata key.
+#[derive(Default)]
+struct FieldValues {
+    values: BTreeMap<String, HashSet<Uuid>>,
+    /// The same documents keyed by the values that are numbers.
+    numbers: BTreeMap<Number, HashSet<Uuid>>,
+    /// Each document's value, for counting the values of a set of hits.
+    docs: HashMap<Uuid, String>,
+}
+
+impl FieldValues {
+    fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+}
+
+/// Exact metadata values, for filters.
+///
+/// Values are kept as written, without analysis, in sorted maps from value to
+/// documents: equality and `in` filters are lookups and ranges are a scan of
+/// the keys between the bounds. Values that are numbers are indexed a second
+/// time in numeric order, so a range from 2 to 10 includes 9.
+///
+/// Keys with a numeric type in `mappings` (including dates) are always
+/// filtered as numbers, and other mapped keys always as strings. Unmapped
+/// keys are compared as numbers when both the value and the filter bounds
+/// look like numbers.
+#[derive(Default)]
+pub struct MetadataIndex {
+    fields: HashMap<String, FieldValues>,
+    mappings: Mappings,
+}
+
+impl MetadataIndex {
+    pub fn new(mappings: Mappings) -> Self {
+        Self {
+            fields: HashMap::new(),
+            mappings,
+        }
+    }
+
+    /// `value` of `field` as a number, if it is ordered as one.
+    fn number(&self, field: &str, value: &str) -> Option<Number> {
+        match self.mappings.get(field) {
+            Some(field_type) => match field_type.parse(value) {
+                Ok(FieldValue::Number(n)) => Some(Number(n)),
+                _ => None,
+            },
+            None => Number::parse(value),
+        }
+    }
+
+    fn is_numeric(&self, field: &str) -> bool {
+        self.mappings.get(field).is_some_and(FieldType::is_numeric)
+    }
+
+    /// The bound as a number, or `None` if it is set and not a number.
+    fn parse_bound(&self, field: &str, bound: &Bound<String>) -> Option<Bound<Number>> {
+        match bound {
+            Bound::Included(value) => self.number(field, value).map(Bound::Included),
+            Bound::Excluded(value) => self.number(field, value).map(Bound::Excluded),
+            Bound::Unbounded => Some(Bound::Unbounded),
+        }
+    }
+
+    pub fn add_document(&mut self, doc: &ReviewDocument) {
+        for (field, value) in &doc.metadata {
+            let number = self.number(field, value);
+            let values = self.fields.entry(field.clone()).or_default();
+            values.values.entry(value.clone()).or_default().insert(doc.id);
+            values.docs.insert(doc.id, value.clone());
+            if let Some(number) = number {
+                values.numbers.entry(number).or_default().insert(doc.id);
+            }
+        }
+    }
+
+    pub fn remove_document(&mut self, doc: &ReviewDocument) {
+        for (field, value) in &doc.metadata {
+            let number = self.number(field, value);
+            let Some(values) = self.fields.get_mut(field) else {
+                continue;
+            };
+            remove_from(&mut values.values, value, &doc.id);
+            values.docs.remove(&doc.id);
+            if let Some(number) = number {
+                remove_from(&mut values.numbers, &number, &doc.id);
+            }
+            if values.is_empty() {
+                self.fields.remove(field);
+            }
+        }
+    }
+
+    /// How many of `ids` hold each value of `field`, in value order, leaving
+    /// out values none of them hold. Values of numeric fields are counted as
+    /// numbers, so "4" and "4.0" are one value. Looks up each of `ids`, so
+    /// the cost follows the number of hits rather than of distinct values.
+    pub fn value_counts<'a>(&self, field: &str, ids: impl IntoIterator<Item = &'a Uuid>) -> Vec<(SortValue, u64)> {
+        let Some(values) = self.fields.get(field) else {
+            return Vec::new();
+        };
+        let held = ids.into_iter().filter_map(|id| values.docs.get(id));
+        if self.is_numeric(field) {
+            let mut counts: BTreeMap<Number, u64> = BTreeMap::new();
+            for number in held.filter_map(|value| self.number(field, value)) {
+                *counts.entry(number).or_default() += 1;
+            }
+            counts.into_iter().map(|(number, count)| (SortValue::Number(number.0), count)).collect()
+        } else {
+            let mut counts: BTreeMap<&str, u64> = BTreeMap::new();
+            for value in held {
+                *counts.entry(value).or_default() += 1;
+            }
+            counts
+                .into_iter()
+                .map(|(value, count)| (SortValue::String(value.to_string()), count))
+                .collect()
+        }
+    }
+
+    /// The documents satisfying `filter`.
+    pub fn evaluate(&self, filter: &Filter) -> HashSet<Uuid> {
+        let field = match filter {
+            Filter::Term { field, .. }
+            | Filter::In { field, .. }
+            | Filter::Range { field, .. }
+            | Filter::Exists { field } => field,
+        };
+        let Some(values) = self.fields.get(field) else {
+            return HashSet::new();
+        };
+
+        // Numeric fields match by value, so "4" finds "4.0".
+        let numeric = self.is_numeric(field);
+        let lookup = |value: &String| {
+            if numeric {
+                self.number(field, value).and_then(|number| values.numbers.get(&number))
+            } else {
+                values.values.get(value)
+            }
+        };
+
+        match filter {
+            Filter::Term { value, .. } => lookup(value).cloned().unwrap_or_default(),
+            Filter::In { values: wanted, .. } => wanted.iter().filter_map(lookup).flatten().copied().collect(),
+            Filter::Range { lower, upper, .. } => {
+                let mapped = self.mappings.contains_key(field);
+                let numeric_lower = self.parse_bound(field, lower);
+                let numeric_upper = self.parse_bound(field, upper);
+                match (numeric_lower, numeric_upper) {
+                    (Some(lower), Some(upper)) if numeric || !mapped => {
+                        union_range(&values.numbers, lower.as_ref(), upper.as_ref())
+                    }
+                    // A bound that is not a number cannot match a number.
+                    _ if numeric => HashSet::new(),
+                    _ => union_range(&values.values, as_str_bound(lower), as_str_bound(upper)),
+                }
+            }
+            Filter::Exists { .. } => values.values.values().flatten().copied().collect(),
+        }
+    }
+}
+
+fn remove_from<K, Q>(map: &mut BTreeMap<K, HashSet<Uuid>>, key: &Q, id: &Uuid)
+where
+    K: Ord + Borrow<Q>,
+    Q: Ord + ?Sized,
+{
+    if let Some(docs) = map.get_mut(key) {
+        docs.remove(id);
+        if docs.is_empty() {
+            map.remove(key);
+        }
+    }
+}
+
+fn as_str_bound(bound: &Bound<String>) -> Bound<&str> {
+    bound.as_ref().map(String::as_str)
+}
+
+/// Every document under a key between `lower` and `upper`.
+fn union_range<K, Q>(map: &BTreeMap<K, HashSet<Uuid>>, lower: Bound<&Q>, upper: Bound<&Q>) -> HashSet<Uuid>
+where
+    K: Ord + Borrow<Q>,
+    Q: Ord + ?Sized,
+{
+    if is_empty_range(lower, upper) {
+        return HashSet::new();
+    }
+    map.range::<Q, _>((lower, upper)).flat_map(|(_, docs)| docs).copied().collect()
+}
+
+/// Whether no key can lie between the bounds. `BTreeMap::range` panics on
+/// such ranges rather than returning nothing.
+fn is_empty_range<Q: Ord + ?Sized>(lower: Bound<&Q>, upper: Bound<&Q>) -> bool {
+    match (lower, upper) {
+        (Bound::Included(lower), Bound::Included(upper)) => lower > upper,
+        (Bound::Included(lower) | Bound::Excluded(lower), Bound::Included(upper) | Bound::Excluded(upper)) => {
+            lower >= upper
+        }
+        _ => false,
+    }
+}
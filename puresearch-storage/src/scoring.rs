@@ -0,0 +1,36 @@
+/// Okapi BM25 relevance scoring.
+#[derive(Debug, Clone, Copy)]
+pub struct Bm25 {
+    /// Term frequency saturation.
+    pub k1: f32,
+    /// Strength of document length normalization, from 0 (none) to 1 (full).
+    pub b: f32,
+}
+
+impl Default for Bm25 {
+    fn default() -> Self {
+        Self { k1: 1.2, b: 0.75 }
+    }
+}
+
+impl Bm25 {
+    /// Inverse document frequency of a term occurring in `doc_freq` of
+    /// `doc_count` documents. Always positive, so common terms still add a
+    /// little to the score rather than penalising a match.
+    pub fn idf(&self, doc_count: usize, doc_freq: usize) -> f32 {
+        let n = doc_count as f32;
+        let df = doc_freq as f32;
+        (1.0 + (n - df + 0.5) / (df + 0.5)).ln()
+    }
+
+    /// Contribution of one term to a document's score.
+    pub fn term_score(&self, idf: f32, term_freq: u32, doc_len: u32, avg_doc_len: f32) -> f32 {
+        let tf = term_freq as f32;
+        let norm = if avg_doc_len > 0.0 {
+            1.0 - self.b + self.b * doc_len as f32 / avg_doc_len
+        } else {
+            1.0
+        };
+        idf * (tf * (self.k1 + 1.0)) / (tf + self.k1 * norm)
+    }
+}
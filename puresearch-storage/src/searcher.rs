@@ -0,0 +1,60 @@
+use crate::doc_store::DocumentStore;
+use crate::highlight::Highlighter;
+use crate::inverted_index::{InvertedIndex, Page};
+use puresearch_core::aggregation::{Aggregation, AggregationResult};
+use puresearch_core::query::Query;
+use puresearch_core::sort::{Cursor, SortField};
+use puresearch_core::ScoredDocument;
+
+/// Searches a set of postings, leaving out deleted documents.
+///
+/// A delete removes the document's postings, but hits are still checked
+/// against the document store's deletion bitmaps, so that a search never
+/// returns a document that cannot be read.
+pub struct Searcher<'a> {
+    postings: &'a InvertedIndex,
+    documents: &'a DocumentStore,
+}
+
+impl<'a> Searcher<'a> {
+    pub fn new(postings: &'a InvertedIndex, documents: &'a DocumentStore) -> Self {
+        Self { postings, documents }
+    }
+
+    /// Returns the live documents matching `query`, in the order of
+    /// `InvertedIndex::search`.
+    pub fn search(&self, query: &Query, sort: &[SortField]) -> Vec<ScoredDocument> {
+        let mut hits = self.postings.search(query, sort);
+        hits.retain(|hit| self.documents.contains(&hit.id));
+        hits
+    }
+
+    /// The live documents matching `query`, in no particular order.
+    pub fn matches(&self, query: &Query) -> Vec<ScoredDocument> {
+        let mut hits = self.postings.matches(query);
+        hits.retain(|hit| self.documents.contains(&hit.id));
+        hits
+    }
+
+    /// The first `limit` live documents matching `query` after `after`, in
+    /// `sort` order.
+    pub fn search_page(&self, query: &Query, sort: &[SortField], after: Option<&Cursor>, limit: usize) -> Page {
+        self.postings
+            .search_page(query, sort, after, limit, |id| self.documents.contains(id))
+    }
+
+    pub fn highlighter(&self, query: &Query) -> Highlighter<'a> {
+        self.postings.highlighter(query)
+    }
+
+    /// Computes `aggregation` over `hits`, which must come from this
+    /// searcher.
+    pub fn aggregate(&self, hits: &[ScoredDocument], aggregation: &Aggregation) -> AggregationResult {
+        self.postings.aggregate(hits, aggregation)
+    }
+
+    /// Where `hit` falls among results sorted by `sort`.
+    pub fn cursor(&self, hit: &ScoredDocument, sort: &[SortField]) -> Cursor {
+        self.postings.cursor(hit, sort)
+    }
+}
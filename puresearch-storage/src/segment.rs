@@ -14,6 +14,7 @@ impl SegmentFile {
     pub fn create<P: AsRef<Path>>(path: P) -> Result<Self> {
         let file = OpenOptions::new()
             .create(true)
+            .truncate(false)
             .read(true)
             .write(true)
             .open(path)?;
@@ -54,26 +55,81 @@ impl SegmentFile {
         writer.write_all(data)?;
         writer.flush()?;
         
+        // The existing mapping stays valid for the bytes it covers; reads
+        // past it go to the file until the next `remap`.
         self.size += data.len();
-        self.mmap = None;
         
         Ok(offset)
     }
 
-    pub fn read_at(&mut self, offset: usize, len: usize) -> Result<Vec<u8>> {
-        if self.mmap.is_none() && self.size > 0 {
-            self.mmap = Some(unsafe { Mmap::map(&self.file)? });
+    /// Reads `len` bytes at `offset`: from the mapping if it covers them,
+    /// otherwise with a positioned read. A segment still being appended to
+    /// is only remapped when it is sealed, as remapping on every write would
+    /// cost more than the reads it saves, so its newest records are read
+    /// this way.
+    pub fn read_at(&self, offset: usize, len: usize) -> Result<Vec<u8>> {
+        if offset + len > self.size {
+            return Err(anyhow::anyhow!("Read beyond segment bounds"));
         }
-
-        if let Some(mmap) = &self.mmap {
-            if offset + len <= mmap.len() {
-                Ok(mmap[offset..offset + len].to_vec())
-            } else {
-                Err(anyhow::anyhow!("Read beyond segment bounds"))
+        match &self.mmap {
+            Some(mmap) if offset + len <= mmap.len() => Ok(mmap[offset..offset + len].to_vec()),
+            _ => {
+                let mut buf = vec![0u8; len];
+                read_exact_at(&self.file, &mut buf, offset as u64)?;
+                Ok(buf)
             }
+        }
+    }
+
+    /// Maps the whole file, so that reads of everything written so far are
+    /// served from memory.
+    pub fn remap(&mut self) -> Result<()> {
+        self.mmap = if self.size > 0 {
+            Some(unsafe { Mmap::map(&self.file)? })
         } else {
-            Err(anyhow::anyhow!("Cannot read from empty segment"))
+            None
+        };
+        Ok(())
+    }
+
+    /// Cuts the file back to `len` bytes.
+    pub fn truncate(&mut self, len: usize) -> Result<()> {
+        self.mmap = None;
+        self.file.set_len(len as u64)?;
+        self.size = len;
+        self.remap()
+    }
+
+    /// Frames `payload` with its length and CRC-32, as WAL records are, and
+    /// adds it to `out` for writing.
+    pub fn frame(payload: &[u8], out: &mut Vec<u8>) {
+        out.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        out.extend_from_slice(&crc32fast::hash(payload).to_le_bytes());
+        out.extend_from_slice(payload);
+    }
+
+    /// Reads back every record written framed by `frame`. Fails if one is
+    /// truncated or does not match its checksum.
+    pub fn records(&self) -> Result<Vec<Vec<u8>>> {
+        let mut records = Vec::new();
+        let mut offset = 0;
+        while offset < self.size {
+            let header = self.read_at(offset, 8)?;
+            let len = u32::from_le_bytes(header[..4].try_into().unwrap()) as usize;
+            let checksum = u32::from_le_bytes(header[4..].try_into().unwrap());
+            let payload = self.read_at(offset + 8, len)?;
+            if crc32fast::hash(&payload) != checksum {
+                return Err(anyhow::anyhow!("Checksum mismatch at segment offset {offset}"));
+            }
+            records.push(payload);
+            offset += 8 + len;
         }
+        Ok(records)
+    }
+
+    pub fn sync(&self) -> Result<()> {
+        self.file.sync_all()?;
+        Ok(())
     }
 
     pub fn len(&self) -> usize {
@@ -83,4 +139,25 @@ impl SegmentFile {
     pub fn is_empty(&self) -> bool {
         self.size == 0
     }
-}
\ No newline at end of file
+}
+
+#[cfg(unix)]
+fn read_exact_at(file: &File, buf: &mut [u8], offset: u64) -> std::io::Result<()> {
+    use std::os::unix::fs::FileExt;
+    file.read_exact_at(buf, offset)
+}
+
+#[cfg(windows)]
+fn read_exact_at(file: &File, mut buf: &mut [u8], mut offset: u64) -> std::io::Result<()> {
+    use std::os::windows::fs::FileExt;
+    while !buf.is_empty() {
+        match file.seek_read(buf, offset)? {
+            0 => return Err(std::io::ErrorKind::UnexpectedEof.into()),
+            n => {
+                buf = &mut buf[n..];
+                offset += n as u64;
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,236 @@
+//! Snapshots: named, point-in-time copies of a data directory, for backups
+//! and for rolling back to an earlier state.
+//!
+//! A snapshot is taken right after a checkpoint, so it needs no WAL: it is
+//! that checkpoint's manifest and segments plus the document segments it
+//! covers, under `snapshots/{name}/` with the data directory's layout. The
+//! files are hard links, so taking one costs no copying. Checkpoint segments
+//! are never modified once written, and document segments are only appended
+//! to, past the lengths the manifest records, so the linked files keep
+//! reading as they did when the snapshot was taken.
+//!
+//! Since document segments are shared with the live data directory, a
+//! snapshot must not be opened as a data directory itself. [`restore`]
+//! copies one into a data directory instead.
+
+use crate::checkpoint::{self, Manifest};
+use crate::doc_store::{self, SegmentState};
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+/// Directory of a data directory holding its snapshots.
+pub const DIR: &str = "snapshots";
+/// File in a snapshot describing it.
+pub const INFO: &str = "snapshot.json";
+/// File present in a data directory while a restore into it is under way.
+pub const RESTORING: &str = "restore.pending";
+
+/// A snapshot as listed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotInfo {
+    pub name: String,
+    /// Seconds since the Unix epoch.
+    pub created_at: u64,
+    /// WAL position the snapshot reflects: every write logged before it.
+    pub lsn: u64,
+    /// Number of documents stored.
+    pub documents: usize,
+}
+
+/// A snapshot that cannot be created as asked.
+#[derive(Debug, Clone, Error, PartialEq, Eq)]
+pub enum SnapshotError {
+    #[error("invalid snapshot name '{0}': use letters, digits, '-' and '_'")]
+    InvalidName(String),
+    #[error("snapshot '{0}' already exists")]
+    AlreadyExists(String),
+}
+
+/// Fails unless `name` can name a snapshot: a non-empty run of ASCII
+/// letters, digits, `-` and `_`, so that it is a plain directory name.
+pub fn validate_name(name: &str) -> Result<(), SnapshotError> {
+    let valid = !name.is_empty()
+        && name.len() <= 128
+        && name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_');
+    if valid {
+        Ok(())
+    } else {
+        Err(SnapshotError::InvalidName(name.to_string()))
+    }
+}
+
+/// The directory of the snapshot `name` of `data_dir`.
+pub fn path(data_dir: &Path, name: &str) -> PathBuf {
+    data_dir.join(DIR).join(name)
+}
+
+/// Snapshots `data_dir` as of the checkpoint `manifest`, which must be its
+/// current one, under `name`.
+pub fn create(data_dir: &Path, name: &str, manifest: &Manifest, documents: usize) -> Result<SnapshotInfo> {
+    validate_name(name)?;
+    let dir = path(data_dir, name);
+    if dir.exists() {
+        return Err(SnapshotError::AlreadyExists(name.to_string()).into());
+    }
+    // Built under a temporary name, so that a crash part way through leaves
+    // nothing that lists as a snapshot.
+    let new_dir = dir.with_extension("new");
+    if new_dir.exists() {
+        std::fs::remove_dir_all(&new_dir)?;
+    }
+    std::fs::create_dir_all(new_dir.join(checkpoint::DIR))?;
+    std::fs::create_dir_all(new_dir.join(doc_store::DIR))?;
+
+    for file in checkpoint_files(manifest) {
+        std::fs::hard_link(data_dir.join(file), new_dir.join(file))?;
+    }
+    for segment in &manifest.segments {
+        let file = segment_file(segment);
+        std::fs::hard_link(data_dir.join(&file), new_dir.join(&file))?;
+    }
+    write_synced(&new_dir.join(checkpoint::MANIFEST), &serde_json::to_vec_pretty(manifest)?)?;
+    let info = SnapshotInfo {
+        name: name.to_string(),
+        created_at: std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)?
+            .as_secs(),
+        lsn: manifest.lsn,
+        documents,
+    };
+    write_synced(&new_dir.join(INFO), &serde_json::to_vec_pretty(&info)?)?;
+    for sub_dir in [checkpoint::DIR, doc_store::DIR] {
+        File::open(new_dir.join(sub_dir))?.sync_all()?;
+    }
+    File::open(&new_dir)?.sync_all()?;
+
+    std::fs::rename(&new_dir, &dir)?;
+    File::open(data_dir.join(DIR))?.sync_all()?;
+    Ok(info)
+}
+
+/// Every snapshot of `data_dir`, oldest first.
+pub fn list(data_dir: &Path) -> Result<Vec<SnapshotInfo>> {
+    let dir = data_dir.join(DIR);
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+    let mut snapshots = Vec::new();
+    for entry in std::fs::read_dir(&dir)? {
+        let path = entry?.path();
+        let is_snapshot = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| validate_name(name).is_ok());
+        if is_snapshot {
+            snapshots.push(read_info(&path)?);
+        }
+    }
+    snapshots.sort_by(|a, b| (a.created_at, &a.name).cmp(&(b.created_at, &b.name)));
+    Ok(snapshots)
+}
+
+/// Deletes the snapshot `name` of `data_dir`, returning whether it existed.
+pub fn delete(data_dir: &Path, name: &str) -> Result<bool> {
+    validate_name(name)?;
+    let dir = path(data_dir, name);
+    if !dir.exists() {
+        return Ok(false);
+    }
+    std::fs::remove_dir_all(&dir)?;
+    Ok(true)
+}
+
+/// Replaces the contents of `data_dir` with the snapshot at `snapshot_dir`,
+/// which may be one of its own. Snapshots already in `data_dir` are kept.
+/// Fails if the data directory is open.
+///
+/// Until the restore finishes, [`RESTORING`] in `data_dir` stops storage
+/// from opening it; if one is interrupted, run it again.
+pub fn restore(snapshot_dir: &Path, data_dir: &Path) -> Result<SnapshotInfo> {
+    let info = read_info(snapshot_dir)?;
+    let manifest_path = snapshot_dir.join(checkpoint::MANIFEST);
+    let manifest_bytes = std::fs::read(&manifest_path)?;
+    let manifest: Manifest = serde_json::from_slice(&manifest_bytes)
+        .with_context(|| format!("invalid checkpoint manifest {}", manifest_path.display()))?;
+
+    std::fs::create_dir_all(data_dir)?;
+    let _lock = crate::lock_data_dir(data_dir)?;
+    write_synced(&data_dir.join(RESTORING), info.name.as_bytes())?;
+    File::open(data_dir)?.sync_all()?;
+
+    for file in ["wal.log", checkpoint::MANIFEST, checkpoint::MERGES] {
+        let path = data_dir.join(file);
+        if path.exists() {
+            std::fs::remove_file(path)?;
+        }
+    }
+    for sub_dir in [checkpoint::DIR, doc_store::DIR] {
+        let path = data_dir.join(sub_dir);
+        if path.exists() {
+            std::fs::remove_dir_all(&path)?;
+        }
+        std::fs::create_dir_all(&path)?;
+    }
+
+    // Copied rather than linked, as the restored store appends to and
+    // truncates its document segments.
+    for file in checkpoint_files(&manifest) {
+        copy_synced(&snapshot_dir.join(file), &data_dir.join(file), None)?;
+    }
+    for segment in &manifest.segments {
+        let file = segment_file(segment);
+        let source = snapshot_dir.join(&file);
+        if std::fs::metadata(&source)?.len() < segment.len {
+            bail!("document segment {} is shorter than its snapshot", source.display());
+        }
+        copy_synced(&source, &data_dir.join(&file), Some(segment.len))?;
+    }
+    for sub_dir in [checkpoint::DIR, doc_store::DIR] {
+        File::open(data_dir.join(sub_dir))?.sync_all()?;
+    }
+    write_synced(&data_dir.join(checkpoint::MANIFEST), &manifest_bytes)?;
+    File::open(data_dir)?.sync_all()?;
+
+    std::fs::remove_file(data_dir.join(RESTORING))?;
+    File::open(data_dir)?.sync_all()?;
+    Ok(info)
+}
+
+fn read_info(snapshot_dir: &Path) -> Result<SnapshotInfo> {
+    let path = snapshot_dir.join(INFO);
+    let bytes = std::fs::read(&path).with_context(|| format!("{} is not a snapshot", snapshot_dir.display()))?;
+    serde_json::from_slice(&bytes).with_context(|| format!("invalid snapshot description {}", path.display()))
+}
+
+/// The checkpoint segments of `manifest`, relative to the data directory.
+fn checkpoint_files(manifest: &Manifest) -> impl Iterator<Item = &String> {
+    [&manifest.documents, &manifest.indices].into_iter().chain(&manifest.deletes)
+}
+
+/// The file of a document segment, relative to the data directory.
+fn segment_file(segment: &SegmentState) -> PathBuf {
+    Path::new(doc_store::DIR).join(doc_store::file_name(segment.id))
+}
+
+fn write_synced(path: &Path, bytes: &[u8]) -> Result<()> {
+    let mut file = File::create(path)?;
+    file.write_all(bytes)?;
+    file.sync_all()?;
+    Ok(())
+}
+
+/// Copies `source` to `target`, or only its first `len` bytes.
+fn copy_synced(source: &Path, target: &Path, len: Option<u64>) -> Result<()> {
+    let mut source = File::open(source)?;
+    let mut target = File::create(target)?;
+    match len {
+        Some(len) => std::io::copy(&mut (&mut source).take(len), &mut target)?,
+        None => std::io::copy(&mut source, &mut target)?,
+    };
+    target.sync_all()?;
+    Ok(())
+}
@@ -0,0 +1,200 @@
+use crate::doc_store::DocumentStore;
+use crate::wal::WalEntry;
+use anyhow::Result;
+use puresearch_core::storage::WriteOp;
+use puresearch_core::{Index, ReviewDocument};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+/// The stored documents and indices as a batch of writes would leave them.
+///
+/// Each op in a batch is checked against the effects of the ops before it,
+/// which are kept here as an overlay on the stored state until the whole
+/// batch has been logged and can be applied.
+pub(crate) struct Staging<'a> {
+    documents: &'a DocumentStore,
+    indices: &'a HashMap<Uuid, Index>,
+    /// Documents written so far, `None` where deleted.
+    staged_documents: HashMap<Uuid, Option<ReviewDocument>>,
+    /// Indices written or whose membership changed so far, `None` where
+    /// deleted.
+    staged_indices: HashMap<Uuid, Option<Index>>,
+}
+
+impl<'a> Staging<'a> {
+    pub fn new(documents: &'a DocumentStore, indices: &'a HashMap<Uuid, Index>) -> Self {
+        Self {
+            documents,
+            indices,
+            staged_documents: HashMap::new(),
+            staged_indices: HashMap::new(),
+        }
+    }
+
+    /// The document `id` as staged so far, read from its segment if the
+    /// batch has not written it.
+    fn document(&self, id: &Uuid) -> Result<Option<ReviewDocument>> {
+        match self.staged_documents.get(id) {
+            Some(doc) => Ok(doc.clone()),
+            None => self.documents.get(id),
+        }
+    }
+
+    fn has_document(&self, id: &Uuid) -> bool {
+        match self.staged_documents.get(id) {
+            Some(doc) => doc.is_some(),
+            None => self.documents.contains(id),
+        }
+    }
+
+    fn index(&self, id: &Uuid) -> Option<&Index> {
+        match self.staged_indices.get(id) {
+            Some(index) => index.as_ref(),
+            None => self.indices.get(id),
+        }
+    }
+
+    fn all_indices(&self) -> impl Iterator<Item = &Index> {
+        let stored = self
+            .indices
+            .values()
+            .filter(|index| !self.staged_indices.contains_key(&index.id));
+        stored.chain(self.staged_indices.values().flatten())
+    }
+
+    /// The index `id` as staged so far, copied into the overlay to be
+    /// changed.
+    fn index_mut(&mut self, id: &Uuid) -> Option<&mut Index> {
+        if !self.staged_indices.contains_key(id) {
+            let index = self.indices.get(id)?.clone();
+            self.staged_indices.insert(*id, Some(index));
+        }
+        self.staged_indices.get_mut(id)?.as_mut()
+    }
+
+    /// The document a `Patch` or `Replace` entry leaves, or `None` if it
+    /// does not exist. Fails with a `MappingError` if an index it belongs
+    /// to would reject it.
+    fn updated(&self, entry: &WalEntry) -> Result<Option<ReviewDocument>> {
+        let id = match entry {
+            WalEntry::Patch { id, .. } | WalEntry::Replace { id, .. } => id,
+            _ => return Ok(None),
+        };
+        let Some(mut doc) = self.document(id)? else {
+            return Ok(None);
+        };
+        update(&mut doc, entry);
+        for index in self.all_indices().filter(|index| index.contains(id)) {
+            index.validate(&doc)?;
+        }
+        Ok(Some(doc))
+    }
+
+    /// Checks `op` and returns the WAL entry that carries it out, or `None`
+    /// if it would change nothing. Fails if the op is invalid, with a
+    /// `MappingError` if it would leave an index member violating the
+    /// index's mappings.
+    pub fn prepare(&self, op: WriteOp) -> Result<Option<WalEntry>> {
+        match op {
+            WriteOp::Store(doc) => {
+                for index in self.all_indices().filter(|index| index.contains(&doc.id)) {
+                    index.validate(&doc)?;
+                }
+                Ok(Some(WalEntry::Document(doc)))
+            }
+            WriteOp::Patch { id, patch } => {
+                let entry = WalEntry::Patch { id, patch };
+                Ok(self.updated(&entry)?.is_some().then_some(entry))
+            }
+            WriteOp::Replace { id, content, metadata } => {
+                let entry = WalEntry::Replace { id, content, metadata };
+                Ok(self.updated(&entry)?.is_some().then_some(entry))
+            }
+            WriteOp::Delete(id) => Ok(self.has_document(&id).then_some(WalEntry::Delete(id))),
+            WriteOp::StoreIndex(index) => {
+                for id in &index.documents {
+                    if let Some(doc) = self.document(id)? {
+                        index.validate(&doc)?;
+                    }
+                }
+                Ok(Some(WalEntry::Index(index)))
+            }
+            WriteOp::DeleteIndex(id) => Ok(self.index(&id).is_some().then_some(WalEntry::DeleteIndex(id))),
+            WriteOp::AddToIndex { index_id, doc_id } => {
+                let index = self
+                    .index(&index_id)
+                    .ok_or_else(|| anyhow::anyhow!("index {index_id} does not exist"))?;
+                let doc = self
+                    .document(&doc_id)?
+                    .ok_or_else(|| anyhow::anyhow!("document {doc_id} does not exist"))?;
+                if index.contains(&doc_id) {
+                    return Ok(None);
+                }
+                index.validate(&doc)?;
+                Ok(Some(WalEntry::AddToIndex { index_id, doc_id }))
+            }
+            WriteOp::RemoveFromIndex { index_id, doc_id } => {
+                let member = self.index(&index_id).is_some_and(|index| index.contains(&doc_id));
+                Ok(member.then_some(WalEntry::RemoveFromIndex { index_id, doc_id }))
+            }
+        }
+    }
+
+    /// Records the effect of a prepared entry, for checking later ops.
+    pub fn stage(&mut self, entry: &WalEntry) {
+        match entry {
+            WalEntry::Document(doc) => {
+                self.staged_documents.insert(doc.id, Some(doc.clone()));
+            }
+            WalEntry::Delete(id) => {
+                self.staged_documents.insert(*id, None);
+                let members: Vec<Uuid> = self
+                    .all_indices()
+                    .filter(|index| index.contains(id))
+                    .map(|index| index.id)
+                    .collect();
+                for index_id in members {
+                    if let Some(index) = self.index_mut(&index_id) {
+                        index.remove_document(id);
+                    }
+                }
+            }
+            WalEntry::Index(index) => {
+                self.staged_indices.insert(index.id, Some(index.clone()));
+            }
+            WalEntry::DeleteIndex(id) => {
+                self.staged_indices.insert(*id, None);
+            }
+            WalEntry::AddToIndex { index_id, doc_id } => {
+                if let Some(index) = self.index_mut(index_id) {
+                    index.add_document(*doc_id);
+                }
+            }
+            WalEntry::RemoveFromIndex { index_id, doc_id } => {
+                if let Some(index) = self.index_mut(index_id) {
+                    index.remove_document(doc_id);
+                }
+            }
+            WalEntry::Patch { id, .. } | WalEntry::Replace { id, .. } => {
+                // Already checked when the entry was prepared.
+                if let Ok(Some(doc)) = self.updated(entry) {
+                    self.staged_documents.insert(*id, Some(doc));
+                }
+            }
+            WalEntry::BeginBatch | WalEntry::CommitBatch | WalEntry::AbortBatch => {}
+        }
+    }
+}
+
+/// Applies a `Patch` or `Replace` entry to `doc`, keeping its ID and
+/// timestamp.
+pub(crate) fn update(doc: &mut ReviewDocument, entry: &WalEntry) {
+    match entry {
+        WalEntry::Patch { patch, .. } => doc.patch(patch.clone()),
+        WalEntry::Replace { content, metadata, .. } => {
+            doc.content = content.clone();
+            doc.metadata = metadata.clone();
+        }
+        _ => {}
+    }
+}
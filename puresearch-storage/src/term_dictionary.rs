@@ -0,0 +1,142 @@
+use anyhow::Result;
+use fst::automaton::{Automaton, Str};
+use fst::{IntoStreamer, Map, MapBuilder, Streamer};
+use serde::Serialize;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap};
+
+/// Pending changes are merged into the FST once they outnumber this, or an
+/// eighth of the FST, whichever is larger, so rebuild cost stays amortised
+/// as the vocabulary grows.
+const MIN_PENDING_LIMIT: usize = 4096;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Suggestion {
+    pub term: String,
+    /// Number of documents containing the term.
+    pub count: u64,
+}
+
+/// Vocabulary with per-term document counts, for prefix completion.
+///
+/// Terms live in an immutable, sorted FST, which keeps millions of terms in
+/// a few bytes each and answers prefix lookups without touching unrelated
+/// terms. Updates go to a small sorted overlay holding the new count of
+/// every term changed since the FST was built; the overlay is folded into a
+/// fresh FST when it grows too large.
+pub struct TermDictionary {
+    fst: Map<Vec<u8>>,
+    /// New counts for terms changed since `fst` was built. Zero marks a
+    /// removed term.
+    pending: BTreeMap<String, u64>,
+}
+
+impl Default for TermDictionary {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TermDictionary {
+    pub fn new() -> Self {
+        Self {
+            fst: Map::default(),
+            pending: BTreeMap::new(),
+        }
+    }
+
+    pub fn count(&self, term: &str) -> u64 {
+        match self.pending.get(term) {
+            Some(count) => *count,
+            None => self.fst.get(term).unwrap_or(0),
+        }
+    }
+
+    pub fn increment(&mut self, term: &str) {
+        let count = self.count(term) + 1;
+        self.set(term, count);
+    }
+
+    pub fn decrement(&mut self, term: &str) {
+        let count = self.count(term).saturating_sub(1);
+        self.set(term, count);
+    }
+
+    fn set(&mut self, term: &str, count: u64) {
+        self.pending.insert(term.to_string(), count);
+        if self.pending.len() > MIN_PENDING_LIMIT.max(self.fst.len() / 8) {
+            // Building from sorted, deduplicated input cannot fail.
+            self.compact().expect("term dictionary rebuild");
+        }
+    }
+
+    /// Folds the pending overlay into a new FST.
+    pub fn compact(&mut self) -> Result<()> {
+        let mut builder = MapBuilder::memory();
+        let mut pending = std::mem::take(&mut self.pending).into_iter().peekable();
+        let mut stream = self.fst.stream();
+
+        while let Some((key, count)) = stream.next() {
+            while let Some((term, new_count)) = pending.next_if(|(term, _)| term.as_bytes() < key) {
+                if new_count > 0 {
+                    builder.insert(term, new_count)?;
+                }
+            }
+            let count = match pending.next_if(|(term, _)| term.as_bytes() == key) {
+                Some((_, new_count)) => new_count,
+                None => count,
+            };
+            if count > 0 {
+                builder.insert(key, count)?;
+            }
+        }
+        for (term, count) in pending {
+            if count > 0 {
+                builder.insert(term, count)?;
+            }
+        }
+
+        self.fst = builder.into_map();
+        Ok(())
+    }
+
+    /// The `limit` most frequent terms starting with `prefix`, most frequent
+    /// first and alphabetically among equal counts.
+    pub fn suggest(&self, prefix: &str, limit: usize) -> Vec<Suggestion> {
+        if limit == 0 {
+            return Vec::new();
+        }
+        // Min-heap of the best `limit` candidates seen so far.
+        let mut best: BinaryHeap<(Reverse<u64>, String)> = BinaryHeap::new();
+        let mut offer = |term: String, count: u64| {
+            if count == 0 {
+                return;
+            }
+            best.push((Reverse(count), term));
+            if best.len() > limit {
+                best.pop();
+            }
+        };
+
+        let mut stream = self.fst.search(Str::new(prefix).starts_with()).into_stream();
+        while let Some((key, count)) = stream.next() {
+            let term = String::from_utf8_lossy(key);
+            if !self.pending.contains_key(term.as_ref()) {
+                offer(term.into_owned(), count);
+            }
+        }
+        for (term, count) in self.pending.range(prefix.to_string()..) {
+            if !term.starts_with(prefix) {
+                break;
+            }
+            offer(term.clone(), *count);
+        }
+
+        let mut suggestions: Vec<Suggestion> = best
+            .into_iter()
+            .map(|(Reverse(count), term)| Suggestion { term, count })
+            .collect();
+        suggestions.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.term.cmp(&b.term)));
+        suggestions
+    }
+}
@@ -1,9 +1,29 @@
-use anyhow::Result;
-use puresearch_core::ReviewDocument;
+//! The write-ahead log.
+//!
+//! The file starts with a 16-byte header: [`MAGIC`], the format version as
+//! a little-endian `u32`, and the log sequence number (LSN) of the first
+//! record as a little-endian `u64`. Records are numbered consecutively from
+//! there, so an LSN identifies a record across rotations of the log. Each
+//! record is the payload length and the CRC-32 of the payload, both
+//! little-endian `u32`s, then the bincode-encoded [`WalEntry`].
+//!
+//! A crash can leave a partly written record at the end of the log. On
+//! recovery the log is read up to the first record that is incomplete,
+//! fails its checksum or cannot be decoded, and cut off there.
+//!
+//! Appending a record only hands it to the OS. [`WalSyncer`] fsyncs the log
+//! for writers that need their records on disk, one fsync at a time, each
+//! covering every record appended before it started.
+
+use anyhow::{bail, Result};
+use puresearch_core::{DocumentPatch, ReviewDocument};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fs::{File, OpenOptions};
 use std::io::{BufWriter, Write, BufReader, Read};
 use std::path::Path;
+use std::sync::{Arc, Condvar, Mutex, Weak};
+use std::time::Duration;
 use uuid::Uuid;
 
 // Add import for Index
@@ -14,27 +34,386 @@ pub enum WalEntry {
     Document(ReviewDocument),
     Delete(Uuid),
     Index(Index),
+    DeleteIndex(Uuid),
+    AddToIndex { index_id: Uuid, doc_id: Uuid },
+    RemoveFromIndex { index_id: Uuid, doc_id: Uuid },
+    /// Starts a batch of entries that recovery applies all or none of.
+    BeginBatch,
+    /// Ends a batch, which recovery can now apply.
+    CommitBatch,
+    /// Marks a batch cut short by a crash, which recovery discards.
+    AbortBatch,
+    /// Patches a stored document, keeping its ID and timestamp.
+    Patch { id: Uuid, patch: DocumentPatch },
+    /// Replaces a stored document's content and metadata, keeping its ID
+    /// and timestamp.
+    Replace {
+        id: Uuid,
+        content: String,
+        metadata: HashMap<String, String>,
+    },
+}
+
+/// Identifies a WAL file.
+pub const MAGIC: [u8; 4] = *b"PSWL";
+/// Version of the record format written by this build.
+pub const FORMAT_VERSION: u32 = 2;
+/// Largest record payload, in bytes. A longer length can only come from
+/// corruption, and is not trusted with an allocation.
+pub const MAX_RECORD_SIZE: usize = 64 * 1024 * 1024;
+
+const HEADER_LEN: u64 = 16;
+/// Length of the version 1 header, which had no LSN.
+const V1_HEADER_LEN: u64 = 8;
+const RECORD_HEADER_LEN: u64 = 8;
+
+/// What recovery found in the log.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RecoveryReport {
+    /// Intact records read back.
+    pub records: usize,
+    /// Bytes cut from the end of the log because they did not form a valid
+    /// record.
+    pub discarded_bytes: u64,
+    /// Why the first discarded record was rejected.
+    pub reason: Option<String>,
+    /// Whether the log ended inside a batch that was never committed, whose
+    /// records were discarded.
+    pub incomplete_batch: bool,
+    /// Whether the log was written in an older format and was rewritten in
+    /// the current one.
+    pub upgraded: bool,
+    /// LSN of the checkpoint recovery started from, if any. Only records
+    /// from there on were replayed.
+    pub checkpoint_lsn: Option<u64>,
+}
+
+impl RecoveryReport {
+    /// Whether recovery had to drop anything.
+    pub fn is_clean(&self) -> bool {
+        self.discarded_bytes == 0 && !self.incomplete_batch
+    }
+}
+
+/// What rewriting a log written in an older format left out.
+#[derive(Debug, Default)]
+struct Upgrade {
+    /// Bytes of a record cut short at the end of the old log.
+    discarded_bytes: u64,
+    reason: Option<String>,
 }
 
 pub struct WriteAheadLog {
     writer: BufWriter<File>,
     path: std::path::PathBuf,
+    /// Set if the log was written in an older format and was rewritten in
+    /// the current one.
+    upgraded: Option<Upgrade>,
+    /// LSN of the first record in the file.
+    base_lsn: u64,
+    /// LSN the next appended record will get.
+    next_lsn: u64,
+    syncer: Arc<WalSyncer>,
+}
+
+/// Fsyncs the log on behalf of writers waiting for their records to be
+/// durable, so that writers waiting at the same time share one fsync.
+///
+/// The first writer to wait while no fsync is under way runs one for
+/// everyone; those who arrive meanwhile wait for it, and run the next one
+/// if it did not cover their records.
+pub struct WalSyncer {
+    state: Mutex<SyncState>,
+    synced: Condvar,
+}
+
+struct SyncState {
+    file: Arc<File>,
+    /// Every record before this LSN has been handed to the OS.
+    appended: u64,
+    /// Every record before this LSN is on disk.
+    synced: u64,
+    /// Whether a writer is running an fsync.
+    syncing: bool,
+    /// Fsyncs completed so far.
+    syncs: u64,
+}
+
+impl WalSyncer {
+    fn new(file: File, lsn: u64) -> Self {
+        Self {
+            state: Mutex::new(SyncState {
+                file: Arc::new(file),
+                appended: lsn,
+                synced: lsn,
+                syncing: false,
+                syncs: 0,
+            }),
+            synced: Condvar::new(),
+        }
+    }
+
+    fn appended(&self, lsn: u64) {
+        self.state.lock().unwrap().appended = lsn;
+    }
+
+    /// Switches to the file of a rotated log, whose records before `lsn`
+    /// are all persisted by a checkpoint.
+    fn rotated(&self, file: File, lsn: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.file = Arc::new(file);
+        state.appended = lsn;
+        state.synced = state.synced.max(lsn);
+        self.synced.notify_all();
+    }
+
+    /// Every record before this LSN is on disk.
+    pub fn synced_lsn(&self) -> u64 {
+        self.state.lock().unwrap().synced
+    }
+
+    /// How many fsyncs have completed, to tell how many writes shared one.
+    pub fn sync_count(&self) -> u64 {
+        self.state.lock().unwrap().syncs
+    }
+
+    /// Blocks until every record before `lsn` is on disk. A writer that
+    /// has to run the fsync first waits `delay`, so that records other
+    /// writers append meanwhile are covered by it too. Records never
+    /// flushed, such as those of a batch that failed partway, are not
+    /// waited for.
+    pub fn wait(&self, lsn: u64, delay: Duration) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let lsn = lsn.min(state.appended);
+        loop {
+            if state.synced >= lsn {
+                return Ok(());
+            }
+            if state.syncing {
+                state = self.synced.wait(state).unwrap();
+                continue;
+            }
+            state.syncing = true;
+            drop(state);
+            if !delay.is_zero() {
+                std::thread::sleep(delay);
+            }
+            let (file, target) = {
+                let state = self.state.lock().unwrap();
+                (state.file.clone(), state.appended)
+            };
+            let result = file.sync_data();
+            state = self.state.lock().unwrap();
+            state.syncing = false;
+            if result.is_ok() {
+                state.synced = state.synced.max(target);
+                state.syncs += 1;
+            }
+            self.synced.notify_all();
+            result?;
+        }
+    }
+
+    /// Blocks until another thread, such as the background syncer, has got
+    /// every record before `lsn` on disk, or until `timeout` runs out,
+    /// without running an fsync itself. Returns whether the records are on
+    /// disk.
+    pub fn wait_synced(&self, lsn: u64, timeout: Duration) -> bool {
+        let state = self.state.lock().unwrap();
+        let lsn = lsn.min(state.appended);
+        let (state, _) = self
+            .synced
+            .wait_timeout_while(state, timeout, |state| state.synced < lsn)
+            .unwrap();
+        state.synced >= lsn
+    }
+
+    /// Fsyncs every record appended so far.
+    pub fn sync(&self) -> Result<()> {
+        let lsn = self.state.lock().unwrap().appended;
+        self.wait(lsn, Duration::ZERO)
+    }
+
+    /// Fsyncs every `interval` on a background thread, until the syncer is
+    /// dropped.
+    pub fn sync_periodically(syncer: Weak<WalSyncer>, interval: Duration) -> Result<()> {
+        std::thread::Builder::new()
+            .name("wal-sync".to_string())
+            .spawn(move || loop {
+                std::thread::sleep(interval);
+                let Some(syncer) = syncer.upgrade() else {
+                    break;
+                };
+                // A failed fsync is retried on the next tick, or by the
+                // next writer that waits.
+                let _ = syncer.sync();
+            })?;
+        Ok(())
+    }
 }
 
 impl WriteAheadLog {
+    /// Opens the log at `path`, creating it if needed. Fails if the file is
+    /// not a WAL or was written in a newer format. Call `recover` before
+    /// appending to an existing log.
     pub fn new<P: AsRef<Path>>(path: P) -> Result<Self> {
         let path = path.as_ref().to_path_buf();
+        let (base_lsn, upgraded) = Self::prepare_file(&path)?;
         let file = OpenOptions::new()
             .create(true)
             .append(true)
             .open(&path)?;
         
         Ok(Self {
+            syncer: Arc::new(WalSyncer::new(file.try_clone()?, base_lsn)),
             writer: BufWriter::new(file),
             path,
+            upgraded,
+            base_lsn,
+            next_lsn: base_lsn,
         })
     }
 
+    /// Makes sure the file at `path` starts with a valid header, writing one
+    /// to a new or empty file and rewriting logs in older formats. Returns
+    /// the LSN of the first record and, if the log was upgraded, what the
+    /// upgrade left out.
+    fn prepare_file(path: &Path) -> Result<(u64, Option<Upgrade>)> {
+        let mut existing = Vec::new();
+        if let Ok(file) = File::open(path) {
+            file.take(HEADER_LEN).read_to_end(&mut existing)?;
+        }
+        if existing.len() < HEADER_LEN as usize && Self::header(0).starts_with(&existing) {
+            // New, or a crash while the header was being written.
+            Self::create_file(path, 0)?;
+            return Ok((0, None));
+        }
+        if !existing.starts_with(&MAGIC) {
+            return Ok((0, Some(Self::upgrade(path)?)));
+        }
+        if existing.len() < V1_HEADER_LEN as usize {
+            bail!("WAL header is truncated");
+        }
+        match u32::from_le_bytes(existing[4..8].try_into().unwrap()) {
+            1 => {
+                Self::upgrade_v1(path)?;
+                Ok((0, Some(Upgrade::default())))
+            }
+            FORMAT_VERSION if existing.len() == HEADER_LEN as usize => {
+                Ok((u64::from_le_bytes(existing[8..16].try_into().unwrap()), None))
+            }
+            FORMAT_VERSION => bail!("WAL header is truncated"),
+            version => bail!("WAL format version {version} is not supported (expected {FORMAT_VERSION})"),
+        }
+    }
+
+    fn header(base_lsn: u64) -> [u8; HEADER_LEN as usize] {
+        let mut header = [0u8; HEADER_LEN as usize];
+        header[..4].copy_from_slice(&MAGIC);
+        header[4..8].copy_from_slice(&FORMAT_VERSION.to_le_bytes());
+        header[8..].copy_from_slice(&base_lsn.to_le_bytes());
+        header
+    }
+
+    /// Replaces the file at `path` with an empty log starting at `base_lsn`.
+    fn create_file(path: &Path, base_lsn: u64) -> Result<()> {
+        let new_path = path.with_extension("new");
+        let mut file = File::create(&new_path)?;
+        file.write_all(&Self::header(base_lsn))?;
+        file.sync_all()?;
+        std::fs::rename(&new_path, path)?;
+        Ok(())
+    }
+
+    /// Rewrites a version 1 log, whose header had no LSN, with a version 2
+    /// header. The records are unchanged.
+    fn upgrade_v1(path: &Path) -> Result<()> {
+        let mut reader = BufReader::new(File::open(path)?);
+        reader.seek_relative(V1_HEADER_LEN as i64)?;
+        let upgraded_path = path.with_extension("upgrade");
+        let mut writer = BufWriter::new(File::create(&upgraded_path)?);
+        writer.write_all(&Self::header(0))?;
+        std::io::copy(&mut reader, &mut writer)?;
+        writer.flush()?;
+        writer.get_ref().sync_all()?;
+        std::fs::rename(&upgraded_path, path)?;
+        Ok(())
+    }
+
+    /// Rewrites a log from before the header and checksums were added, whose
+    /// records are a length followed by the payload, in the current format.
+    ///
+    /// Fails, leaving the file as it is, if it does not decode as such a log.
+    /// The only damage tolerated is a record cut short at the end by a
+    /// crash, which is left out; the original file is then kept next to the
+    /// log, as `wal.legacy`, rather than replaced.
+    fn upgrade(path: &Path) -> Result<Upgrade> {
+        let file = File::open(path)?;
+        let file_len = file.metadata()?.len();
+        let mut reader = BufReader::new(file);
+        let upgraded_path = path.with_extension("upgrade");
+        let mut writer = BufWriter::new(File::create(&upgraded_path)?);
+        writer.write_all(&Self::header(0))?;
+        let mut offset = 0;
+        let reason = loop {
+            if offset == file_len {
+                break None;
+            }
+            if file_len - offset < 4 {
+                break Some("truncated record header");
+            }
+            let mut len_bytes = [0u8; 4];
+            reader.read_exact(&mut len_bytes)?;
+            let len = u32::from_le_bytes(len_bytes) as usize;
+            let error = if len > MAX_RECORD_SIZE {
+                Some(format!("record length {len} exceeds the maximum of {MAX_RECORD_SIZE}"))
+            } else if file_len - offset - 4 < len as u64 {
+                break Some("truncated record");
+            } else {
+                let mut payload = vec![0u8; len];
+                reader.read_exact(&mut payload)?;
+                match legacy::decode(&payload) {
+                    Ok(entry) => {
+                        Self::write_record(&mut writer, &bincode::serialize(&entry)?)?;
+                        None
+                    }
+                    Err(err) => Some(format!("undecodable record: {err}")),
+                }
+            };
+            if let Some(error) = error {
+                drop(writer);
+                std::fs::remove_file(&upgraded_path)?;
+                bail!(
+                    "{} has no WAL header and is not a legacy log: {error} at byte {offset}",
+                    path.display()
+                );
+            }
+            offset += 4 + len as u64;
+        };
+        if let (Some(reason), 0) = (reason, offset) {
+            drop(writer);
+            std::fs::remove_file(&upgraded_path)?;
+            bail!("{} has no WAL header and is not a legacy log: {reason} at byte 0", path.display());
+        }
+        writer.flush()?;
+        writer.get_ref().sync_all()?;
+        if reason.is_some() {
+            std::fs::rename(path, path.with_extension("legacy"))?;
+        }
+        std::fs::rename(&upgraded_path, path)?;
+        Ok(Upgrade {
+            discarded_bytes: file_len - offset,
+            reason: reason.map(str::to_string),
+        })
+    }
+
+    fn write_record(writer: &mut impl Write, payload: &[u8]) -> Result<()> {
+        writer.write_all(&(payload.len() as u32).to_le_bytes())?;
+        writer.write_all(&crc32fast::hash(payload).to_le_bytes())?;
+        writer.write_all(payload)?;
+        Ok(())
+    }
+
     pub fn write_document_entry(&mut self, doc: &ReviewDocument) -> Result<()> {
         let entry = WalEntry::Document(doc.clone());
         self.write_entry(&entry)
@@ -50,44 +429,234 @@ impl WriteAheadLog {
         self.write_entry(&entry)
     }
 
-    fn write_entry(&mut self, entry: &WalEntry) -> Result<()> {
+    pub fn write_entry(&mut self, entry: &WalEntry) -> Result<()> {
+        self.append(entry)?;
+        self.flush()
+    }
+
+    /// Appends `entries` framed as a batch, so that recovery applies all or
+    /// none of them, and flushes once.
+    pub fn write_batch(&mut self, entries: &[WalEntry]) -> Result<()> {
+        match entries {
+            [] => Ok(()),
+            [entry] => self.write_entry(entry),
+            entries => {
+                // Every entry is encoded before any is written, so that one
+                // too large to log leaves no part of the batch behind for
+                // the next write to flush.
+                let records = std::iter::once(&WalEntry::BeginBatch)
+                    .chain(entries)
+                    .chain(std::iter::once(&WalEntry::CommitBatch))
+                    .map(Self::encode)
+                    .collect::<Result<Vec<_>>>()?;
+                for record in &records {
+                    Self::write_record(&mut self.writer, record)?;
+                }
+                self.next_lsn += records.len() as u64;
+                self.flush()
+            }
+        }
+    }
+
+    /// Buffers `entry` without flushing, so that a batch of entries reaches
+    /// the file in as few writes as possible. Call `flush` once the batch
+    /// is complete.
+    /// Fails if the entry is larger than `MAX_RECORD_SIZE`, as it could not
+    /// be read back.
+    pub fn append(&mut self, entry: &WalEntry) -> Result<()> {
+        let serialized = Self::encode(entry)?;
+        Self::write_record(&mut self.writer, &serialized)?;
+        self.next_lsn += 1;
+        Ok(())
+    }
+
+    /// The payload of `entry`'s record. Fails if it is larger than
+    /// `MAX_RECORD_SIZE`.
+    fn encode(entry: &WalEntry) -> Result<Vec<u8>> {
         let serialized = bincode::serialize(entry)?;
-        let len = serialized.len() as u32;
-        
-        self.writer.write_all(&len.to_le_bytes())?;
-        self.writer.write_all(&serialized)?;
+        if serialized.len() > MAX_RECORD_SIZE {
+            bail!(
+                "WAL record of {} bytes exceeds the maximum of {MAX_RECORD_SIZE}",
+                serialized.len()
+            );
+        }
+        Ok(serialized)
+    }
+
+    /// Hands every appended record to the OS.
+    pub fn flush(&mut self) -> Result<()> {
         self.writer.flush()?;
-        
+        self.syncer.appended(self.next_lsn);
         Ok(())
     }
 
-    pub fn sync(&mut self) -> Result<()> {
-        self.writer.flush()?;
-        self.writer.get_ref().sync_all()?;
+    /// The syncer that makes this log's records durable.
+    pub fn syncer(&self) -> Arc<WalSyncer> {
+        self.syncer.clone()
+    }
+
+    /// LSN of the first record in the file.
+    pub fn base_lsn(&self) -> u64 {
+        self.base_lsn
+    }
+
+    /// LSN the next appended record will get; every record before it has
+    /// been appended.
+    pub fn next_lsn(&self) -> u64 {
+        self.next_lsn
+    }
+
+    /// Replaces the log with an empty one whose first record will get
+    /// `base_lsn`. Only safe once every record logged so far is persisted
+    /// elsewhere, as by a checkpoint.
+    pub fn rotate(&mut self, base_lsn: u64) -> Result<()> {
+        self.flush()?;
+        Self::create_file(&self.path, base_lsn)?;
+        let file = OpenOptions::new().append(true).open(&self.path)?;
+        self.syncer.rotated(file.try_clone()?, base_lsn);
+        self.writer = BufWriter::new(file);
+        self.base_lsn = base_lsn;
+        self.next_lsn = base_lsn;
         Ok(())
     }
 
-    pub fn read_all_entries(&self) -> Result<Vec<WalEntry>> {
+    /// Size of the log file in bytes, including buffered records.
+    pub fn byte_len(&self) -> Result<u64> {
+        Ok(self.writer.get_ref().metadata()?.len() + self.writer.buffer().len() as u64)
+    }
+
+    /// Flushes and fsyncs every appended record.
+    pub fn sync(&mut self) -> Result<()> {
+        self.flush()?;
+        self.syncer.wait(self.next_lsn, Duration::ZERO)
+    }
+
+    /// Reads every intact entry, in LSN order from `base_lsn`, then cuts the
+    /// log off after the last one so that new records follow it. Call
+    /// before writing anything.
+    pub fn recover(&mut self) -> Result<(Vec<WalEntry>, RecoveryReport)> {
         let file = File::open(&self.path)?;
+        let file_len = file.metadata()?.len();
         let mut reader = BufReader::new(file);
+        reader.seek_relative(HEADER_LEN as i64)?;
         let mut entries = Vec::new();
+        let mut offset = HEADER_LEN;
 
-        loop {
-            let mut len_bytes = [0u8; 4];
-            match reader.read_exact(&mut len_bytes) {
-                Ok(_) => {},
-                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
-                Err(e) => return Err(e.into()),
+        let reason = loop {
+            if offset == file_len {
+                break None;
+            }
+            if file_len - offset < RECORD_HEADER_LEN {
+                break Some("truncated record header".to_string());
+            }
+            let mut record_header = [0u8; RECORD_HEADER_LEN as usize];
+            reader.read_exact(&mut record_header)?;
+            let len = u32::from_le_bytes(record_header[..4].try_into().unwrap()) as usize;
+            let checksum = u32::from_le_bytes(record_header[4..].try_into().unwrap());
+            if len > MAX_RECORD_SIZE {
+                break Some(format!("record length {len} exceeds the maximum of {MAX_RECORD_SIZE}"));
+            }
+            if file_len - offset - RECORD_HEADER_LEN < len as u64 {
+                break Some("truncated record".to_string());
             }
 
-            let len = u32::from_le_bytes(len_bytes) as usize;
-            let mut entry_bytes = vec![0u8; len];
-            reader.read_exact(&mut entry_bytes)?;
+            let mut payload = vec![0u8; len];
+            reader.read_exact(&mut payload)?;
+            if crc32fast::hash(&payload) != checksum {
+                break Some("checksum mismatch".to_string());
+            }
+            match bincode::deserialize(&payload) {
+                Ok(entry) => entries.push(entry),
+                Err(err) => break Some(format!("undecodable record: {err}")),
+            }
+            offset += RECORD_HEADER_LEN + len as u64;
+        };
 
-            let entry: WalEntry = bincode::deserialize(&entry_bytes)?;
-            entries.push(entry);
+        let mut discarded_bytes = file_len - offset;
+        if discarded_bytes > 0 {
+            let file = OpenOptions::new().write(true).open(&self.path)?;
+            file.set_len(offset)?;
+            file.sync_all()?;
         }
+        // Whatever the upgrade cut from the end of the old log came after
+        // every record read here.
+        let mut reason = reason;
+        if let Some(upgrade) = &self.upgraded {
+            discarded_bytes += upgrade.discarded_bytes;
+            reason = reason.or_else(|| upgrade.reason.clone());
+        }
+        let report = RecoveryReport {
+            records: entries.len(),
+            discarded_bytes,
+            reason,
+            incomplete_batch: false,
+            upgraded: self.upgraded.is_some(),
+            checkpoint_lsn: None,
+        };
+        self.next_lsn = self.base_lsn + entries.len() as u64;
+        self.syncer.appended(self.next_lsn);
+        Ok((entries, report))
+    }
+}
+
+/// The records of logs from before the header was added, frozen as they
+/// were written then so that such logs still decode as [`WalEntry`] and the
+/// types in it change.
+mod legacy {
+    use super::WalEntry;
+    use bincode::Options;
+    use puresearch_core::analysis::AnalyzerConfig;
+    use puresearch_core::mapping::Mappings;
+    use serde::Deserialize;
+    use std::collections::HashMap;
+    use uuid::Uuid;
+
+    #[derive(Deserialize)]
+    enum Entry {
+        Document(Document),
+        Delete(Uuid),
+        Index(Index),
+    }
+
+    #[derive(Deserialize)]
+    struct Document {
+        id: Uuid,
+        content: String,
+        metadata: HashMap<String, String>,
+        timestamp: u64,
+    }
 
-        Ok(entries)
+    #[derive(Deserialize)]
+    struct Index {
+        id: Uuid,
+        name: String,
+        documents: Vec<Uuid>,
+        created_at: u64,
     }
-}
\ No newline at end of file
+
+    /// Decodes a legacy record, which must be exactly one entry. Indices
+    /// get the default analyzer and no mappings, as they had neither.
+    pub fn decode(payload: &[u8]) -> bincode::Result<WalEntry> {
+        let entry = bincode::DefaultOptions::new()
+            .with_fixint_encoding()
+            .reject_trailing_bytes()
+            .deserialize(payload)?;
+        Ok(match entry {
+            Entry::Document(doc) => WalEntry::Document(puresearch_core::ReviewDocument {
+                id: doc.id,
+                content: doc.content,
+                metadata: doc.metadata,
+                timestamp: doc.timestamp,
+            }),
+            Entry::Delete(id) => WalEntry::Delete(id),
+            Entry::Index(index) => WalEntry::Index(puresearch_core::Index {
+                id: index.id,
+                name: index.name,
+                documents: index.documents.into_iter().collect(),
+                created_at: index.created_at,
+                analyzer: AnalyzerConfig::default(),
+                mappings: Mappings::new(),
+            }),
+        })
+    }
+}
@@ -0,0 +1,139 @@
+use puresearch_core::{
+    query::Query,
+    storage::{IndexStorage, SearchIndex, StorageEngine},
+    Index, ReviewDocument,
+};
+use puresearch_storage::checkpoint::{self, MANIFEST};
+use puresearch_storage::{MmapStorage, StorageOptions};
+use tempfile::tempdir;
+
+mod common;
+
+use common::{manual_checkpoints, review};
+
+#[test]
+fn test_recovery_replays_only_after_checkpoint() {
+    let temp_dir = tempdir().unwrap();
+    let wal_path = temp_dir.path().join("wal.log");
+
+    let kept = review("Battery died fast");
+    let deleted = review("Screen cracked");
+    let mut updated = review("Battery lasts forever");
+    let later = review("Battery is fine");
+    let mut index = Index::new("batteries".to_string());
+    index.add_document(kept.id);
+    {
+        let mut storage = MmapStorage::with_options(temp_dir.path(), manual_checkpoints()).unwrap();
+        for doc in [&kept, &deleted, &updated] {
+            storage.store_document(doc).unwrap();
+        }
+        storage.store_index(&index).unwrap();
+        storage.delete_document(&deleted.id).unwrap();
+
+        assert_eq!(storage.checkpoint().unwrap(), 5);
+        assert_eq!(std::fs::metadata(&wal_path).unwrap().len(), 16);
+
+        updated.content = "Battery lasts a week".to_string();
+        storage.store_document(&updated).unwrap();
+        storage.store_document(&later).unwrap();
+    }
+
+    let storage = MmapStorage::new(temp_dir.path()).unwrap();
+    let report = storage.recovery_report();
+    assert_eq!(report.checkpoint_lsn, Some(5));
+    assert_eq!(report.records, 2);
+    assert!(storage.get_document(&deleted.id).unwrap().is_none());
+    assert_eq!(storage.get_document(&updated.id).unwrap().unwrap().content, "Battery lasts a week");
+    assert_eq!(storage.search(&Query::term("battery")).unwrap().len(), 3);
+    let scoped = storage.search_in_index(&index.id, &Query::term("battery"), &[]).unwrap().unwrap();
+    assert_eq!(scoped.len(), 1);
+    assert_eq!(scoped[0].id, kept.id);
+}
+
+#[test]
+fn test_checkpoint_at_same_lsn_replaces_segments_in_place() {
+    let temp_dir = tempdir().unwrap();
+    let doc = review("Battery died fast");
+    let checkpoints = temp_dir.path().join(checkpoint::DIR);
+    {
+        let mut storage = MmapStorage::with_options(temp_dir.path(), manual_checkpoints()).unwrap();
+        storage.store_document(&doc).unwrap();
+        assert_eq!(storage.checkpoint().unwrap(), 1);
+
+        // As if a rewrite at the same LSN had died partway through.
+        std::fs::write(checkpoints.join("1.documents.new"), b"torn").unwrap();
+        assert_eq!(storage.checkpoint().unwrap(), 1);
+    }
+
+    let mut files: Vec<_> = std::fs::read_dir(&checkpoints)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    files.sort();
+    assert_eq!(files, vec!["1.deletes", "1.documents", "1.indices"]);
+    let storage = MmapStorage::new(temp_dir.path()).unwrap();
+    assert_eq!(storage.recovery_report().checkpoint_lsn, Some(1));
+    assert_eq!(storage.get_document(&doc.id).unwrap().unwrap().content, "Battery died fast");
+}
+
+#[test]
+fn test_checkpoint_survives_crash_before_wal_rotation() {
+    let temp_dir = tempdir().unwrap();
+    let wal_path = temp_dir.path().join("wal.log");
+
+    let first = review("Battery died fast");
+    let second = review("Battery lasts forever");
+    {
+        let mut storage = MmapStorage::with_options(temp_dir.path(), manual_checkpoints()).unwrap();
+        storage.store_document(&first).unwrap();
+        storage.store_document(&second).unwrap();
+        storage.delete_document(&first.id).unwrap();
+        storage.flush().unwrap();
+        let full_log = std::fs::read(&wal_path).unwrap();
+        storage.checkpoint().unwrap();
+        drop(storage);
+        // As if the process died after the manifest was written but before
+        // the log was replaced.
+        std::fs::write(&wal_path, full_log).unwrap();
+    }
+
+    let storage = MmapStorage::new(temp_dir.path()).unwrap();
+    assert_eq!(storage.recovery_report().checkpoint_lsn, Some(3));
+    assert!(storage.get_document(&first.id).unwrap().is_none());
+    assert!(storage.get_document(&second.id).unwrap().is_some());
+}
+
+#[test]
+fn test_checkpoints_when_due_bound_the_wal() {
+    let temp_dir = tempdir().unwrap();
+    let wal_path = temp_dir.path().join("wal.log");
+
+    let options = StorageOptions {
+        checkpoint_wal_bytes: Some(4096),
+        ..StorageOptions::default()
+    };
+    let docs: Vec<ReviewDocument> = (0..200).map(|i| review(&format!("Review number {i}"))).collect();
+    {
+        let mut storage = MmapStorage::with_options(temp_dir.path(), options).unwrap();
+        for doc in &docs {
+            storage.store_document(doc).unwrap();
+            if storage.checkpoint_due().unwrap() {
+                assert!(storage.maybe_checkpoint().unwrap().is_some());
+            }
+            assert!(std::fs::metadata(&wal_path).unwrap().len() <= 4096);
+        }
+        // Writes leave checkpoints to `maybe_checkpoint`.
+        for i in 0..100 {
+            storage.store_document(&review(&format!("Late review {i}"))).unwrap();
+        }
+        assert!(std::fs::metadata(&wal_path).unwrap().len() > 4096);
+        assert!(storage.checkpoint_due().unwrap());
+    }
+    assert!(temp_dir.path().join(MANIFEST).exists());
+    // Only the latest checkpoint's segments are kept.
+    assert_eq!(std::fs::read_dir(temp_dir.path().join(checkpoint::DIR)).unwrap().count(), 3);
+
+    let storage = MmapStorage::new(temp_dir.path()).unwrap();
+    assert!(storage.recovery_report().checkpoint_lsn.is_some());
+    assert_eq!(storage.list_documents().unwrap().len(), 300);
+}
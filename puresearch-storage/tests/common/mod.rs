@@ -0,0 +1,27 @@
+//! Fixtures shared by the storage tests.
+#![allow(dead_code)]
+
+use puresearch_core::ReviewDocument;
+use puresearch_storage::StorageOptions;
+use std::collections::HashMap;
+
+pub fn review(content: &str) -> ReviewDocument {
+    ReviewDocument::new(content.to_string(), HashMap::new())
+}
+
+/// Options under which checkpoints are only taken when asked for.
+pub fn manual_checkpoints() -> StorageOptions {
+    StorageOptions {
+        checkpoint_wal_bytes: None,
+        ..StorageOptions::default()
+    }
+}
+
+/// Options under which document segments roll over every few documents,
+/// with checkpoints only taken when asked for.
+pub fn small_segments() -> StorageOptions {
+    StorageOptions {
+        segment_bytes: 1024,
+        ..manual_checkpoints()
+    }
+}
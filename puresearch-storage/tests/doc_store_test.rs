@@ -0,0 +1,167 @@
+use puresearch_core::{
+    query::Query,
+    storage::{IndexStorage, SearchIndex, StorageEngine},
+    Index, ReviewDocument,
+};
+use puresearch_storage::doc_store::{self, DocumentStore};
+use puresearch_storage::MmapStorage;
+use tempfile::tempdir;
+
+mod common;
+
+use common::{review, small_segments};
+
+fn segment_count(dir: &std::path::Path) -> usize {
+    std::fs::read_dir(dir).unwrap().count()
+}
+
+#[test]
+fn test_segments_roll_over_and_survive_restart() {
+    let temp_dir = tempdir().unwrap();
+    let options = small_segments();
+    let mut docs: Vec<ReviewDocument> = (0..50).map(|i| review(&format!("Review number {i}"))).collect();
+    {
+        let mut storage = MmapStorage::with_options(temp_dir.path(), options.clone()).unwrap();
+        for doc in &docs[..25] {
+            storage.store_document(doc).unwrap();
+        }
+        storage.checkpoint().unwrap();
+        for doc in &docs[25..] {
+            storage.store_document(doc).unwrap();
+        }
+        docs[0].content = "Review number zero".to_string();
+        storage.store_document(&docs[0]).unwrap();
+    }
+    assert!(segment_count(&temp_dir.path().join(doc_store::DIR)) > 1);
+
+    let storage = MmapStorage::with_options(temp_dir.path(), options).unwrap();
+    assert_eq!(storage.list_documents().unwrap().len(), 50);
+    for doc in &docs {
+        assert_eq!(storage.get_document(&doc.id).unwrap().unwrap().content, doc.content);
+    }
+    assert_eq!(storage.search(&Query::term("number")).unwrap().len(), 50);
+    assert_eq!(storage.search(&Query::term("zero")).unwrap().len(), 1);
+}
+
+#[test]
+fn test_open_discards_writes_after_checkpoint() {
+    let temp_dir = tempdir().unwrap();
+    let dir = temp_dir.path().join(doc_store::DIR);
+
+    let kept = review("Battery died fast");
+    let states = {
+        let mut store = DocumentStore::open(&dir, &[], None, 64).unwrap();
+        store.put(&kept).unwrap();
+        let states = store.sync().unwrap();
+        // Rolls over to a second segment, which the checkpoint never saw.
+        store.put(&review("Screen cracked")).unwrap();
+        store.put(&review("Battery lasts forever")).unwrap();
+        states
+    };
+    assert_eq!(states.len(), 1);
+    assert_eq!(segment_count(&dir), 3);
+
+    let mut store = DocumentStore::open(&dir, &states, Some(0), 4096).unwrap();
+    assert_eq!(segment_count(&dir), 1);
+    let segment = std::fs::read_dir(&dir).unwrap().next().unwrap().unwrap();
+    assert_eq!(segment.metadata().unwrap().len(), states[0].len);
+
+    // Writes pick up where the checkpoint left off.
+    store.put(&kept).unwrap();
+    let (_, location) = store.locations().next().unwrap();
+    assert_eq!(location.offset, states[0].len);
+}
+
+#[test]
+fn test_corrupt_document_is_detected() {
+    let temp_dir = tempdir().unwrap();
+    let dir = temp_dir.path().join(doc_store::DIR);
+
+    let doc = review("Battery died fast");
+    let mut store = DocumentStore::open(&dir, &[], None, 1024).unwrap();
+    store.put(&doc).unwrap();
+    let states = store.sync().unwrap();
+    let (_, location) = store.locations().next().unwrap();
+    let location = *location;
+    drop(store);
+
+    let path = std::fs::read_dir(&dir).unwrap().next().unwrap().unwrap().path();
+    let mut bytes = std::fs::read(&path).unwrap();
+    *bytes.last_mut().unwrap() ^= 0xff;
+    std::fs::write(&path, bytes).unwrap();
+
+    let mut store = DocumentStore::open(&dir, &states, Some(0), 1024).unwrap();
+    store.restore(doc.id, location);
+    let err = store.get(&doc.id).unwrap_err();
+    assert!(err.to_string().contains("checksum mismatch"), "{err}");
+}
+
+#[test]
+fn test_delete_sets_deletion_bit() {
+    let temp_dir = tempdir().unwrap();
+    let dir = temp_dir.path().join(doc_store::DIR);
+
+    let deleted = review("Screen cracked");
+    let kept = review("Battery died fast");
+    let mut store = DocumentStore::open(&dir, &[], None, 1024).unwrap();
+    store.put(&deleted).unwrap();
+    store.put(&kept).unwrap();
+
+    assert!(store.delete(&deleted.id));
+    assert!(!store.delete(&deleted.id));
+    assert!(!store.contains(&deleted.id));
+    assert!(store.get(&deleted.id).unwrap().is_none());
+    assert_eq!(store.latest(&deleted.id).unwrap().unwrap().content, "Screen cracked");
+    assert_eq!(store.ids().collect::<Vec<_>>(), vec![&kept.id]);
+    assert_eq!(store.tombstones_in(&[0]), vec![deleted.id]);
+
+    let deletes: Vec<_> = store.deletes().collect();
+    assert_eq!(deletes.len(), 1);
+    assert_eq!(deletes[0].0, 0);
+    assert_eq!(deletes[0].1.iter().collect::<Vec<_>>(), vec![0]);
+}
+
+#[test]
+fn test_deleted_documents_are_hidden_until_purged() {
+    let temp_dir = tempdir().unwrap();
+    let deleted = review("Screen cracked");
+    let kept = review("Screen is fine");
+    let mut index = Index::new("screens".to_string());
+    index.add_document(deleted.id);
+    index.add_document(kept.id);
+    {
+        let mut storage = MmapStorage::new(temp_dir.path()).unwrap();
+        storage.store_document(&deleted).unwrap();
+        storage.store_document(&kept).unwrap();
+        storage.store_index(&index).unwrap();
+        storage.delete_document(&deleted.id).unwrap();
+
+        assert_eq!(storage.search(&Query::term("screen")).unwrap().len(), 1);
+        assert!(storage.search(&Query::term("cracked")).unwrap().is_empty());
+        let scoped = storage.search_in_index(&index.id, &Query::MatchAll, &[]).unwrap().unwrap();
+        assert_eq!(scoped.len(), 1);
+        assert!(!storage.get_index(&index.id).unwrap().unwrap().contains(&deleted.id));
+
+        storage.checkpoint().unwrap();
+    }
+
+    let mut storage = MmapStorage::new(temp_dir.path()).unwrap();
+    assert!(storage.get_document(&deleted.id).unwrap().is_none());
+    assert_eq!(storage.list_documents().unwrap(), vec![kept.id]);
+    assert!(storage.search(&Query::term("cracked")).unwrap().is_empty());
+
+    // Stored again under the same ID, only the new version is searchable.
+    let mut restored = deleted.clone();
+    restored.content = "Screen replaced".to_string();
+    storage.store_document(&restored).unwrap();
+    storage.delete_document(&restored.id).unwrap();
+    storage.store_document(&deleted).unwrap();
+    assert_eq!(storage.search(&Query::term("cracked")).unwrap().len(), 1);
+    assert!(storage.search(&Query::term("replaced")).unwrap().is_empty());
+
+    storage.delete_document(&deleted.id).unwrap();
+    let stats = storage.compact().unwrap();
+    assert_eq!(stats.deletes_purged, 1);
+    assert_eq!(stats.documents, 1);
+    assert!(storage.searcher(None).unwrap().search(&Query::term("cracked"), &[]).is_empty());
+}
@@ -0,0 +1,89 @@
+use puresearch_core::storage::StorageEngine;
+use puresearch_storage::{Durability, MmapStorage, StorageOptions};
+use std::sync::{Arc, Barrier, RwLock};
+use std::time::Duration;
+use tempfile::tempdir;
+
+mod common;
+
+use common::review;
+
+#[test]
+fn test_deferred_write_waits_for_its_commit() {
+    let temp_dir = tempdir().unwrap();
+    let options = StorageOptions {
+        durability: Durability::Async,
+        sync_interval: Duration::from_secs(3600),
+        ..StorageOptions::default()
+    };
+    let mut storage = MmapStorage::with_options(temp_dir.path(), options).unwrap();
+
+    let (result, commit) = storage.deferred(|storage| storage.store_document(&review("Battery died fast")));
+    result.unwrap();
+    assert!(!commit.is_durable());
+    commit.wait(None).unwrap();
+    assert!(!commit.is_durable());
+    commit.wait(Some(Durability::Sync)).unwrap();
+    assert!(commit.is_durable());
+}
+
+#[test]
+fn test_async_writes_are_synced_in_the_background() {
+    let temp_dir = tempdir().unwrap();
+    let options = StorageOptions {
+        durability: Durability::Async,
+        sync_interval: Duration::from_millis(10),
+        ..StorageOptions::default()
+    };
+    let mut storage = MmapStorage::with_options(temp_dir.path(), options).unwrap();
+
+    let syncs = storage.wal_syncer().sync_count();
+    let (result, commit) = storage.deferred(|storage| storage.store_document(&review("Battery died fast")));
+    result.unwrap();
+    commit.wait(None).unwrap();
+    assert!(commit.wait_synced(Duration::from_secs(30)), "async write was never synced");
+    assert!(storage.wal_syncer().sync_count() > syncs);
+}
+
+#[test]
+fn test_group_commit_from_concurrent_writers() {
+    let temp_dir = tempdir().unwrap();
+    let options = StorageOptions {
+        durability: Durability::Group,
+        group_commit_latency: Duration::from_millis(5),
+        sync_interval: Duration::from_secs(3600),
+        ..StorageOptions::default()
+    };
+    let storage = Arc::new(RwLock::new(MmapStorage::with_options(temp_dir.path(), options.clone()).unwrap()));
+    let syncer = storage.read().unwrap().wal_syncer();
+    let syncs = syncer.sync_count();
+
+    // Every writer logs its record before any of them waits, so the first
+    // fsync covers them all.
+    let writers = 8;
+    let logged = Arc::new(Barrier::new(writers));
+    let writers: Vec<_> = (0..writers)
+        .map(|i| {
+            let storage = storage.clone();
+            let logged = logged.clone();
+            std::thread::spawn(move || {
+                let doc = review(&format!("Review number {i}"));
+                let (result, commit) = storage.write().unwrap().deferred(|storage| storage.store_document(&doc));
+                result.unwrap();
+                logged.wait();
+                commit.wait(None).unwrap();
+                assert!(commit.is_durable());
+                doc.id
+            })
+        })
+        .collect();
+    let ids: Vec<_> = writers.into_iter().map(|writer| writer.join().unwrap()).collect();
+    assert_eq!(syncer.sync_count() - syncs, 1);
+    drop(syncer);
+    drop(storage);
+
+    let storage = MmapStorage::with_options(temp_dir.path(), options).unwrap();
+    for id in &ids {
+        assert!(storage.get_document(id).unwrap().is_some());
+    }
+}
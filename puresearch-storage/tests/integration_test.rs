@@ -1,11 +1,12 @@
-use puresearch_storage::MmapStorage;
-use puresearch_core::{storage::StorageEngine, ReviewDocument};
+use puresearch_storage::{wal::WalEntry, HighlightOptions, MmapStorage, WriteAheadLog};
+use puresearch_core::{storage::{StorageEngine, SearchIndex, WriteOp}, ReviewDocument};
 use tempfile::tempdir;
 use std::collections::HashMap;
 
 // Add imports
-use puresearch_core::{Index, storage::IndexStorage};
+use puresearch_core::{Index, aggregation::Aggregation, analysis::AnalyzerConfig, mapping::{FieldType, MappingError, Mappings}, query::{Fuzziness, Operator, Query, QueryParser}, sort::SortField, storage::IndexStorage};
 use uuid::Uuid;
+use serde_json::{json, Value};
 
 #[test]
 fn test_basic_storage_operations() {
@@ -94,7 +95,7 @@ fn test_basic_index_operations() {
     assert_eq!(retrieved.name, "test_index");
     assert!(retrieved.documents.is_empty());
     
-    let mut metadata = HashMap::new();
+    let metadata = HashMap::new();
     let doc = ReviewDocument::new("Test content".to_string(), metadata);
     let doc_id = doc.id;
     storage.store_document(&doc).unwrap();
@@ -122,7 +123,7 @@ fn test_index_persistence() {
         let mut index = Index::new("persistent_index".to_string());
         index_id = index.id;
         
-        let mut metadata = HashMap::new();
+        let metadata = HashMap::new();
         let doc = ReviewDocument::new("Persistent content".to_string(), metadata);
         doc_id = doc.id;
         storage.store_document(&doc).unwrap();
@@ -240,4 +241,848 @@ fn test_index_with_duplicates() {
     let retrieved = storage.get_index(&index.id).unwrap().unwrap();
     assert_eq!(retrieved.documents.len(), 1);
     assert!(retrieved.documents.contains(&doc_id));
-}
\ No newline at end of file
+}
+
+fn hit_ids(storage: &MmapStorage, query: &str) -> Vec<Uuid> {
+    storage.search(&Query::parse(query).unwrap()).unwrap().iter().map(|hit| hit.id).collect()
+}
+
+#[test]
+fn test_search_uses_inverted_index() {
+    let temp_dir = tempdir().unwrap();
+    let mut storage = MmapStorage::new(temp_dir.path()).unwrap();
+
+    let battery = ReviewDocument::new("Battery life is great".to_string(), HashMap::new());
+    let screen = ReviewDocument::new("The screen is great, battery so-so".to_string(), HashMap::new());
+    let other = ReviewDocument::new("Shipping was slow".to_string(), HashMap::new());
+    storage.store_document(&battery).unwrap();
+    storage.store_document(&screen).unwrap();
+    storage.store_document(&other).unwrap();
+
+    let mut hits = hit_ids(&storage, "GREAT battery");
+    hits.sort();
+    let mut expected = vec![battery.id, screen.id];
+    expected.sort();
+    assert_eq!(hits, expected);
+
+    assert_eq!(hit_ids(&storage, "slow"), vec![other.id]);
+    assert!(hit_ids(&storage, "missing").is_empty());
+    assert!(hit_ids(&storage, "").is_empty());
+}
+
+#[test]
+fn test_search_tracks_updates_and_deletes() {
+    let temp_dir = tempdir().unwrap();
+    let mut storage = MmapStorage::new(temp_dir.path()).unwrap();
+
+    let mut doc = ReviewDocument::new("Arrived broken".to_string(), HashMap::new());
+    storage.store_document(&doc).unwrap();
+    assert_eq!(hit_ids(&storage, "broken"), vec![doc.id]);
+
+    doc.content = "Replacement arrived working".to_string();
+    storage.store_document(&doc).unwrap();
+    assert!(hit_ids(&storage, "broken").is_empty());
+    assert_eq!(hit_ids(&storage, "working"), vec![doc.id]);
+
+    storage.delete_document(&doc.id).unwrap();
+    assert!(hit_ids(&storage, "working").is_empty());
+}
+
+#[test]
+fn test_search_index_rebuilt_on_recovery() {
+    let temp_dir = tempdir().unwrap();
+
+    let kept = ReviewDocument::new("Comfortable headphones".to_string(), HashMap::new());
+    let removed = ReviewDocument::new("Uncomfortable headphones".to_string(), HashMap::new());
+    {
+        let mut storage = MmapStorage::new(temp_dir.path()).unwrap();
+        storage.store_document(&kept).unwrap();
+        storage.store_document(&removed).unwrap();
+        storage.delete_document(&removed.id).unwrap();
+        storage.flush().unwrap();
+    }
+
+    let storage = MmapStorage::new(temp_dir.path()).unwrap();
+    assert_eq!(hit_ids(&storage, "headphones"), vec![kept.id]);
+    assert!(hit_ids(&storage, "uncomfortable").is_empty());
+}
+
+#[test]
+fn test_search_ranks_by_bm25() {
+    let temp_dir = tempdir().unwrap();
+    let mut storage = MmapStorage::new(temp_dir.path()).unwrap();
+
+    let passing = ReviewDocument::new(
+        "Nice case. The packaging, colours and delivery were all fine, and the refund policy is mentioned once".to_string(),
+        HashMap::new(),
+    );
+    let focused = ReviewDocument::new("Refund took weeks, refund still pending".to_string(), HashMap::new());
+    let unrelated = ReviewDocument::new("Lovely colours".to_string(), HashMap::new());
+    storage.store_document(&passing).unwrap();
+    storage.store_document(&focused).unwrap();
+    storage.store_document(&unrelated).unwrap();
+
+    let hits = storage.search(&Query::parse("refund").unwrap()).unwrap();
+    assert_eq!(hits.len(), 2);
+    assert_eq!(hits[0].id, focused.id);
+    assert_eq!(hits[1].id, passing.id);
+    assert!(hits[0].score > hits[1].score);
+    assert!(hits[1].score > 0.0);
+}
+
+#[test]
+fn test_search_matches_stems_and_accents() {
+    let temp_dir = tempdir().unwrap();
+    let mut storage = MmapStorage::new(temp_dir.path()).unwrap();
+
+    let doc = ReviewDocument::new("Great for running, and the café nearby".to_string(), HashMap::new());
+    storage.store_document(&doc).unwrap();
+
+    assert_eq!(hit_ids(&storage, "run"), vec![doc.id]);
+    assert_eq!(hit_ids(&storage, "runs"), vec![doc.id]);
+    assert_eq!(hit_ids(&storage, "CAFE"), vec![doc.id]);
+}
+
+#[test]
+fn test_index_uses_its_own_analyzer() {
+    let temp_dir = tempdir().unwrap();
+
+    let member = ReviewDocument::new("<p>Running shoes</p>".to_string(), HashMap::new());
+    let outsider = ReviewDocument::new("Running late".to_string(), HashMap::new());
+    let mut index = Index::new("exact".to_string()).with_analyzer(AnalyzerConfig::simple());
+    index.add_document(member.id);
+    {
+        let mut storage = MmapStorage::new(temp_dir.path()).unwrap();
+        storage.store_index(&index).unwrap();
+        storage.store_document(&member).unwrap();
+        storage.store_document(&outsider).unwrap();
+        storage.flush().unwrap();
+    }
+
+    let storage = MmapStorage::new(temp_dir.path()).unwrap();
+    let scoped = |query: &str| -> Vec<Uuid> {
+        storage
+            .search_in_index(&index.id, &Query::parse(query).unwrap(), &[])
+            .unwrap()
+            .unwrap()
+            .iter()
+            .map(|hit| hit.id)
+            .collect()
+    };
+    assert_eq!(scoped("running"), vec![member.id]);
+    assert!(scoped("run").is_empty());
+    assert_eq!(hit_ids(&storage, "run").len(), 2);
+    assert!(storage.search_in_index(&Uuid::new_v4(), &Query::term("run"), &[]).unwrap().is_none());
+}
+
+#[test]
+fn test_index_membership() {
+    let temp_dir = tempdir().unwrap();
+
+    let first = ReviewDocument::new("Battery died fast".to_string(), HashMap::new());
+    let second = ReviewDocument::new("Battery lasts forever".to_string(), HashMap::new());
+    let index = Index::new("batteries".to_string());
+    let dropped = Index::new("dropped".to_string());
+    {
+        let mut storage = MmapStorage::new(temp_dir.path()).unwrap();
+        storage.store_document(&first).unwrap();
+        storage.store_document(&second).unwrap();
+        storage.store_index(&index).unwrap();
+        storage.store_index(&dropped).unwrap();
+
+        assert!(storage.add_document(&index.id, &first.id).unwrap());
+        assert!(storage.add_document(&index.id, &second.id).unwrap());
+        assert!(!storage.add_document(&index.id, &second.id).unwrap());
+        assert!(storage.add_document(&Uuid::new_v4(), &first.id).is_err());
+        assert!(storage.add_document(&index.id, &Uuid::new_v4()).is_err());
+
+        assert!(storage.remove_document(&index.id, &second.id).unwrap());
+        assert!(!storage.remove_document(&index.id, &second.id).unwrap());
+        assert!(storage.delete_index(&dropped.id).unwrap());
+        assert!(!storage.delete_index(&dropped.id).unwrap());
+        storage.flush().unwrap();
+    }
+
+    let mut storage = MmapStorage::new(temp_dir.path()).unwrap();
+    let scoped = |storage: &MmapStorage| -> Vec<Uuid> {
+        storage
+            .search_in_index(&index.id, &Query::term("battery"), &[])
+            .unwrap()
+            .unwrap()
+            .iter()
+            .map(|hit| hit.id)
+            .collect()
+    };
+    assert_eq!(scoped(&storage), vec![first.id]);
+    assert!(storage.get_index(&dropped.id).unwrap().is_none());
+    assert_eq!(storage.list_indices().unwrap().len(), 1);
+    // Removing a document from an index or deleting the index keeps it.
+    assert!(storage.get_document(&second.id).unwrap().is_some());
+
+    // Deleting a document drops its memberships.
+    storage.delete_document(&first.id).unwrap();
+    assert!(storage.get_index(&index.id).unwrap().unwrap().documents.is_empty());
+    assert!(scoped(&storage).is_empty());
+}
+
+#[test]
+fn test_write_each() {
+    let temp_dir = tempdir().unwrap();
+
+    let mut mappings = Mappings::new();
+    mappings.insert("rating".to_string(), FieldType::Integer);
+    let index = Index::new("rated".to_string()).with_mappings(mappings);
+    let rated = |content: &str, rating: &str| {
+        let mut metadata = HashMap::new();
+        metadata.insert("rating".to_string(), rating.to_string());
+        ReviewDocument::new(content.to_string(), metadata)
+    };
+    let kept = rated("Battery died fast", "1");
+    let invalid = rated("Battery is fine", "five");
+    let deleted = rated("Screen cracked", "2");
+    {
+        let mut storage = MmapStorage::new(temp_dir.path()).unwrap();
+        let results = storage
+            .write_each(vec![
+                WriteOp::StoreIndex(index.clone()),
+                WriteOp::Store(kept.clone()),
+                WriteOp::AddToIndex { index_id: index.id, doc_id: kept.id },
+                WriteOp::Store(invalid.clone()),
+                WriteOp::AddToIndex { index_id: index.id, doc_id: invalid.id },
+                WriteOp::Store(deleted.clone()),
+                WriteOp::Delete(deleted.id),
+                WriteOp::Delete(deleted.id),
+            ])
+            .unwrap();
+        let outcomes: Vec<Option<bool>> = results.iter().map(|result| result.as_ref().ok().copied()).collect();
+        assert_eq!(
+            outcomes,
+            vec![Some(true), Some(true), Some(true), Some(true), None, Some(true), Some(true), Some(false)]
+        );
+        assert!(results[4].as_ref().unwrap_err().downcast_ref::<MappingError>().is_some());
+    }
+
+    let storage = MmapStorage::new(temp_dir.path()).unwrap();
+    let members = storage.get_index(&index.id).unwrap().unwrap().documents;
+    assert_eq!(members.into_iter().collect::<Vec<_>>(), vec![kept.id]);
+    assert!(storage.get_document(&invalid.id).unwrap().is_some());
+    assert!(storage.get_document(&deleted.id).unwrap().is_none());
+}
+
+#[test]
+fn test_write_batch_is_atomic() {
+    let temp_dir = tempdir().unwrap();
+
+    let mut metadata = HashMap::new();
+    metadata.insert("rating".to_string(), "five".to_string());
+    let doc = ReviewDocument::new("Battery died fast".to_string(), metadata);
+    let mut mappings = Mappings::new();
+    mappings.insert("rating".to_string(), FieldType::Integer);
+    let mut from = Index::new("inbox".to_string());
+    from.add_document(doc.id);
+    let typed = Index::new("typed".to_string()).with_mappings(mappings);
+    let untyped = Index::new("untyped".to_string());
+    let move_to = |to: &Index| {
+        vec![
+            WriteOp::RemoveFromIndex { index_id: from.id, doc_id: doc.id },
+            WriteOp::AddToIndex { index_id: to.id, doc_id: doc.id },
+        ]
+    };
+    {
+        let mut storage = MmapStorage::new(temp_dir.path()).unwrap();
+        storage.store_document(&doc).unwrap();
+        for index in [&from, &typed, &untyped] {
+            storage.store_index(index).unwrap();
+        }
+
+        let err = storage.write_batch(move_to(&typed)).unwrap_err();
+        assert!(err.downcast_ref::<MappingError>().is_some());
+        assert!(storage.get_index(&from.id).unwrap().unwrap().contains(&doc.id));
+
+        assert_eq!(storage.write_batch(move_to(&untyped)).unwrap(), vec![true, true]);
+    }
+
+    let storage = MmapStorage::new(temp_dir.path()).unwrap();
+    assert!(storage.get_index(&from.id).unwrap().unwrap().documents.is_empty());
+    assert!(storage.get_index(&untyped.id).unwrap().unwrap().contains(&doc.id));
+}
+
+#[test]
+fn test_recovery_discards_uncommitted_batch() {
+    let temp_dir = tempdir().unwrap();
+
+    let torn = ReviewDocument::new("Battery died fast".to_string(), HashMap::new());
+    let later = ReviewDocument::new("Battery lasts forever".to_string(), HashMap::new());
+    {
+        // A crash after part of a batch reached the log.
+        let mut wal = WriteAheadLog::new(temp_dir.path().join("wal.log")).unwrap();
+        wal.append(&WalEntry::BeginBatch).unwrap();
+        wal.append(&WalEntry::Document(torn.clone())).unwrap();
+        wal.flush().unwrap();
+    }
+    {
+        let mut storage = MmapStorage::new(temp_dir.path()).unwrap();
+        assert!(storage.recovery_report().incomplete_batch);
+        assert!(storage.get_document(&torn.id).unwrap().is_none());
+        storage.store_document(&later).unwrap();
+    }
+
+    let storage = MmapStorage::new(temp_dir.path()).unwrap();
+    assert!(storage.recovery_report().is_clean());
+    assert!(storage.get_document(&torn.id).unwrap().is_none());
+    assert!(storage.get_document(&later.id).unwrap().is_some());
+}
+
+#[test]
+fn test_phrase_queries() {
+    let temp_dir = tempdir().unwrap();
+    let mut storage = MmapStorage::new(temp_dir.path()).unwrap();
+
+    let exact = ReviewDocument::new("Honestly not worth it at this price".to_string(), HashMap::new());
+    let scattered = ReviewDocument::new("Worth it, not bad at all".to_string(), HashMap::new());
+    let gap = ReviewDocument::new("The battery has a long life".to_string(), HashMap::new());
+    let adjacent = ReviewDocument::new("Battery life is excellent".to_string(), HashMap::new());
+    for doc in [&exact, &scattered, &gap, &adjacent] {
+        storage.store_document(doc).unwrap();
+    }
+
+    assert_eq!(hit_ids(&storage, "\"not worth it\""), vec![exact.id]);
+    assert_eq!(hit_ids(&storage, "\"battery life\""), vec![adjacent.id]);
+
+    let mut sloppy = hit_ids(&storage, "\"battery life\"~3");
+    sloppy.sort();
+    let mut expected = vec![gap.id, adjacent.id];
+    expected.sort();
+    assert_eq!(sloppy, expected);
+
+    assert_eq!(hit_ids(&storage, "excellent \"battery life\""), vec![adjacent.id]);
+    assert!(hit_ids(&storage, "\"life battery\"").is_empty());
+}
+
+#[test]
+fn test_boolean_queries() {
+    let temp_dir = tempdir().unwrap();
+    let mut storage = MmapStorage::new(temp_dir.path()).unwrap();
+
+    let mut amazon = HashMap::new();
+    amazon.insert("source".to_string(), "Amazon".to_string());
+    let mut shop = HashMap::new();
+    shop.insert("source".to_string(), "Web shop".to_string());
+
+    let charger = ReviewDocument::new("Battery charges fast, cable is flimsy".to_string(), amazon.clone());
+    let battery = ReviewDocument::new("Battery life is superb".to_string(), shop);
+    let screen = ReviewDocument::new("Screen is bright".to_string(), amazon);
+    for doc in [&charger, &battery, &screen] {
+        storage.store_document(doc).unwrap();
+    }
+
+    let sorted = |query: &str| {
+        let mut ids = hit_ids(&storage, query);
+        ids.sort();
+        ids
+    };
+    let expect = |mut ids: Vec<Uuid>| {
+        ids.sort();
+        ids
+    };
+
+    assert_eq!(sorted("battery -cable"), vec![battery.id]);
+    assert_eq!(sorted("battery AND NOT cable"), vec![battery.id]);
+    assert_eq!(sorted("screen OR superb"), expect(vec![screen.id, battery.id]));
+    assert_eq!(sorted("battery AND (flimsy OR superb)"), expect(vec![charger.id, battery.id]));
+    assert_eq!(sorted("source:amazon"), expect(vec![charger.id, screen.id]));
+    assert_eq!(sorted("source:\"web shop\" battery"), vec![battery.id]);
+    assert_eq!(sorted("-battery"), vec![screen.id]);
+    assert_eq!(sorted("*"), expect(vec![charger.id, battery.id, screen.id]));
+
+    // With OR as the default operator, +clauses are required and bare
+    // clauses only contribute to the score.
+    let parser = QueryParser::new(Operator::Or);
+    let hits = storage.search(&parser.parse("+battery superb").unwrap()).unwrap();
+    assert_eq!(hits.len(), 2);
+    assert_eq!(hits[0].id, battery.id);
+}
+
+#[test]
+fn test_metadata_filters() {
+    let temp_dir = tempdir().unwrap();
+    let mut storage = MmapStorage::new(temp_dir.path()).unwrap();
+
+    let review = |content: &str, rating: &str, product: &str| {
+        let mut metadata = HashMap::new();
+        metadata.insert("rating".to_string(), rating.to_string());
+        metadata.insert("product_id".to_string(), product.to_string());
+        ReviewDocument::new(content.to_string(), metadata)
+    };
+    let great = review("Battery lasts for days", "5", "B00X");
+    let fine = review("Battery is fine", "4", "B00Y");
+    let poor = review("Battery died, poor value", "2", "B00X");
+    let mut unrated = ReviewDocument::new("Battery unknown".to_string(), HashMap::new());
+    unrated.metadata.insert("product_id".to_string(), "B00X".to_string());
+    for doc in [&great, &fine, &poor, &unrated] {
+        storage.store_document(doc).unwrap();
+    }
+
+    let filtered = |query: &str, filter: &str| {
+        let query = Query::parse(query).unwrap().filtered(QueryParser::default().parse_filter(filter).unwrap());
+        let mut ids: Vec<Uuid> = storage.search(&query).unwrap().into_iter().map(|hit| hit.id).collect();
+        ids.sort();
+        ids
+    };
+    let expect = |mut ids: Vec<Uuid>| {
+        ids.sort();
+        ids
+    };
+
+    assert_eq!(filtered("battery", "rating:>=4 AND product_id:B00X"), vec![great.id]);
+    assert_eq!(filtered("battery", "product_id:B00X"), expect(vec![great.id, poor.id, unrated.id]));
+    assert_eq!(filtered("battery", "rating:(2 OR 5)"), expect(vec![great.id, poor.id]));
+    assert_eq!(filtered("battery", "rating:[3 TO 10]"), expect(vec![great.id, fine.id]));
+    assert_eq!(filtered("battery", "product_id:{B00X TO *]"), vec![fine.id]);
+    assert_eq!(filtered("battery", "-_exists_:rating"), vec![unrated.id]);
+    assert_eq!(filtered("", "rating:<3"), vec![poor.id]);
+
+    // Filters restrict the hits without changing their scores.
+    let plain = storage.search(&Query::parse("battery").unwrap()).unwrap();
+    let query = Query::parse("battery").unwrap().filtered(QueryParser::default().parse_filter("rating:5").unwrap());
+    let hits = storage.search(&query).unwrap();
+    let unfiltered = plain.iter().find(|hit| hit.id == great.id).unwrap();
+    assert_eq!(hits, vec![*unfiltered]);
+
+    // Range clauses in the query language filter too.
+    assert_eq!(hit_ids(&storage, "battery rating:<=2"), vec![poor.id]);
+
+    // Deleted and recovered documents keep the filter index in step.
+    storage.delete_document(&poor.id).unwrap();
+    drop(storage);
+    let storage = MmapStorage::new(temp_dir.path()).unwrap();
+    assert!(hit_ids(&storage, "battery rating:<=2").is_empty());
+    assert_eq!(hit_ids(&storage, "_exists_:rating -rating:4"), vec![great.id]);
+}
+
+#[test]
+fn test_index_mappings() {
+    let temp_dir = tempdir().unwrap();
+    let mut storage = MmapStorage::new(temp_dir.path()).unwrap();
+
+    let review = |rating: &str, code: &str, date: &str| {
+        let mut metadata = HashMap::new();
+        metadata.insert("rating".to_string(), rating.to_string());
+        metadata.insert("code".to_string(), code.to_string());
+        metadata.insert("reviewed".to_string(), date.to_string());
+        ReviewDocument::new("Battery review".to_string(), metadata)
+    };
+    let nine = review("9", "9", "2024-03-01");
+    let ten = review("10", "10", "2024-06-15T12:00:00Z");
+    for doc in [&nine, &ten] {
+        storage.store_document(doc).unwrap();
+    }
+
+    let mut index = Index::new("typed".to_string()).with_mappings(Mappings::from([
+        ("rating".to_string(), FieldType::Integer),
+        ("code".to_string(), FieldType::Keyword),
+        ("reviewed".to_string(), FieldType::Date),
+    ]));
+    index.add_document(nine.id);
+    index.add_document(ten.id);
+    storage.store_index(&index).unwrap();
+
+    let filtered = |filter: &str| {
+        let query = Query::MatchAll.filtered(QueryParser::default().parse_filter(filter).unwrap());
+        let mut ids: Vec<Uuid> = storage
+            .search_in_index(&index.id, &query, &[])
+            .unwrap()
+            .unwrap()
+            .into_iter()
+            .map(|hit| hit.id)
+            .collect();
+        ids.sort();
+        ids
+    };
+
+    // Integers compare as numbers and keywords as strings, where "10" < "9".
+    assert_eq!(filtered("rating:>9"), vec![ten.id]);
+    assert_eq!(filtered("code:>9"), Vec::<Uuid>::new());
+    assert_eq!(filtered("code:<9"), vec![ten.id]);
+    assert_eq!(filtered("reviewed:[2024-06-01 TO 2024-12-31]"), vec![ten.id]);
+    assert_eq!(filtered("reviewed:<2024-06-15T12:00:00Z"), vec![nine.id]);
+    assert_eq!(filtered("rating:(9 OR 11)"), vec![nine.id]);
+
+    // Documents that do not fit the mappings are rejected, both when they
+    // are updated and when they are added to the index.
+    let mut invalid = nine.clone();
+    invalid.metadata.insert("rating".to_string(), "nine".to_string());
+    let err = storage.store_document(&invalid).unwrap_err();
+    let err = err.downcast_ref::<MappingError>().unwrap();
+    assert_eq!(err.field, "rating");
+    assert_eq!(storage.get_document(&nine.id).unwrap().unwrap().metadata["rating"], "9");
+
+    let mut outsider = review("3", "x", "not a date");
+    storage.store_document(&outsider).unwrap();
+    let mut grown = index.clone();
+    grown.add_document(outsider.id);
+    let err = storage.store_index(&grown).unwrap_err();
+    assert_eq!(err.downcast_ref::<MappingError>().unwrap().field, "reviewed");
+
+    outsider.metadata.insert("reviewed".to_string(), "2023-01-01".to_string());
+    storage.store_document(&outsider).unwrap();
+    storage.store_index(&grown).unwrap();
+    drop(storage);
+
+    // Mappings survive recovery.
+    let storage = MmapStorage::new(temp_dir.path()).unwrap();
+    let query = Query::MatchAll.filtered(QueryParser::default().parse_filter("reviewed:<2024-01-01").unwrap());
+    let hits = storage.search_in_index(&index.id, &query, &[]).unwrap().unwrap();
+    assert_eq!(hits.len(), 1);
+    assert_eq!(hits[0].id, outsider.id);
+}
+
+#[test]
+fn test_keyword_fields_match_exactly() {
+    let temp_dir = tempdir().unwrap();
+    let mut storage = MmapStorage::new(temp_dir.path()).unwrap();
+
+    let review = |category: &str| {
+        let metadata = HashMap::from([("category".to_string(), category.to_string())]);
+        ReviewDocument::new("Battery review".to_string(), metadata)
+    };
+    let plural = review("Batteries");
+    let singular = review("battery");
+    let phrase = review("Phone Cases");
+    let mut index = Index::new("keywords".to_string())
+        .with_mappings(Mappings::from([("category".to_string(), FieldType::Keyword)]));
+    for doc in [&plural, &singular, &phrase] {
+        storage.store_document(doc).unwrap();
+        index.add_document(doc.id);
+    }
+    storage.store_index(&index).unwrap();
+
+    let hits = |query: &str| -> Vec<Uuid> {
+        let query = Query::parse(query).unwrap();
+        storage.search_in_index(&index.id, &query, &[]).unwrap().unwrap().iter().map(|hit| hit.id).collect()
+    };
+    // Neither lowercased nor stemmed, and a value is one term.
+    assert_eq!(hits("category:Batteries"), vec![plural.id]);
+    assert_eq!(hits("category:battery"), vec![singular.id]);
+    assert!(hits("category:batteries").is_empty());
+    assert_eq!(hits("category:\"Phone Cases\""), vec![phrase.id]);
+    assert!(hits("category:Phone").is_empty());
+
+    // Unmapped, the same values go through the analyzer.
+    let mut both = hit_ids(&storage, "category:battery");
+    both.sort();
+    let mut expected = vec![plural.id, singular.id];
+    expected.sort();
+    assert_eq!(both, expected);
+}
+
+#[test]
+fn test_sorted_search() {
+    let temp_dir = tempdir().unwrap();
+    let mut storage = MmapStorage::new(temp_dir.path()).unwrap();
+
+    let review = |content: &str, rating: Option<&str>, timestamp: u64| {
+        let mut metadata = HashMap::new();
+        if let Some(rating) = rating {
+            metadata.insert("rating".to_string(), rating.to_string());
+        }
+        let mut doc = ReviewDocument::new(content.to_string(), metadata);
+        doc.timestamp = timestamp;
+        doc
+    };
+    let old = review("Refund took weeks, refund denied", Some("10"), 100);
+    let recent = review("Asked for a refund", Some("9"), 300);
+    let unrated = review("Refund was quick", None, 200);
+    let other = review("Works fine", Some("1"), 400);
+    for doc in [&old, &recent, &unrated, &other] {
+        storage.store_document(doc).unwrap();
+    }
+
+    let sorted = |query: &str, sort: &str| -> Vec<Uuid> {
+        let sort = SortField::parse_list(sort).unwrap();
+        storage
+            .search_sorted(&Query::parse(query).unwrap(), &sort)
+            .unwrap()
+            .into_iter()
+            .map(|hit| hit.id)
+            .collect()
+    };
+
+    assert_eq!(sorted("refund", "timestamp:desc"), vec![recent.id, unrated.id, old.id]);
+    // Unmapped numbers sort numerically; documents without a value go last
+    // in either direction.
+    assert_eq!(sorted("refund", "rating"), vec![recent.id, old.id, unrated.id]);
+    assert_eq!(sorted("refund", "rating:desc"), vec![old.id, recent.id, unrated.id]);
+    assert_eq!(sorted("refund", "_score"), hit_ids(&storage, "refund"));
+
+    // Equal values fall through to the next key, then to the document ID.
+    let mut tied = vec![old.id, recent.id, unrated.id, other.id];
+    tied.sort();
+    assert_eq!(sorted("*", "missing_key"), tied);
+
+    // Mapped keywords sort as strings, where "10" < "9".
+    let mut index = Index::new("typed".to_string())
+        .with_mappings(Mappings::from([("rating".to_string(), FieldType::Keyword)]));
+    for doc in [&old, &recent, &other] {
+        index.add_document(doc.id);
+    }
+    storage.store_index(&index).unwrap();
+    let sort = SortField::parse_list("rating").unwrap();
+    let ids: Vec<Uuid> = storage
+        .search_in_index(&index.id, &Query::MatchAll, &sort)
+        .unwrap()
+        .unwrap()
+        .into_iter()
+        .map(|hit| hit.id)
+        .collect();
+    assert_eq!(ids, vec![other.id, old.id, recent.id]);
+}
+
+#[test]
+fn test_search_pages_follow_sort_order() {
+    let temp_dir = tempdir().unwrap();
+    let mut storage = MmapStorage::new(temp_dir.path()).unwrap();
+    let mut deleted = None;
+    for i in 0..25 {
+        let metadata = HashMap::from([("rating".to_string(), (i % 5).to_string())]);
+        let doc = ReviewDocument::new(format!("Refund number {i}"), metadata);
+        storage.store_document(&doc).unwrap();
+        deleted.get_or_insert(doc.id);
+    }
+    storage.delete_document(&deleted.unwrap()).unwrap();
+
+    let query = Query::parse("refund").unwrap();
+    for sort in ["rating:desc", "_score"] {
+        let sort = SortField::parse_list(sort).unwrap();
+        let searcher = storage.searcher(None).unwrap();
+        let all: Vec<Uuid> = searcher.search(&query, &sort).iter().map(|hit| hit.id).collect();
+        assert_eq!(all.len(), 24);
+
+        let mut paged = Vec::new();
+        let mut after = None;
+        loop {
+            let page = searcher.search_page(&query, &sort, after.as_ref(), 7);
+            assert_eq!(page.total, 24);
+            assert_eq!(page.remaining, 24 - paged.len());
+            paged.extend(page.hits.iter().map(|hit| hit.id));
+            if !page.has_more() {
+                break;
+            }
+            after = Some(searcher.cursor(page.hits.last().unwrap(), &sort));
+        }
+        assert_eq!(paged, all);
+    }
+}
+
+#[test]
+fn test_aggregations() {
+    let temp_dir = tempdir().unwrap();
+    let mut storage = MmapStorage::new(temp_dir.path()).unwrap();
+
+    // 2024-01-15, 2024-01-20 and 2024-03-02, at noon UTC.
+    let days = [1_705_320_000, 1_705_752_000, 1_709_380_800];
+    let reviews = [
+        ("Battery great", "5", "B00X", "19.5", days[0]),
+        ("Battery fine", "4", "B00X", "25.00", days[1]),
+        ("Battery poor", "1", "B00Y", "5.5", days[2]),
+        ("Battery great again", "5", "B00Y", "n/a", days[2]),
+        ("Screen great", "5", "B00Z", "100", days[2]),
+    ];
+    for (content, rating, product, price, timestamp) in reviews {
+        let mut metadata = HashMap::new();
+        metadata.insert("rating".to_string(), rating.to_string());
+        metadata.insert("product_id".to_string(), product.to_string());
+        metadata.insert("price".to_string(), price.to_string());
+        let mut doc = ReviewDocument::new(content.to_string(), metadata);
+        doc.timestamp = timestamp;
+        storage.store_document(&doc).unwrap();
+    }
+
+    let hits = storage.search(&Query::parse("battery").unwrap()).unwrap();
+    let aggregate = |json: Value| {
+        let aggregation: Aggregation = serde_json::from_value(json).unwrap();
+        serde_json::to_value(storage.aggregate(&hits, &aggregation)).unwrap()
+    };
+
+    assert_eq!(
+        aggregate(json!({ "terms": { "field": "product_id" } })),
+        json!({ "buckets": [{ "key": "B00X", "doc_count": 2 }, { "key": "B00Y", "doc_count": 2 }] })
+    );
+    assert_eq!(
+        aggregate(json!({ "terms": { "field": "rating", "size": 1 } })),
+        json!({ "buckets": [{ "key": "5", "doc_count": 2 }] })
+    );
+    assert_eq!(
+        aggregate(json!({ "histogram": { "field": "price", "interval": 10.0 } })),
+        json!({ "buckets": [{ "key": 0.0, "doc_count": 1 }, { "key": 10.0, "doc_count": 1 }, { "key": 20.0, "doc_count": 1 }] })
+    );
+    assert_eq!(
+        aggregate(json!({ "date_histogram": { "interval": "month" } })),
+        json!({ "buckets": [
+            { "key": 1_704_067_200_000.0, "key_as_string": "2024-01-01T00:00:00Z", "doc_count": 2 },
+            { "key": 1_709_251_200_000.0, "key_as_string": "2024-03-01T00:00:00Z", "doc_count": 2 },
+        ] })
+    );
+    // "n/a" is not a number and is left out.
+    assert_eq!(
+        aggregate(json!({ "stats": { "field": "price" } })),
+        json!({ "count": 3, "min": 5.5, "max": 25.0, "avg": 50.0 / 3.0, "sum": 50.0 })
+    );
+    assert_eq!(
+        aggregate(json!({ "stats": { "field": "missing" } })),
+        json!({ "count": 0, "min": null, "max": null, "avg": null, "sum": 0.0 })
+    );
+}
+
+#[test]
+fn test_fuzzy_queries() {
+    let temp_dir = tempdir().unwrap();
+    let mut storage = MmapStorage::new(temp_dir.path()).unwrap();
+
+    let received = ReviewDocument::new("Received it broken".to_string(), HashMap::new());
+    let battery = ReviewDocument::new("Battery drains overnight".to_string(), HashMap::new());
+    let typo = ReviewDocument::new("The battary is weak".to_string(), HashMap::new());
+    for doc in [&received, &battery, &typo] {
+        storage.store_document(doc).unwrap();
+    }
+
+    assert!(hit_ids(&storage, "recieved").is_empty());
+    assert_eq!(hit_ids(&storage, "recieved~1"), vec![received.id]);
+
+    // The exact spelling outranks the one-edit variant.
+    let hits = storage.search(&Query::parse("battary~1").unwrap()).unwrap();
+    assert_eq!(hits.len(), 2);
+    assert_eq!(hits[0].id, typo.id);
+    assert_eq!(hits[1].id, battery.id);
+    assert!(hits[0].score > hits[1].score);
+
+    let parser = QueryParser::default().with_fuzziness(Fuzziness::Auto);
+    let hits = storage.search(&parser.parse("batery overnite").unwrap()).unwrap();
+    assert_eq!(hits.len(), 1);
+    assert_eq!(hits[0].id, battery.id);
+}
+
+#[test]
+fn test_suggestions_follow_vocabulary() {
+    let temp_dir = tempdir().unwrap();
+    let mut storage = MmapStorage::new(temp_dir.path()).unwrap();
+
+    let first = ReviewDocument::new("Battery died. Batteries are hard to find".to_string(), HashMap::new());
+    let second = ReviewDocument::new("battery is fine, battery again".to_string(), HashMap::new());
+    storage.store_document(&first).unwrap();
+    storage.store_document(&second).unwrap();
+
+    let terms = |suggestions: Vec<puresearch_storage::Suggestion>| -> Vec<(String, u64)> {
+        suggestions.into_iter().map(|s| (s.term, s.count)).collect()
+    };
+    assert_eq!(
+        terms(storage.suggest("BATT", 5)),
+        vec![("battery".to_string(), 2), ("batteries".to_string(), 1)]
+    );
+
+    storage.delete_document(&first.id).unwrap();
+    assert_eq!(terms(storage.suggest("batt", 5)), vec![("battery".to_string(), 1)]);
+
+    let mut index = Index::new("scoped".to_string());
+    index.add_document(first.id);
+    storage.store_document(&first).unwrap();
+    storage.store_index(&index).unwrap();
+    assert_eq!(
+        terms(storage.suggest_in_index(&index.id, "batt", 5).unwrap()),
+        vec![("batteries".to_string(), 1), ("battery".to_string(), 1)]
+    );
+    assert!(storage.suggest_in_index(&Uuid::new_v4(), "batt", 5).is_none());
+}
+
+#[test]
+fn test_delete_drops_suggestions_and_statistics() {
+    let temp_dir = tempdir().unwrap();
+    let kept = ReviewDocument::new("battery lasts all day".to_string(), HashMap::new());
+    let deleted = ReviewDocument::new("battery drains, battery dies".to_string(), HashMap::new());
+    let query = Query::parse("battery").unwrap();
+    {
+        let mut storage = MmapStorage::new(temp_dir.path()).unwrap();
+        storage.store_document(&kept).unwrap();
+        storage.store_document(&deleted).unwrap();
+        storage.delete_document(&deleted.id).unwrap();
+
+        let suggestions = storage.suggest("batt", 5);
+        assert_eq!(suggestions.len(), 1);
+        assert_eq!((suggestions[0].term.as_str(), suggestions[0].count), ("battery", 1));
+    }
+
+    // Scores match those of a store that never held the deleted document,
+    // both before and after a restart replays the delete.
+    let fresh_dir = tempdir().unwrap();
+    let mut fresh = MmapStorage::new(fresh_dir.path()).unwrap();
+    fresh.store_document(&kept).unwrap();
+    let expected = fresh.search(&query).unwrap();
+
+    let storage = MmapStorage::new(temp_dir.path()).unwrap();
+    let suggestions = storage.suggest("batt", 5);
+    assert_eq!(suggestions.len(), 1);
+    assert_eq!((suggestions[0].term.as_str(), suggestions[0].count), ("battery", 1));
+    let hits = storage.search(&query).unwrap();
+    assert_eq!(hits.len(), 1);
+    assert_eq!(hits[0].id, kept.id);
+    assert_eq!(hits[0].score, expected[0].score);
+}
+
+#[test]
+fn test_highlighting() {
+    let temp_dir = tempdir().unwrap();
+    let storage = MmapStorage::new(temp_dir.path()).unwrap();
+
+    let text = "Great phone. The batteries last two days, which beats my old one. \
+                Camera is average and the speaker is quiet. \
+                Charging the battery fully takes an hour, running apps drains it slowly.";
+    let options = HighlightOptions {
+        fragment_size: 60,
+        ..HighlightOptions::default()
+    };
+
+    let query = Query::parse("battery -camera").unwrap();
+    let fragments = storage.highlighter(&query).highlight(text, &options);
+    assert_eq!(
+        fragments,
+        vec![
+            "phone. The <em>batteries</em> last two days, which beats my old one.",
+            "Charging the <em>battery</em> fully takes an hour, running apps",
+        ]
+    );
+
+    let options = HighlightOptions {
+        pre_tag: "[".to_string(),
+        post_tag: "]".to_string(),
+        number_of_fragments: 1,
+        ..HighlightOptions::default()
+    };
+    let query = QueryParser::default().with_fuzziness(Fuzziness::Auto).parse("\"old one\" run").unwrap();
+    let fragments = storage.highlighter(&query).highlight(text, &options);
+    assert_eq!(fragments.len(), 1);
+    assert!(fragments[0].contains("my [old] [one]."));
+    assert!(fragments[0].contains("[running]"));
+
+    assert!(storage.highlighter(&Query::parse("refund").unwrap()).highlight(text, &options).is_empty());
+}
+
+#[test]
+fn test_highlighting_escapes_html() {
+    let temp_dir = tempdir().unwrap();
+    let storage = MmapStorage::new(temp_dir.path()).unwrap();
+
+    let text = "Battery <script>alert('x')</script> & \"charger\"";
+    let query = Query::parse("battery charger").unwrap();
+    let fragments = storage.highlighter(&query).highlight(text, &HighlightOptions::default());
+    assert_eq!(
+        fragments,
+        vec!["<em>Battery</em> &lt;script&gt;alert(&#39;x&#39;)&lt;/script&gt; &amp; &quot;<em>charger</em>&quot;"]
+    );
+
+    let options = HighlightOptions {
+        escape_html: false,
+        ..HighlightOptions::default()
+    };
+    let fragments = storage.highlighter(&query).highlight(text, &options);
+    assert_eq!(fragments, vec!["<em>Battery</em> <script>alert('x')</script> & \"<em>charger</em>\""]);
+}
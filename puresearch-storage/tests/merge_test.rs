@@ -0,0 +1,180 @@
+use puresearch_core::{
+    query::Query,
+    storage::{SearchIndex, StorageEngine},
+    ReviewDocument,
+};
+use puresearch_storage::{checkpoint, doc_store};
+use puresearch_storage::merge::SegmentStats;
+use puresearch_storage::{MergePolicy, MmapStorage};
+use std::path::Path;
+use tempfile::tempdir;
+
+mod common;
+
+use common::{review, small_segments};
+
+fn segments_size(data_dir: &Path) -> u64 {
+    std::fs::read_dir(data_dir.join(doc_store::DIR))
+        .unwrap()
+        .map(|entry| entry.unwrap().metadata().unwrap().len())
+        .sum()
+}
+
+#[test]
+fn test_merge_policy() {
+    let policy = MergePolicy {
+        segments_per_tier: 3,
+        floor_segment_bytes: 100,
+        max_dead_ratio: 0.5,
+    };
+    let segment = |id, len, live_bytes| SegmentStats { id, len, live_bytes };
+
+    assert_eq!(policy.select(&[segment(0, 100, 90), segment(1, 100, 80)]), None);
+    // Mostly dead, so merged on its own.
+    assert_eq!(policy.select(&[segment(0, 100, 90), segment(1, 100, 40)]), Some(vec![1]));
+    // Three segments in the lowest tier; the one in the tier above waits.
+    let segments = [segment(0, 500, 500), segment(1, 50, 50), segment(2, 90, 90), segment(3, 100, 60)];
+    assert_eq!(policy.select(&segments), Some(vec![1, 2, 3]));
+}
+
+#[test]
+fn test_compact_reclaims_dead_documents() {
+    let temp_dir = tempdir().unwrap();
+    let mut docs: Vec<ReviewDocument> = (0..40).map(|i| review(&format!("Review number {i}"))).collect();
+    {
+        let mut storage = MmapStorage::with_options(temp_dir.path(), small_segments()).unwrap();
+        for doc in &docs {
+            storage.store_document(doc).unwrap();
+        }
+        for doc in &mut docs[..20] {
+            doc.content = format!("{} revised", doc.content);
+            storage.store_document(doc).unwrap();
+        }
+        for doc in &docs[20..30] {
+            storage.delete_document(&doc.id).unwrap();
+        }
+        let before = segments_size(temp_dir.path());
+
+        let stats = storage.compact().unwrap();
+        assert_eq!(stats.documents, 30);
+        assert_eq!(segments_size(temp_dir.path()), before - stats.bytes_reclaimed);
+        assert_eq!(std::fs::read_dir(temp_dir.path().join(doc_store::DIR)).unwrap().count(), 2);
+        assert_eq!(storage.search(&Query::term("revised")).unwrap().len(), 20);
+    }
+
+    let storage = MmapStorage::with_options(temp_dir.path(), small_segments()).unwrap();
+    assert_eq!(storage.list_documents().unwrap().len(), 30);
+    for doc in docs[..20].iter().chain(&docs[30..]) {
+        assert_eq!(storage.get_document(&doc.id).unwrap().unwrap().content, doc.content);
+    }
+    assert!(storage.get_document(&docs[25].id).unwrap().is_none());
+}
+
+#[test]
+fn test_writes_during_merge_are_kept() {
+    let temp_dir = tempdir().unwrap();
+    let mut updated = review("Battery died fast");
+    let deleted = review("Screen cracked");
+    {
+        let mut storage = MmapStorage::with_options(temp_dir.path(), small_segments()).unwrap();
+        storage.store_document(&updated).unwrap();
+        storage.store_document(&deleted).unwrap();
+
+        let segments = storage.plan_compaction().unwrap();
+        let merged = storage.prepare_merge(&segments).unwrap();
+        updated.content = "Battery lasts a week".to_string();
+        storage.store_document(&updated).unwrap();
+        storage.delete_document(&deleted.id).unwrap();
+        let stats = storage.finish_merge(merged).unwrap();
+        assert_eq!(stats.documents, 0);
+
+        assert_eq!(storage.get_document(&updated.id).unwrap().unwrap().content, "Battery lasts a week");
+        assert!(storage.get_document(&deleted.id).unwrap().is_none());
+    }
+
+    let storage = MmapStorage::with_options(temp_dir.path(), small_segments()).unwrap();
+    assert_eq!(storage.get_document(&updated.id).unwrap().unwrap().content, "Battery lasts a week");
+    assert!(storage.get_document(&deleted.id).unwrap().is_none());
+}
+
+#[test]
+fn test_unfinished_merge_is_discarded() {
+    let temp_dir = tempdir().unwrap();
+    let doc = review("Battery died fast");
+    {
+        let mut storage = MmapStorage::with_options(temp_dir.path(), small_segments()).unwrap();
+        storage.store_document(&doc).unwrap();
+        storage.checkpoint().unwrap();
+        let segments = storage.plan_compaction().unwrap();
+        // As if the process died before the merge was swapped in.
+        let _merged = storage.prepare_merge(&segments).unwrap();
+    }
+
+    let storage = MmapStorage::with_options(temp_dir.path(), small_segments()).unwrap();
+    let files: Vec<_> = std::fs::read_dir(temp_dir.path().join(doc_store::DIR))
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    assert!(files.iter().all(|path| path.extension().unwrap() == "seg"), "{files:?}");
+    assert_eq!(storage.get_document(&doc.id).unwrap().unwrap().content, "Battery died fast");
+}
+
+#[test]
+fn test_merge_is_recorded_without_a_checkpoint() {
+    let temp_dir = tempdir().unwrap();
+    let segments_dir = temp_dir.path().join(doc_store::DIR);
+    let mut docs: Vec<ReviewDocument> = (0..40).map(|i| review(&format!("Review number {i}"))).collect();
+    {
+        let mut storage = MmapStorage::with_options(temp_dir.path(), small_segments()).unwrap();
+        for doc in &docs {
+            storage.store_document(doc).unwrap();
+        }
+        let lsn = storage.checkpoint().unwrap();
+        for doc in &mut docs[..10] {
+            doc.content = format!("{} revised", doc.content);
+            storage.store_document(doc).unwrap();
+        }
+        for doc in &docs[10..20] {
+            storage.delete_document(&doc.id).unwrap();
+        }
+
+        storage.compact().unwrap();
+        let manifest: checkpoint::Manifest =
+            serde_json::from_slice(&std::fs::read(temp_dir.path().join(checkpoint::MANIFEST)).unwrap()).unwrap();
+        assert_eq!(manifest.lsn, lsn);
+        assert!(temp_dir.path().join(checkpoint::MERGES).exists());
+        // The sources are gone already; the merged segment and the new
+        // active one are left.
+        assert_eq!(std::fs::read_dir(&segments_dir).unwrap().count(), 2);
+    }
+
+    let mut storage = MmapStorage::with_options(temp_dir.path(), small_segments()).unwrap();
+    assert_eq!(storage.list_documents().unwrap().len(), 30);
+    for doc in docs[..10].iter().chain(&docs[20..]) {
+        assert_eq!(storage.get_document(&doc.id).unwrap().unwrap().content, doc.content);
+    }
+    assert!(storage.get_document(&docs[15].id).unwrap().is_none());
+    assert_eq!(storage.search(&Query::term("revised")).unwrap().len(), 10);
+
+    // Once a checkpoint records a merged segment, writes still go to the
+    // active segment and never to it, though it has the highest ID.
+    storage.compact().unwrap();
+    storage.checkpoint().unwrap();
+    assert!(!temp_dir.path().join(checkpoint::MERGES).exists());
+    drop(storage);
+    let mut storage = MmapStorage::with_options(temp_dir.path(), small_segments()).unwrap();
+    let manifest: checkpoint::Manifest =
+        serde_json::from_slice(&std::fs::read(temp_dir.path().join(checkpoint::MANIFEST)).unwrap()).unwrap();
+    let active = segments_dir.join(doc_store::file_name(manifest.active.unwrap()));
+    let sealed: Vec<(std::path::PathBuf, u64)> = std::fs::read_dir(&segments_dir)
+        .unwrap()
+        .map(|entry| entry.unwrap())
+        .filter(|entry| entry.path() != active)
+        .map(|entry| (entry.path(), entry.metadata().unwrap().len()))
+        .collect();
+    assert!(sealed.iter().any(|(_, len)| *len > 0));
+    storage.store_document(&review("Battery died fast")).unwrap();
+    for (path, len) in sealed {
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), len, "{}", path.display());
+    }
+}
@@ -0,0 +1,115 @@
+use puresearch_core::{
+    query::Query,
+    storage::{SearchIndex, StorageEngine},
+};
+use puresearch_storage::snapshot::{self, SnapshotError};
+use puresearch_storage::MmapStorage;
+use tempfile::tempdir;
+
+mod common;
+
+use common::review;
+
+#[test]
+fn test_restore_rolls_back_to_snapshot() {
+    let temp_dir = tempdir().unwrap();
+    let kept = review("Battery died fast");
+    let mut replaced = review("Screen cracked");
+    {
+        let mut storage = MmapStorage::new(temp_dir.path()).unwrap();
+        storage.store_document(&kept).unwrap();
+        storage.store_document(&replaced).unwrap();
+        let info = storage.snapshot("nightly").unwrap();
+        assert_eq!(info.documents, 2);
+
+        // A bad import, appended to the segment the snapshot links to and
+        // then merged away from it.
+        for i in 0..20 {
+            storage.store_document(&review(&format!("Bad import {i}"))).unwrap();
+        }
+        replaced.content = "Screen replaced".to_string();
+        storage.store_document(&replaced).unwrap();
+        storage.delete_document(&kept.id).unwrap();
+        storage.compact().unwrap();
+    }
+
+    let info = snapshot::restore(&snapshot::path(temp_dir.path(), "nightly"), temp_dir.path()).unwrap();
+    assert_eq!(info.name, "nightly");
+
+    let mut storage = MmapStorage::new(temp_dir.path()).unwrap();
+    assert_eq!(storage.list_documents().unwrap().len(), 2);
+    assert_eq!(storage.get_document(&kept.id).unwrap().unwrap().content, "Battery died fast");
+    assert_eq!(storage.get_document(&replaced.id).unwrap().unwrap().content, "Screen cracked");
+    assert!(storage.search(&Query::term("import")).unwrap().is_empty());
+    assert_eq!(storage.snapshots().unwrap(), vec![info]);
+
+    // The restored directory takes writes, and the snapshot is unchanged by
+    // them.
+    storage.store_document(&review("Battery lasts forever")).unwrap();
+    drop(storage);
+    let restore_dir = tempdir().unwrap();
+    snapshot::restore(&snapshot::path(temp_dir.path(), "nightly"), restore_dir.path()).unwrap();
+    let storage = MmapStorage::new(restore_dir.path()).unwrap();
+    assert_eq!(storage.list_documents().unwrap().len(), 2);
+    assert_eq!(storage.search(&Query::term("battery")).unwrap().len(), 1);
+}
+
+#[test]
+fn test_create_list_and_delete_snapshots() {
+    let temp_dir = tempdir().unwrap();
+    let mut storage = MmapStorage::new(temp_dir.path()).unwrap();
+    storage.store_document(&review("Battery died fast")).unwrap();
+    storage.snapshot("before").unwrap();
+    storage.store_document(&review("Screen cracked")).unwrap();
+    storage.snapshot("after").unwrap();
+
+    let err = storage.snapshot("before").unwrap_err();
+    assert_eq!(err.downcast_ref(), Some(&SnapshotError::AlreadyExists("before".to_string())));
+    let err = storage.snapshot("../escape").unwrap_err();
+    assert_eq!(err.downcast_ref(), Some(&SnapshotError::InvalidName("../escape".to_string())));
+
+    let snapshots = storage.snapshots().unwrap();
+    let mut names: Vec<_> = snapshots.iter().map(|info| info.name.as_str()).collect();
+    names.sort();
+    assert_eq!(names, vec!["after", "before"]);
+    assert!(snapshots.iter().any(|info| info.name == "after" && info.documents == 2));
+
+    assert!(storage.delete_snapshot("before").unwrap());
+    assert!(!storage.delete_snapshot("before").unwrap());
+    assert_eq!(storage.snapshots().unwrap().len(), 1);
+}
+
+#[test]
+fn test_unfinished_restore_blocks_opening() {
+    let temp_dir = tempdir().unwrap();
+    {
+        let mut storage = MmapStorage::new(temp_dir.path()).unwrap();
+        storage.store_document(&review("Battery died fast")).unwrap();
+        storage.snapshot("nightly").unwrap();
+    }
+    std::fs::write(temp_dir.path().join(snapshot::RESTORING), "nightly").unwrap();
+    let err = MmapStorage::new(temp_dir.path()).err().unwrap();
+    assert!(err.to_string().contains("did not finish"), "{err}");
+
+    snapshot::restore(&snapshot::path(temp_dir.path(), "nightly"), temp_dir.path()).unwrap();
+    let storage = MmapStorage::new(temp_dir.path()).unwrap();
+    assert_eq!(storage.list_documents().unwrap().len(), 1);
+}
+
+#[test]
+fn test_open_data_dir_is_locked() {
+    let temp_dir = tempdir().unwrap();
+    let mut storage = MmapStorage::new(temp_dir.path()).unwrap();
+    storage.store_document(&review("Battery died fast")).unwrap();
+    storage.snapshot("nightly").unwrap();
+
+    let err = MmapStorage::new(temp_dir.path()).err().unwrap();
+    assert!(err.to_string().contains("in use"), "{err}");
+    let err = snapshot::restore(&snapshot::path(temp_dir.path(), "nightly"), temp_dir.path()).unwrap_err();
+    assert!(err.to_string().contains("in use"), "{err}");
+    assert!(!temp_dir.path().join(snapshot::RESTORING).exists());
+
+    drop(storage);
+    let storage = MmapStorage::new(temp_dir.path()).unwrap();
+    assert_eq!(storage.list_documents().unwrap().len(), 1);
+}
@@ -0,0 +1,47 @@
+use puresearch_storage::{Suggestion, TermDictionary};
+
+fn suggestion(term: &str, count: u64) -> Suggestion {
+    Suggestion {
+        term: term.to_string(),
+        count,
+    }
+}
+
+#[test]
+fn test_suggest_orders_by_count() {
+    let mut dictionary = TermDictionary::new();
+    for term in ["battery", "battery", "batteries", "batch", "battery", "cable"] {
+        dictionary.increment(term);
+    }
+
+    assert_eq!(
+        dictionary.suggest("batt", 10),
+        vec![suggestion("battery", 3), suggestion("batteries", 1)]
+    );
+    assert_eq!(dictionary.suggest("bat", 1), vec![suggestion("battery", 3)]);
+    assert!(dictionary.suggest("x", 10).is_empty());
+}
+
+#[test]
+fn test_suggest_merges_compacted_and_pending_terms() {
+    let mut dictionary = TermDictionary::new();
+    for i in 0..10_000 {
+        dictionary.increment(&format!("term{i:05}"));
+    }
+    dictionary.increment("term00042");
+    dictionary.increment("termination");
+    dictionary.decrement("term00007");
+    dictionary.compact().unwrap();
+    dictionary.increment("term00042");
+    dictionary.decrement("term00043");
+
+    assert_eq!(dictionary.count("term00042"), 3);
+    assert_eq!(dictionary.count("term00007"), 0);
+    assert_eq!(dictionary.count("term00043"), 0);
+    assert_eq!(
+        dictionary.suggest("term0004", 3),
+        vec![suggestion("term00042", 3), suggestion("term00040", 1), suggestion("term00041", 1)]
+    );
+    assert!(dictionary.suggest("term00007", 5).is_empty());
+    assert_eq!(dictionary.suggest("termi", 5), vec![suggestion("termination", 1)]);
+}
@@ -0,0 +1,286 @@
+use puresearch_core::storage::{IndexStorage, StorageEngine, WriteOp};
+use puresearch_core::{DocumentPatch, ReviewDocument};
+use puresearch_storage::wal::{WalEntry, MAGIC, MAX_RECORD_SIZE};
+use puresearch_storage::{MmapStorage, WriteAheadLog};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+use tempfile::tempdir;
+use uuid::Uuid;
+
+mod common;
+
+use common::review;
+
+fn append_bytes(path: &Path, bytes: &[u8]) {
+    let mut file = OpenOptions::new().append(true).open(path).unwrap();
+    file.write_all(bytes).unwrap();
+}
+
+#[test]
+fn test_recovery_truncates_torn_tail() {
+    let temp_dir = tempdir().unwrap();
+    let wal_path = temp_dir.path().join("wal.log");
+
+    let first = review("Battery died fast");
+    let second = review("Battery lasts forever");
+    {
+        let mut storage = MmapStorage::new(temp_dir.path()).unwrap();
+        storage.store_document(&first).unwrap();
+        storage.store_document(&second).unwrap();
+        storage.flush().unwrap();
+    }
+    let intact_len = std::fs::metadata(&wal_path).unwrap().len();
+    // Half of a record header, as left by a crash mid-write.
+    append_bytes(&wal_path, &[42, 0, 0]);
+
+    let third = review("Screen cracked");
+    {
+        let mut storage = MmapStorage::new(temp_dir.path()).unwrap();
+        let report = storage.recovery_report();
+        assert_eq!(report.records, 2);
+        assert_eq!(report.discarded_bytes, 3);
+        assert_eq!(report.reason.as_deref(), Some("truncated record header"));
+        assert_eq!(std::fs::metadata(&wal_path).unwrap().len(), intact_len);
+        storage.store_document(&third).unwrap();
+    }
+
+    let storage = MmapStorage::new(temp_dir.path()).unwrap();
+    assert!(storage.recovery_report().is_clean());
+    for doc in [&first, &second, &third] {
+        assert!(storage.get_document(&doc.id).unwrap().is_some());
+    }
+}
+
+#[test]
+fn test_recovery_rejects_corrupt_records() {
+    let temp_dir = tempdir().unwrap();
+    let wal_path = temp_dir.path().join("wal.log");
+
+    let kept = review("Battery died fast");
+    let corrupted = review("Battery lasts forever");
+    {
+        let mut storage = MmapStorage::new(temp_dir.path()).unwrap();
+        storage.store_document(&kept).unwrap();
+        storage.store_document(&corrupted).unwrap();
+    }
+    let mut bytes = std::fs::read(&wal_path).unwrap();
+    let last = bytes.len() - 1;
+    bytes[last] ^= 0xff;
+    std::fs::write(&wal_path, &bytes).unwrap();
+    {
+        let storage = MmapStorage::new(temp_dir.path()).unwrap();
+        assert_eq!(storage.recovery_report().reason.as_deref(), Some("checksum mismatch"));
+        assert!(storage.get_document(&kept.id).unwrap().is_some());
+        assert!(storage.get_document(&corrupted.id).unwrap().is_none());
+    }
+
+    // A corrupted length must not be trusted with an allocation.
+    append_bytes(&wal_path, &[u32::MAX.to_le_bytes(), 0u32.to_le_bytes()].concat());
+    let storage = MmapStorage::new(temp_dir.path()).unwrap();
+    let report = storage.recovery_report();
+    assert_eq!(report.discarded_bytes, 8);
+    assert!(report.reason.as_deref().unwrap().starts_with("record length 4294967295 exceeds"));
+    assert!(storage.get_document(&kept.id).unwrap().is_some());
+}
+
+#[test]
+fn test_updates_are_logged_and_recovered() {
+    let temp_dir = tempdir().unwrap();
+    let wal_path = temp_dir.path().join("wal.log");
+
+    let patched = review("Battery died fast");
+    let replaced = review("Battery lasts forever");
+    {
+        let mut storage = MmapStorage::new(temp_dir.path()).unwrap();
+        storage.store_document(&patched).unwrap();
+        storage.store_document(&replaced).unwrap();
+        let patch = DocumentPatch {
+            content: Some("Battery died within a week".to_string()),
+            metadata: HashMap::from([("rating".to_string(), Some("1".to_string()))]),
+        };
+        let ops = vec![
+            WriteOp::Patch { id: patched.id, patch },
+            WriteOp::Replace {
+                id: replaced.id,
+                content: "Screen cracked".to_string(),
+                metadata: HashMap::from([("rating".to_string(), "2".to_string())]),
+            },
+        ];
+        assert_eq!(storage.write_batch(ops).unwrap(), vec![true, true]);
+    }
+
+    // Logged as updates, not as rewrites of the whole document.
+    let (entries, _) = WriteAheadLog::new(&wal_path).unwrap().recover().unwrap();
+    assert!(matches!(entries[3], WalEntry::Patch { id, .. } if id == patched.id));
+    assert!(matches!(entries[4], WalEntry::Replace { id, .. } if id == replaced.id));
+
+    let storage = MmapStorage::new(temp_dir.path()).unwrap();
+    let doc = storage.get_document(&patched.id).unwrap().unwrap();
+    assert_eq!(doc.content, "Battery died within a week");
+    assert_eq!(doc.metadata["rating"], "1");
+    assert_eq!(doc.timestamp, patched.timestamp);
+    let doc = storage.get_document(&replaced.id).unwrap().unwrap();
+    assert_eq!(doc.content, "Screen cracked");
+    assert_eq!(doc.metadata, HashMap::from([("rating".to_string(), "2".to_string())]));
+}
+
+#[test]
+fn test_failed_batch_leaves_nothing_in_the_log() {
+    let temp_dir = tempdir().unwrap();
+    let skipped = review("Battery died fast");
+    let stored = review("Battery lasts forever");
+    {
+        let mut storage = MmapStorage::new(temp_dir.path()).unwrap();
+        let oversized = review(&"x".repeat(MAX_RECORD_SIZE));
+        let err = storage
+            .write_batch(vec![WriteOp::Store(skipped.clone()), WriteOp::Store(oversized)])
+            .unwrap_err();
+        assert!(err.to_string().contains("exceeds the maximum"), "{err}");
+        storage.store_document(&stored).unwrap();
+    }
+
+    let storage = MmapStorage::new(temp_dir.path()).unwrap();
+    assert!(storage.recovery_report().is_clean());
+    assert!(storage.get_document(&stored.id).unwrap().is_some());
+    assert!(storage.get_document(&skipped.id).unwrap().is_none());
+}
+
+#[test]
+fn test_wal_header() {
+    let temp_dir = tempdir().unwrap();
+    let wal_path = temp_dir.path().join("wal.log");
+
+    let mut wal = WriteAheadLog::new(&wal_path).unwrap();
+    let bytes = std::fs::read(&wal_path).unwrap();
+    assert_eq!(bytes[..4], MAGIC);
+    assert_eq!(bytes[4..8], 2u32.to_le_bytes());
+    assert_eq!(bytes[8..16], 0u64.to_le_bytes());
+
+    wal.append(&WalEntry::Delete(uuid::Uuid::new_v4())).unwrap();
+    assert_eq!(wal.next_lsn(), 1);
+    wal.rotate(7).unwrap();
+    drop(wal);
+    let bytes = std::fs::read(&wal_path).unwrap();
+    assert_eq!(bytes.len(), 16);
+    assert_eq!(bytes[8..16], 7u64.to_le_bytes());
+    let wal = WriteAheadLog::new(&wal_path).unwrap();
+    assert_eq!((wal.base_lsn(), wal.next_lsn()), (7, 7));
+
+    let mut newer = bytes.clone();
+    newer[4..8].copy_from_slice(&3u32.to_le_bytes());
+    std::fs::write(&wal_path, &newer).unwrap();
+    let err = WriteAheadLog::new(&wal_path).err().unwrap();
+    assert_eq!(err.to_string(), "WAL format version 3 is not supported (expected 2)");
+}
+
+#[test]
+fn test_headerless_log_is_upgraded() {
+    let temp_dir = tempdir().unwrap();
+    let wal_path = temp_dir.path().join("wal.log");
+
+    let doc = review("Battery died fast");
+    let payload = bincode::serialize(&WalEntry::Document(doc.clone())).unwrap();
+    let mut legacy = (payload.len() as u32).to_le_bytes().to_vec();
+    legacy.extend_from_slice(&payload);
+    std::fs::write(&wal_path, &legacy).unwrap();
+
+    {
+        let storage = MmapStorage::new(temp_dir.path()).unwrap();
+        assert!(storage.recovery_report().upgraded);
+        assert!(storage.get_document(&doc.id).unwrap().is_some());
+    }
+    assert_eq!(std::fs::read(&wal_path).unwrap()[..4], MAGIC);
+    let storage = MmapStorage::new(temp_dir.path()).unwrap();
+    assert!(!storage.recovery_report().upgraded);
+    assert!(storage.get_document(&doc.id).unwrap().is_some());
+}
+
+/// A record as written before the WAL had a header, when indices had no
+/// analyzer or mappings.
+#[derive(Serialize)]
+enum LegacyEntry {
+    Document(ReviewDocument),
+    #[allow(dead_code)]
+    Delete(Uuid),
+    Index { id: Uuid, name: String, documents: Vec<Uuid>, created_at: u64 },
+}
+
+fn legacy_record(entry: &LegacyEntry) -> Vec<u8> {
+    let payload = bincode::serialize(entry).unwrap();
+    let mut record = (payload.len() as u32).to_le_bytes().to_vec();
+    record.extend_from_slice(&payload);
+    record
+}
+
+#[test]
+fn test_headerless_log_with_indices_is_upgraded() {
+    let temp_dir = tempdir().unwrap();
+    let wal_path = temp_dir.path().join("wal.log");
+
+    let doc = review("Battery died fast");
+    let index_id = Uuid::new_v4();
+    let mut legacy = legacy_record(&LegacyEntry::Document(doc.clone()));
+    legacy.extend(legacy_record(&LegacyEntry::Index {
+        id: index_id,
+        name: "reviews".to_string(),
+        documents: vec![doc.id],
+        created_at: 7,
+    }));
+    // A record cut short by a crash.
+    legacy.extend_from_slice(&[200, 0, 0, 0, 1, 2]);
+    std::fs::write(&wal_path, &legacy).unwrap();
+
+    let storage = MmapStorage::new(temp_dir.path()).unwrap();
+    let report = storage.recovery_report();
+    assert!(report.upgraded);
+    assert_eq!(report.records, 2);
+    assert_eq!(report.discarded_bytes, 6);
+    assert_eq!(report.reason.as_deref(), Some("truncated record"));
+    let index = storage.get_index(&index_id).unwrap().unwrap();
+    assert_eq!(index.name, "reviews");
+    assert!(index.contains(&doc.id));
+    // What the upgrade left out is kept.
+    assert_eq!(std::fs::read(temp_dir.path().join("wal.legacy")).unwrap(), legacy);
+}
+
+#[test]
+fn test_headerless_log_that_does_not_decode_is_rejected() {
+    let temp_dir = tempdir().unwrap();
+    let wal_path = temp_dir.path().join("wal.log");
+
+    let mut bytes = legacy_record(&LegacyEntry::Document(review("Battery died fast")));
+    let offset = bytes.len();
+    bytes.extend_from_slice(&[3, 0, 0, 0, 9, 9, 9]);
+    std::fs::write(&wal_path, &bytes).unwrap();
+    let err = MmapStorage::new(temp_dir.path()).err().unwrap().to_string();
+    assert!(err.contains("is not a legacy log: undecodable record"), "{err}");
+    assert!(err.ends_with(&format!("at byte {offset}")), "{err}");
+    assert_eq!(std::fs::read(&wal_path).unwrap(), bytes);
+
+    std::fs::write(&wal_path, b"not a log").unwrap();
+    assert!(MmapStorage::new(temp_dir.path()).is_err());
+    assert_eq!(std::fs::read(&wal_path).unwrap(), b"not a log");
+}
+
+#[test]
+fn test_version_1_log_is_upgraded() {
+    let temp_dir = tempdir().unwrap();
+    let wal_path = temp_dir.path().join("wal.log");
+
+    let doc = review("Battery died fast");
+    let payload = bincode::serialize(&WalEntry::Document(doc.clone())).unwrap();
+    let mut v1 = MAGIC.to_vec();
+    v1.extend_from_slice(&1u32.to_le_bytes());
+    v1.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    v1.extend_from_slice(&crc32fast::hash(&payload).to_le_bytes());
+    v1.extend_from_slice(&payload);
+    std::fs::write(&wal_path, &v1).unwrap();
+
+    let storage = MmapStorage::new(temp_dir.path()).unwrap();
+    assert!(storage.recovery_report().upgraded);
+    assert!(storage.get_document(&doc.id).unwrap().is_some());
+    assert_eq!(std::fs::read(&wal_path).unwrap()[4..8], 2u32.to_le_bytes());
+}